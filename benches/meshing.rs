@@ -0,0 +1,23 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use voxelgame::block::BlockProtoSet;
+use voxelgame::chunkset::ChunkSet;
+use voxelgame::settings::TerrainSettings;
+use voxelgame::terrain::Terrain;
+
+// remeshing a chunk is what a block edit costs, so this is the number to watch when touching the tessellator
+fn mesh_chunks(c: &mut Criterion) {
+    let block_proto_set = BlockProtoSet::builtin().unwrap();
+    let tp = rayon::ThreadPoolBuilder::new().build().unwrap();
+    let terrain = Terrain::new(1, TerrainSettings::default());
+    // nowhere anything was saved, so the chunks always generate fresh
+    let save_dir = std::env::temp_dir().join("voxelgame-bench-no-saves");
+
+    // hills cut through the surface chunk; the underground one is solid stone speckled with ore
+    for (name, chunk_coord) in [("mesh surface chunk", (0, 0, -1)), ("mesh underground chunk", (0, 0, -3))] {
+        let mut chunk = ChunkSet::generate_chunk(chunk_coord, &tp, &block_proto_set, &terrain, 1, None, &save_dir, vec![]);
+        c.bench_function(name, |b| b.iter(|| chunk.make_mesh(&block_proto_set, &tp)));
+    }
+}
+
+criterion_group!(benches, mesh_chunks);
+criterion_main!(benches);
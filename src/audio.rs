@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+use rodio::{Decoder, OutputStream, OutputStreamHandle, Source};
+use log::warn;
+use crate::block::{BlockID, BlockProtoSet};
+
+pub const SOUND_DIR: &str = "assets/sounds/";
+
+// each sound set has one file per event, e.g. assets/sounds/stone_break.wav
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SoundEvent {
+    Break,
+    Place,
+    Step,
+}
+impl SoundEvent {
+    pub fn all() -> [Self; 3] {
+        [Self::Break, Self::Place, Self::Step]
+    }
+    fn name(self) -> &'static str {
+        match self {
+            Self::Break => "break",
+            Self::Place => "place",
+            Self::Step => "step",
+        }
+    }
+}
+
+// block sounds, loaded up front so playing one never touches the disk.
+// no audio device or a missing file only costs a warning; the game just goes quiet
+pub struct Audio {
+    // has to outlive anything played through `handle`
+    _stream: Option<OutputStream>,
+    handle: Option<OutputStreamHandle>,
+    // "stone_break" -> the encoded file, decoded fresh each time it plays
+    sounds: HashMap<String, Arc<[u8]>>,
+    pub volume: f32,
+}
+
+impl Audio {
+    pub fn new() -> Self {
+        let (stream, handle) = match OutputStream::try_default() {
+            Ok((stream, handle)) => (Some(stream), Some(handle)),
+            Err(e) => {
+                warn!("No audio output ({}), sounds are off", e);
+                (None, None)
+            }
+        };
+        Self {
+            _stream: stream,
+            handle,
+            sounds: HashMap::new(),
+            volume: 1.0,
+        }
+    }
+
+    fn key(sound_set: &str, event: SoundEvent) -> String {
+        format!("{}_{}", sound_set, event.name())
+    }
+
+    // load every event for these sets, skipping ones we already have. safe to call again after the block config is reloaded
+    pub fn load_sound_sets<'a>(&mut self, sound_sets: impl Iterator<Item=&'a str>) {
+        for sound_set in sound_sets {
+            for event in SoundEvent::all() {
+                let key = Self::key(sound_set, event);
+                if self.sounds.contains_key(&key) {
+                    continue;
+                }
+                let fp = format!("{}{}.wav", SOUND_DIR, key);
+                match std::fs::read(&fp) {
+                    Ok(data) => { self.sounds.insert(key, data.into()); }
+                    Err(e) => warn!("Couldn't load sound {} ({})", fp, e),
+                }
+            }
+        }
+    }
+
+    // whatever sound set the block uses, if any
+    pub fn play_block(&self, block_properties: &BlockProtoSet, block_id: BlockID, event: SoundEvent) {
+        if let Some(sound_set) = &block_properties.by_id(block_id).sound {
+            self.play(sound_set, event);
+        }
+    }
+
+    pub fn play(&self, sound_set: &str, event: SoundEvent) {
+        let Some(handle) = &self.handle else { return };
+        let Some(data) = self.sounds.get(&Self::key(sound_set, event)) else { return };
+        match Decoder::new(Cursor::new(data.clone())) {
+            Ok(source) => {
+                if let Err(e) = handle.play_raw(source.convert_samples().amplify(self.volume)) {
+                    warn!("Couldn't play {}: {}", Self::key(sound_set, event), e);
+                }
+            }
+            Err(e) => warn!("Couldn't decode {}: {}", Self::key(sound_set, event), e),
+        }
+    }
+}
@@ -1,26 +1,195 @@
 use serde::Deserialize;
-use crate::geometry::{Vertex, Facing};
+use log::warn;
+use crate::geometry::{Vertex, Facing, Aabb};
 use ndarray::prelude::*;
 use ndarray::{Array, Ix3, Axis};
 
 
 pub type BlockID = u16;
 
+pub const TEXTURE_DIR: &str = "assets/textures/";
+// set this to ignore config/ and assets/ on disk and run entirely off the copies built into the binary
+pub const BUILTIN_ASSETS_ENV: &str = "VOXELGAME_BUILTIN_ASSETS";
+const BUILTIN_BLOCKS_TOML: &str = include_str!("../config/blocks.toml");
+
+pub fn builtin_assets_forced() -> bool {
+    std::env::var_os(BUILTIN_ASSETS_ENV).is_some()
+}
+
+#[derive(Debug)]
+pub enum BlockConfigError {
+    Io { path: String, error: std::io::Error },
+    Parse { path: String, error: toml::de::Error },
+    TexFaceMapOutOfRange { block: String, face: usize, index: usize, texture_count: usize },
+    MissingTexture { block: String, texture: String },
+    MissingFaceTexture { block: String, face: &'static str },
+    BadCollisionHeight { block: String, height: f32 },
+    BadOre { block: String, reason: &'static str },
+    OpaqueRenderLayer { block: String, layer: RenderLayer },
+    BadLightEmission { block: String, light: u8 },
+}
+impl std::fmt::Display for BlockConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            // toml's own errors already point at the offending line and column
+            Self::Io { path, error } => write!(f, "Couldn't open {}: {}", path, error),
+            Self::Parse { path, error } => write!(f, "Improperly formatted {}: {}", path, error),
+            Self::TexFaceMapOutOfRange { block, face, index, texture_count } => write!(f,
+                "Block \"{}\": tex_face_map[{}] = {} but the block only has {} texture(s)", block, face, index, texture_count
+            ),
+            Self::MissingTexture { block, texture } => write!(f,
+                "Block \"{}\": texture \"{}\" was not found in {}", block, texture, TEXTURE_DIR
+            ),
+            Self::MissingFaceTexture { block, face } => write!(f,
+                "Block \"{}\": textures has no entry for the {} face (give it directly or through \"side\")", block, face
+            ),
+            Self::BadCollisionHeight { block, height } => write!(f,
+                "Block \"{}\": collision_height is {} but has to be above 0 and at most 1", block, height
+            ),
+            Self::BadOre { block, reason } => write!(f, "Block \"{}\": bad ore settings, {}", block, reason),
+            Self::OpaqueRenderLayer { block, layer } => write!(f,
+                "Block \"{}\": render_layer is {:?} but the block isn't transparent, so whatever shows through it would be culled (set transparent = true)", block, layer
+            ),
+            Self::BadLightEmission { block, light } => write!(f,
+                "Block \"{}\": light_emission is {} but light levels only go up to {}", block, light, crate::geometry::MAX_LIGHT
+            ),
+        }
+    }
+}
+
 mod BlockProtoDefaults {
     pub fn True () -> bool {true}
+    pub fn One () -> f32 {1.0}
     pub fn TexFaceMapZeros () -> [usize; 6] {[0, 0, 0, 0, 0, 0]}
+    pub fn OreScale () -> f32 {0.15}
+}
+
+// newsud, same order as Facing
+const FACE_NAMES: [&str; 6] = ["north", "east", "west", "south", "top", "bottom"];
+
+// makes a block generate as veins inside stone, see Chunk::generate_ores
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OreSpec {
+    // 0 never shows up, 1 replaces all the stone. ~0.4 is common, ~0.25 rare
+    pub density: f32,
+    // world z range the veins show up in, inclusive
+    pub min_z: f32,
+    pub max_z: f32,
+    // noise frequency; bigger makes smaller, more scattered veins
+    #[serde(default = "BlockProtoDefaults::OreScale")]
+    pub scale: f32,
+}
+
+// textures can be written as a list indexed by tex_face_map, or as a table of named faces
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum TextureSpec {
+    List(Vec<String>),
+    Faces(FaceTextures),
+}
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FaceTextures {
+    north: Option<String>,
+    east: Option<String>,
+    west: Option<String>,
+    south: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    // fallback for any of the four horizontal faces that aren't named
+    side: Option<String>,
+}
+impl FaceTextures {
+    // flatten into a texture list and a tex_face_map, sharing entries between faces that use the same file
+    fn expand(&self, block: &str) -> Result<(Vec<String>, [usize; 6]), BlockConfigError> {
+        let per_face = [&self.north, &self.east, &self.west, &self.south, &self.top, &self.bottom];
+        let mut textures = Vec::<String>::new();
+        let mut tex_face_map = [0; 6];
+
+        for (face, texture) in per_face.into_iter().enumerate() {
+            let texture = match (texture, face < 4) {
+                (Some(texture), _) => texture,
+                (None, true) if self.side.is_some() => self.side.as_ref().unwrap(),
+                _ => return Err(BlockConfigError::MissingFaceTexture { block: block.to_string(), face: FACE_NAMES[face] }),
+            };
+            tex_face_map[face] = match textures.iter().position(|t| t == texture) {
+                Some(i) => i,
+                None => {
+                    textures.push(texture.clone());
+                    textures.len() - 1
+                }
+            };
+        }
+        Ok((textures, tex_face_map))
+    }
 }
+
 #[derive(Deserialize, Debug)]
 pub struct BlockProto {
     pub name: String,
+    #[serde(rename = "textures")]
+    texture_spec: TextureSpec,
+    // filled in from texture_spec by BlockProtoSet::from_toml
+    #[serde(skip)]
     pub textures: Vec<String>,
 
+    // only read for the list form of textures; the table form builds its own
     #[serde(default = "BlockProtoDefaults::TexFaceMapZeros")]
     pub tex_face_map: [usize; 6], // newsud
     #[serde(default = "BlockProtoDefaults::True")]
     pub solid: bool,
     #[serde(default)]
     pub transparent: bool,
+    // repeat the texture once per block across merged faces; false stretches a single copy over the face and stops the block from merging
+    #[serde(default = "BlockProtoDefaults::True")]
+    pub tile: bool,
+    // the block's top turns toward the face it was placed against (logs, pillars)
+    #[serde(default)]
+    pub orientable: bool,
+    // how much of the block (from its bottom, following orientation) you collide with: 0.5 for slabs. solid blocks only
+    #[serde(default = "BlockProtoDefaults::One")]
+    pub collision_height: f32,
+    // an explosion has to still have more strength than this when it reaches the block to break it (see World::explode). inf never breaks
+    #[serde(default)]
+    pub blast_resistance: f32,
+    #[serde(default)]
+    pub ore: Option<OreSpec>,
+    // which sounds it makes when broken, placed and walked on, see audio.rs. None is silent
+    #[serde(default)]
+    pub sound: Option<String>,
+    #[serde(default)]
+    pub render_layer: RenderLayer,
+    // light level the block gives off, 0 to 15 (full sunlight). spreads like sunlight does, see Chunk::compute_light
+    #[serde(default)]
+    pub light_emission: u8,
+}
+
+// which pass a block's faces are drawn in. chunk meshes keep each layer's quads together (see GpuMesh::layer_indices)
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderLayer {
+    #[default]
+    Opaque,
+    // fully see-through wherever the texture's alpha is under half (leaves, plants). still writes depth, so no sorting
+    Cutout,
+    // blended over what's behind it (glass, water)
+    Translucent,
+}
+impl RenderLayer {
+    pub const COUNT: usize = 3;
+    pub fn all() -> [Self; Self::COUNT] {
+        [Self::Opaque, Self::Cutout, Self::Translucent]
+    }
+}
+impl BlockProto {
+    fn expand_textures(&mut self) -> Result<(), BlockConfigError> {
+        match &self.texture_spec {
+            TextureSpec::List(textures) => self.textures = textures.clone(),
+            TextureSpec::Faces(faces) => (self.textures, self.tex_face_map) = faces.expand(&self.name)?,
+        }
+        Ok(())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -33,45 +202,152 @@ pub struct BlockProtoSet {
     blocks: Vec<BlockProto>,
 }
 impl BlockProtoSet {
-    pub fn from_toml(fp: &str) -> Self {
+    // the config at `fp` if it exists, otherwise (or if BUILTIN_ASSETS_ENV is set) the block set built into the binary
+    pub fn load(fp: &str, use_builtin: bool) -> Result<Self, BlockConfigError> {
+        if use_builtin || builtin_assets_forced() {
+            return Self::builtin();
+        }
+        if !std::path::Path::new(fp).is_file() {
+            warn!("{} not found, using the built-in block set", fp);
+            return Self::builtin();
+        }
+        Self::from_toml(fp)
+    }
+
+    pub fn builtin() -> Result<Self, BlockConfigError> {
+        Self::from_toml_str(BUILTIN_BLOCKS_TOML, "<built-in blocks.toml>")
+    }
+
+    pub fn from_toml(fp: &str) -> Result<Self, BlockConfigError> {
         use std::fs::read_to_string;
-        let data = read_to_string(fp).expect(&format!("Couldn't open {}", fp));
-        let mut wrapper = toml::from_str::<BlockProtoArrayTableWrapper>(&data).expect("Improperly formatted toml");
+        let data = read_to_string(fp).map_err(|error| BlockConfigError::Io { path: fp.to_string(), error })?;
+        Self::from_toml_str(&data, fp)
+    }
+
+    // `source` is only used to say where the config came from in errors
+    pub fn from_toml_str(data: &str, source: &str) -> Result<Self, BlockConfigError> {
+        let mut wrapper = toml::from_str::<BlockProtoArrayTableWrapper>(data).map_err(|error| BlockConfigError::Parse { path: source.to_string(), error })?;
         let mut true_tex_offset = 0;
 
+        for block in wrapper.blocks.iter_mut() {
+            block.expand_textures()?;
+            Self::validate(block)?;
+            // a missing texture file isn't fatal, the texture set fills in a placeholder
+            for warning in Self::check_textures_exist(block) {
+                warn!("{}", warning);
+            }
+        }
+
         for block in wrapper.blocks.iter_mut() {
             for val in &mut block.tex_face_map {
                 *val += true_tex_offset;
             }
             true_tex_offset += block.textures.len();
-            //println!("{:?}", block.tex_face_map);
         }
 
         let mut actual_blocks = Vec::<BlockProto>::with_capacity(wrapper.blocks.len()+1);
         // ADD AIR FOR CONVENIENCE
         actual_blocks.push(BlockProto{
             name: "Air".to_string(),
+            texture_spec: TextureSpec::List(vec![]),
             textures: vec![],
             tex_face_map: [0,0,0,0,0,0],
             solid: false,
             transparent: true,
+            tile: true,
+            orientable: false,
+            collision_height: 1.0,
+            blast_resistance: 0.0,
+            ore: None,
+            sound: None,
+            render_layer: RenderLayer::Opaque,
+            light_emission: 0,
         });
         actual_blocks.extend(wrapper.blocks);
 
-        Self {
+        Ok(Self {
             blocks: actual_blocks,
+        })
+    }
+
+    // check a block as written in the config, before its tex_face_map is offset into the global texture array
+    fn validate(block: &BlockProto) -> Result<(), BlockConfigError> {
+        if !(block.collision_height > 0.0 && block.collision_height <= 1.0) {
+            return Err(BlockConfigError::BadCollisionHeight { block: block.name.clone(), height: block.collision_height });
+        }
+        if block.render_layer != RenderLayer::Opaque && !block.transparent {
+            return Err(BlockConfigError::OpaqueRenderLayer { block: block.name.clone(), layer: block.render_layer });
+        }
+        if block.light_emission as u32 > crate::geometry::MAX_LIGHT {
+            return Err(BlockConfigError::BadLightEmission { block: block.name.clone(), light: block.light_emission });
+        }
+        if let Some(ore) = &block.ore {
+            let reason = if !(0.0..=1.0).contains(&ore.density) {
+                Some("density has to be between 0 and 1")
+            } else if ore.min_z > ore.max_z {
+                Some("min_z is above max_z")
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                return Err(BlockConfigError::BadOre { block: block.name.clone(), reason });
+            }
         }
+        for (face, &index) in block.tex_face_map.iter().enumerate() {
+            if index >= block.textures.len() {
+                return Err(BlockConfigError::TexFaceMapOutOfRange {
+                    block: block.name.clone(),
+                    face,
+                    index,
+                    texture_count: block.textures.len(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn check_textures_exist(block: &BlockProto) -> Vec<BlockConfigError> {
+        block.textures.iter()
+            .filter(|texture| !std::path::Path::new(TEXTURE_DIR).join(texture).is_file() && crate::texturing::builtin_texture(texture).is_none())
+            .map(|texture| BlockConfigError::MissingTexture { block: block.name.clone(), texture: texture.clone() })
+            .collect()
+    }
+
+    // number of block ids, including air
+    pub fn len(&self) -> usize {
+        self.blocks.len()
     }
 
     pub fn by_id(&self, block_id: BlockID) -> &BlockProto {
         &self.blocks[block_id as usize]
     }
 
+    // every sound set some block uses, each once
+    pub fn sound_sets(&self) -> Vec<&str> {
+        let mut sets = self.blocks.iter().filter_map(|block| block.sound.as_deref()).collect::<Vec<_>>();
+        sets.sort();
+        sets.dedup();
+        sets
+    }
+
+    // (id, spec) of every block that generates as ore
+    pub fn ores(&self) -> impl Iterator<Item=(BlockID, &OreSpec)> {
+        self.blocks.iter().enumerate().filter_map(|(id, block)| block.ore.as_ref().map(|ore| (id as BlockID, ore)))
+    }
+
+    // whether a face of `block_id` is out of sight because `neighbor_id` sits right up against it.
+    // opaque neighbors hide anything; transparent ones only hide faces between two of the same block,
+    // so a wall of glass doesn't draw its insides but glass next to stone or air still shows both sides
+    pub fn face_hidden_by(&self, block_id: BlockID, neighbor_id: BlockID) -> bool {
+        !self.by_id(neighbor_id).transparent || (neighbor_id == block_id && block_id != 0)
+    }
+
+    // every block's texture paths in tex_face_map order. called on the reload path, so keep it quiet
     pub fn collect_textures(&self) -> Vec<String> {
         let mut textures = Vec::<String>::new();
         for block in &self.blocks {
             for texture in &block.textures {
-                let mut s = "assets/textures/".to_string();
+                let mut s = TEXTURE_DIR.to_string();
                 s.push_str(texture);
                 textures.push(s);
             }
@@ -79,7 +355,150 @@ impl BlockProtoSet {
         textures
     }
 
-    pub fn get_tex_id(&self, block_id: BlockID, facing: Facing) -> usize {
-        self.blocks[block_id as usize].tex_face_map[facing as usize]
+    // the part of the block you bump into, in block-local coords. None if you walk straight through it.
+    // a collision_height under 1 makes a slab that thick, sitting on the side opposite `orientation`
+    // (the way its top points), so a slab placed against a ceiling with orientation D hangs from the top of its block
+    pub fn collision_box(&self, block_id: BlockID, orientation: Facing) -> Option<Aabb> {
+        let block = self.by_id(block_id);
+        if !block.solid {
+            None
+        } else if block.collision_height >= 1.0 {
+            Some(Aabb::UNIT)
+        } else {
+            Some(Aabb::unit_slab(block.collision_height, orientation))
+        }
+    }
+
+    // `top` is the direction the block's top face points in the world (Facing::U for unrotated blocks)
+    pub fn get_tex_id(&self, block_id: BlockID, facing: Facing, top: Facing) -> usize {
+        self.blocks[block_id as usize].tex_face_map[facing.to_block_local(top) as usize]
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tex_face_map_out_of_range() {
+        let toml = r#"
+            [[blocks]]
+            name = "Broken"
+            textures = ["stone.png"]
+            tex_face_map = [0, 0, 0, 0, 0, 1]
+        "#;
+        match BlockProtoSet::from_toml_str(toml, "test") {
+            Err(BlockConfigError::TexFaceMapOutOfRange { face, index, texture_count, .. }) => {
+                assert_eq!((face, index, texture_count), (5, 1, 1));
+            },
+            other => panic!("expected TexFaceMapOutOfRange, got {:?}", other.map(|set| set.len())),
+        }
+    }
+
+    #[test]
+    fn light_emission_parses_and_defaults_to_dark() {
+        let toml = r#"
+            [[blocks]]
+            name = "Stone"
+            textures = ["stone.png"]
+
+            [[blocks]]
+            name = "Lamp"
+            textures = ["gold_block.png"]
+            light_emission = 12
+        "#;
+        let set = BlockProtoSet::from_toml_str(toml, "test").unwrap();
+        assert_eq!(set.by_id(1).light_emission, 0);
+        assert_eq!(set.by_id(2).light_emission, 12);
+        assert!(BlockProtoSet::builtin().unwrap().blocks.iter().all(|block| block.light_emission == 0));
+    }
+
+    #[test]
+    fn light_emission_past_max_light_is_refused() {
+        let toml = r#"
+            [[blocks]]
+            name = "Sun"
+            textures = ["gold_block.png"]
+            light_emission = 16
+        "#;
+        match BlockProtoSet::from_toml_str(toml, "test") {
+            Err(BlockConfigError::BadLightEmission { block, light }) => assert_eq!((block.as_str(), light), ("Sun", 16)),
+            other => panic!("expected BadLightEmission, got {:?}", other.map(|set| set.len())),
+        }
+    }
+
+    #[test]
+    fn table_textures_pick_per_face() {
+        let set = BlockProtoSet::builtin().unwrap();
+        let textures = set.collect_textures();
+        let grass = 4;
+        let texture_of = |facing, top| textures[set.get_tex_id(grass, facing, top)].as_str();
+
+        assert_eq!(texture_of(Facing::U, Facing::U), "assets/textures/grass_block_top.png");
+        assert_eq!(texture_of(Facing::D, Facing::U), "assets/textures/dirt.png");
+        for side in [Facing::N, Facing::E, Facing::W, Facing::S] {
+            assert_eq!(texture_of(side, Facing::U), "assets/textures/grass_block_side.png");
+        }
+        // "side" is only listed once even though four faces use it
+        assert_eq!(set.by_id(grass).textures.len(), 3);
+        // tipped over so its top points north
+        assert_eq!(texture_of(Facing::N, Facing::N), "assets/textures/grass_block_top.png");
+    }
+
+    #[test]
+    fn collect_textures_lists_every_block_texture_in_order() {
+        let set = BlockProtoSet::builtin().unwrap();
+        let expected = [
+            "bedrock.png", "stone.png", "cobblestone.png",
+            "grass_block_side.png", "grass_block_top.png", "dirt.png",
+            "dirt.png", "gold_block.png", "coal_ore.png", "iron_ore.png", "oak_leaves.png", "glass.png",
+        ].map(|texture| format!("{}{}", TEXTURE_DIR, texture));
+        assert_eq!(set.collect_textures(), expected);
+        // every face's tex id points into its own block's textures
+        let textures = set.collect_textures();
+        for id in 1..set.len() as BlockID {
+            for facing in Facing::all() {
+                let texture = &textures[set.get_tex_id(id, facing, Facing::U)];
+                assert!(set.by_id(id).textures.iter().any(|t| texture.ends_with(t.as_str())));
+            }
+        }
+    }
+
+    #[test]
+    fn glass_only_hides_faces_against_itself() {
+        let set = BlockProtoSet::builtin().unwrap();
+        let (air, stone, leaves, glass) = (0, 2, 9, 10);
+        // the wall between two panes of glass isn't drawn
+        assert!(set.face_hidden_by(glass, glass));
+        // but stone behind glass shows through, and glass still shows against stone it's touching
+        assert!(!set.face_hidden_by(stone, glass));
+        assert!(set.face_hidden_by(glass, stone));
+        assert!(!set.face_hidden_by(glass, air));
+        assert!(!set.face_hidden_by(glass, leaves));
+        assert!(!set.face_hidden_by(leaves, glass));
+        assert!(set.face_hidden_by(stone, stone));
+    }
+
+    #[test]
+    fn table_textures_need_every_face() {
+        let toml = r#"
+            [[blocks]]
+            name = "Topless"
+            textures = { side = "stone.png", bottom = "dirt.png" }
+        "#;
+        assert!(matches!(BlockProtoSet::from_toml_str(toml, "test"), Err(BlockConfigError::MissingFaceTexture { face: "top", .. })));
+    }
+
+    #[test]
+    fn missing_texture_is_reported_but_not_fatal() {
+        let toml = r#"
+            [[blocks]]
+            name = "Ghost"
+            textures = ["stone.png", "definitely_not_a_texture.png"]
+        "#;
+        let set = BlockProtoSet::from_toml_str(toml, "test").unwrap();
+        let missing = BlockProtoSet::check_textures_exist(set.by_id(1));
+        assert_eq!(missing.len(), 1);
+        assert!(matches!(&missing[0], BlockConfigError::MissingTexture { texture, .. } if texture == "definitely_not_a_texture.png"));
+    }
+}
@@ -1,20 +1,47 @@
 extern crate glam;
 
-use std::sync::RwLock;
 use crate::entity;
+use crate::settings::CameraSettings;
 
 use glam::{
     Vec3,
     Mat4,
+    Quat,
 };
 
 const DEG_TO_RAD: f32 = 0.0174532925;
+// degrees turned per unit of mouse movement. macos reports much smaller mouse deltas
+pub const DEFAULT_LOOK_SENSITIVITY: f32 = if cfg!(target_os = "macos") {0.07} else {0.02};
+// the [ and ] keys scale sensitivity by this much each press
+pub const LOOK_SENSITIVITY_STEP: f32 = 1.1;
+// how fast shake dies out, per second
+const SHAKE_DECAY: f32 = 6.0;
+// how fast bobbing fades in and out when you start/stop walking, per second
+const BOB_FADE_RATE: f32 = 8.0;
+// how fast the fov eases into and out of the sprint boost, per second
+const SPRINT_FOV_RATE: f32 = 8.0;
 
 pub struct Camera {
+    // what's on screen right now, radians. eases towards base_fov plus the sprint boost, see update()
     pub fov: f32,
+    base_fov: f32,
+    pub sprint_fov_boost: f32, // degrees
     pub aspect_ratio: f32,
     pub look_sensitivity: f32,
     pub proj_mat: Mat4,
+
+    // radians around the look direction, positive tilts the view clockwise
+    pub roll: f32,
+    // current shake strength in blocks, decays in update()
+    shake_amplitude: f32,
+    time: f32,
+
+    pub view_bobbing: bool,
+    pub bob_amplitude: f32,
+    pub bob_frequency: f32,
+    bob_phase: f32,
+    // 0..1, eases towards how fast we're walking so bobbing doesn't snap on and off
+    bob_weight: f32,
 }
 
 impl Camera {
@@ -22,16 +49,99 @@ impl Camera {
         let fov = std::f32::consts::FRAC_PI_2;
         Self {
             fov,
+            base_fov: fov,
+            sprint_fov_boost: 10.0,
             aspect_ratio,
-            look_sensitivity: if cfg!(target_os = "macos") {0.07} else {0.02},
-            proj_mat: Self::get_proj_mat(fov, aspect_ratio)
+            look_sensitivity: DEFAULT_LOOK_SENSITIVITY,
+            proj_mat: Self::get_proj_mat(fov, aspect_ratio),
+
+            roll: 0.0,
+            shake_amplitude: 0.0,
+            time: 0.0,
+
+            view_bobbing: true,
+            bob_amplitude: 0.05,
+            bob_frequency: 0.6,
+            bob_phase: 0.0,
+            bob_weight: 0.0,
+        }
+    }
+
+    pub fn apply_settings(&mut self, settings: &CameraSettings) {
+        self.view_bobbing = settings.view_bobbing;
+        self.bob_amplitude = settings.bob_amplitude;
+        self.bob_frequency = settings.bob_frequency;
+        self.sprint_fov_boost = settings.sprint_fov_boost;
+        self.look_sensitivity = settings.look_sensitivity;
+    }
+
+    // kick off (or add to) a screen shake, e.g. landing hard or an explosion nearby
+    pub fn shake(&mut self, amplitude: f32) {
+        self.shake_amplitude += amplitude;
+    }
+
+    // advance transient effects. call once a frame with whoever the camera is following
+    pub fn update(&mut self, dt: f32, entity: &entity::Entity) {
+        self.time += dt;
+        self.shake_amplitude *= (-SHAKE_DECAY * dt).exp();
+        if self.shake_amplitude < 0.001 {
+            self.shake_amplitude = 0.0;
+        }
+
+        // only bob while walking on the ground
+        let speed = entity.vel.with_z(0.0).length();
+        let target = if self.view_bobbing && !entity.flying && !entity.in_air {
+            (speed / entity.move_speed).min(1.0)
+        } else {
+            0.0
+        };
+        self.bob_weight += (target - self.bob_weight) * (BOB_FADE_RATE * dt).min(1.0);
+        // the vertical bob runs at twice the phase, so a pi of phase is one bob
+        self.bob_phase = (self.bob_phase + speed * self.bob_frequency * std::f32::consts::PI * dt) % std::f32::consts::TAU;
+
+        // widen a little while sprinting, for a sense of speed
+        let target_fov = if entity.is_sprinting() { self.base_fov + self.sprint_fov_boost * DEG_TO_RAD } else { self.base_fov };
+        if self.fov != target_fov {
+            self.fov += (target_fov - self.fov) * (SPRINT_FOV_RATE * dt).min(1.0);
+            if (target_fov - self.fov).abs() < 0.0001 {
+                self.fov = target_fov;
+            }
+            self.proj_mat = Self::get_proj_mat(self.fov, self.aspect_ratio);
+        }
+    }
+
+    // up and down once per step, side to side once every two
+    fn bob_offset(&self, entity: &entity::Entity) -> Vec3 {
+        if self.bob_weight < 0.001 {
+            return Vec3::ZERO;
+        }
+        let amplitude = self.bob_amplitude * self.bob_weight;
+        entity.body_rightward() * self.bob_phase.sin() * amplitude * 0.5
+            + Vec3::Z * (self.bob_phase * 2.0).sin() * amplitude
+    }
+
+    // a jittery offset from a few out-of-phase sines, cheaper than real noise and smooth enough not to look like a glitch
+    fn shake_offset(&self) -> (Vec3, f32) {
+        if self.shake_amplitude == 0.0 {
+            return (Vec3::ZERO, 0.0);
         }
+        let t = self.time;
+        let offset = Vec3::new(
+            (t * 47.0).sin() + (t * 31.0).sin() * 0.5,
+            (t * 53.0).sin() + (t * 29.0).sin() * 0.5,
+            (t * 59.0).sin() + (t * 37.0).sin() * 0.5,
+        ) * self.shake_amplitude / 1.5;
+        let roll = (t * 43.0).sin() * self.shake_amplitude * 0.1;
+        (offset, roll)
     }
 
+    // the fov without any sprint boost. takes effect immediately, the boost (if any) is dropped until update() eases it back in
     pub fn set_fov(&mut self, fov_deg: f32) {
-        self.fov = fov_deg * DEG_TO_RAD;
+        self.base_fov = fov_deg * DEG_TO_RAD;
+        self.fov = self.base_fov;
         self.proj_mat = Self::get_proj_mat(self.fov, self.aspect_ratio);
     }
+    // keeps whatever fov we're at, so resizing mid-sprint doesn't snap the boost off
     pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
         self.aspect_ratio = aspect_ratio;
         self.proj_mat = Self::get_proj_mat(self.fov, self.aspect_ratio);
@@ -40,11 +150,13 @@ impl Camera {
     fn get_proj_mat(fov: f32, aspect_ratio: f32) -> Mat4 {
         Mat4::perspective_infinite_rh(fov, aspect_ratio, 0.001)
     }
-    fn get_view_mat(&self, entity: &RwLock<entity::Entity>) -> Mat4 {
-        let entity = entity.read().unwrap();
-        Mat4::look_to_rh(entity.pos + Vec3::Z * entity.eye_height, entity.facing, Vec3::Z)
+    fn get_view_mat(&self, entity: &entity::Entity) -> Mat4 {
+        let (shake_offset, shake_roll) = self.shake_offset();
+        let roll = self.roll + shake_roll;
+        let up = if roll == 0.0 { Vec3::Z } else { Quat::from_axis_angle(entity.look_dir(), roll) * Vec3::Z };
+        Mat4::look_to_rh(entity.pos + Vec3::Z * entity.eye_height + shake_offset + self.bob_offset(entity), entity.look_dir(), up)
     }
-    pub fn get_projview(&self, entity: &RwLock<entity::Entity>) -> Mat4 {
+    pub fn get_projview(&self, entity: &entity::Entity) -> Mat4 {
         self.proj_mat * self.get_view_mat(entity)
     }
 }
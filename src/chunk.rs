@@ -1,30 +1,109 @@
 use std::sync::RwLock;
-use std::sync::Mutex;
+use std::cell::RefCell;
 use std::sync::Arc;
-use crate::block::{BlockProtoSet, BlockID};
-use crate::geometry::{Vertex, Facing};
+use std::time::Instant;
+use crate::block::{BlockProtoSet, BlockID, RenderLayer};
+use crate::geometry::{Vertex, Facing, MAX_LIGHT};
+use crate::meshcache::{GpuMesh, MeshCache};
 use ndarray::prelude::*;
 use ndarray::{Ix3, Axis};
+use std::collections::VecDeque;
 use noise::NoiseFn;
+use crate::terrain::Terrain;
 use glam::Vec3;
 
+// the one knob for chunk size. everything else (arrays, views, tessellator, chunkset math, culling) derives from it
 pub const CHUNK_SIZE: usize = 16;
 pub const CHUNK_VOLUME: usize = CHUNK_SIZE*CHUNK_SIZE*CHUNK_SIZE;
 pub const CHUNK_SIZE_F: f32 = CHUNK_SIZE as f32;
+// corner to corner
+pub const CHUNK_DIAGONAL: f32 = CHUNK_SIZE_F * 1.7320508;
+
+// vertex positions are packed chunk-local (see geometry::Vertex)
+const _: () = assert!(CHUNK_SIZE as u32 <= crate::geometry::MAX_PACKED_POS, "CHUNK_SIZE is too big for the packed vertex format");
+// ...and so are the uvs of a merged face, which can stretch the whole chunk
+const _: () = assert!(CHUNK_SIZE <= 0xff, "CHUNK_SIZE is too big for the packed uvs");
 
 
 type ChunkArray<T> = [T; CHUNK_VOLUME];
 
+// a mesh under construction, one vec per render layer
+pub type LayerVertices = [Vec<Vertex>; RenderLayer::COUNT];
+
+// mesh_key of every chunk with nothing in it
+const EMPTY_MESH_KEY: u64 = 0;
+
+// what the tessellator merges faces on: two faces only merge if their block id, orientation and light all match
+// low 16 bits are the block id, the next 4 the orientation, then 4 for the light (added by make_mesh once it knows which way
+// the face points). air is always 0 so the tessellator can skip it
+pub type FaceKey = u32;
+const FACE_KEY_LIGHT_SHIFT: u32 = 20;
+pub fn face_key(id: BlockID, orientation: u8) -> FaceKey {
+    if id == 0 { 0 } else { id as FaceKey | (orientation as FaceKey) << 16 }
+}
+pub fn face_key_id(key: FaceKey) -> BlockID {
+    (key & 0xffff) as BlockID
+}
+pub fn face_key_orientation(key: FaceKey) -> Facing {
+    Facing::from_index(((key >> 16) & 0xf) as u8)
+}
+pub fn face_key_with_light(key: FaceKey, light: u8) -> FaceKey {
+    key | (light as FaceKey) << FACE_KEY_LIGHT_SHIFT
+}
+pub fn face_key_light(key: FaceKey) -> u32 {
+    (key >> FACE_KEY_LIGHT_SHIFT) & MAX_LIGHT
+}
+
+// bumped whenever the byte layout of Chunk::serialize changes, so old saves are refused instead of misread
+const SAVE_FORMAT_VERSION: u8 = 1;
+// one run: block count (u32), block id (u16), orientation (u8), all little endian
+const SAVE_RUN_BYTES: usize = 7;
+
+#[derive(Debug)]
+pub enum ChunkDecodeError {
+    UnknownVersion(u8),
+    // ends partway through a run
+    Truncated,
+    // the runs don't add up to exactly one chunk
+    WrongBlockCount(usize),
+    BadOrientation(u8),
+}
+impl std::fmt::Display for ChunkDecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownVersion(version) => write!(f, "save format {} isn't one we know (expected {})", version, SAVE_FORMAT_VERSION),
+            Self::Truncated => write!(f, "the data ends partway through a block"),
+            Self::WrongBlockCount(found) => write!(f, "it holds {} blocks instead of {}", found, CHUNK_VOLUME),
+            Self::BadOrientation(orientation) => write!(f, "a block has orientation {}, which isn't a facing (0-5)", orientation),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Chunk {
     pub pos: Vec3,
     ids_array: ChunkArray<BlockID>,
+    // which way each block's top points, as a Facing index. only meaningful for orientable blocks
+    orientation_array: ChunkArray<u8>,
     visibility_array: ChunkArray<u8>,
+    // 0 (dark) to MAX_LIGHT (open sky), only meaningful in blocks light gets into. rebuilt by make_mesh, see compute_light
+    light_array: ChunkArray<u8>,
+    // quads grouped by render layer, in RenderLayer order. layer_quads says how many belong to each
     pub mesh: Vec<Vertex>,
+    pub layer_quads: [u32; RenderLayer::COUNT],
     pub ready_to_display: bool,
-    pub vertex_buffer: Option<wgpu::Buffer>,
-    pub index_buffer: Option<wgpu::Buffer>,
-    pub index_count: u32,
+    // when ready_to_display first flipped, for the fade-in. remeshing doesn't reset it
+    pub displayed_at: Option<Instant>,
+    // the mesh changed since it was last pushed to the gpu
+    pub needs_upload: bool,
+    // possibly shared with other chunks, see meshcache.rs
+    pub gpu_mesh: Option<Arc<GpuMesh>>,
+    // hash of the blocks `mesh` was built from
+    pub mesh_key: u64,
+    // which of the renderer's texture sets the mesh's tex_ids point into
+    pub texture_set: usize,
+    // blocks were set since it was generated or loaded, so it has to be saved to keep them
+    pub modified: bool,
 }
 
 impl<'a> Chunk {
@@ -33,12 +112,18 @@ impl<'a> Chunk {
         Self {
             pos: Vec3::new(x, y, z), // in world coords
             ids_array: [0; CHUNK_VOLUME],
+            orientation_array: [Facing::U as u8; CHUNK_VOLUME],
             visibility_array: [1; CHUNK_VOLUME],
+            light_array: [MAX_LIGHT as u8; CHUNK_VOLUME],
             mesh: vec![],
+            layer_quads: [0; RenderLayer::COUNT],
             ready_to_display: false,
-            vertex_buffer: None,
-            index_buffer: None,
-            index_count: 0,
+            displayed_at: None,
+            needs_upload: false,
+            gpu_mesh: None,
+            mesh_key: 0,
+            texture_set: 0,
+            modified: false,
         }
         //})
     }
@@ -50,9 +135,21 @@ impl<'a> Chunk {
     }
 
     pub fn set_block_id_at(&mut self, pos: Vec3, id: BlockID) {
+        self.set_block_at(pos, id, Facing::U);
+    }
+
+    pub fn set_block_at(&mut self, pos: Vec3, id: BlockID, orientation: Facing) {
         let (chunk_x, chunk_y, chunk_z) = (pos.floor()-self.pos).into();
         let (chunk_i, chunk_j, chunk_k) = (chunk_x as usize, chunk_y as usize, chunk_z as usize);
         Self::get_view_mut(&mut self.ids_array)[(chunk_i, chunk_j, chunk_k)] = id;
+        Self::get_view_mut(&mut self.orientation_array)[(chunk_i, chunk_j, chunk_k)] = orientation as u8;
+        self.modified = true;
+    }
+
+    pub fn get_orientation_at(&self, pos: Vec3) -> Facing {
+        let (chunk_x, chunk_y, chunk_z) = (pos.floor()-self.pos).into();
+        let (chunk_i, chunk_j, chunk_k) = (chunk_x as usize, chunk_y as usize, chunk_z as usize);
+        Facing::from_index(Self::get_view(&self.orientation_array)[(chunk_i, chunk_j, chunk_k)])
     }
 
     pub fn get_block_id_at(&self, pos: Vec3) -> BlockID {
@@ -77,65 +174,222 @@ impl<'a> Chunk {
         }
     }
 
-    pub fn make_vertex_buffer(&mut self, device: &impl wgpu::util::DeviceExt) {
-        self.vertex_buffer = Some(device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&self.mesh),
-                usage: wgpu::BufferUsages::VERTEX,
+    // points this chunk at a gpu copy of its mesh, sharing one with any other chunk that has the same blocks
+    pub fn make_vertex_buffer(&mut self, device: &wgpu::Device, mesh_cache: &mut MeshCache) {
+        let gpu_mesh = mesh_cache.get_or_upload(self.mesh_key, || GpuMesh::new(device, &self.mesh, &self.get_indices(0)).with_layer_quads(self.layer_quads));
+        // our old mesh (if any) is released here; it's only freed once no other chunk is drawing it
+        self.gpu_mesh = Some(gpu_mesh);
+        self.needs_upload = false;
+    }
+
+    // identifies the mesh these blocks produce. equal keys mean equal meshes, which is what lets chunks share them
+    fn compute_mesh_key(&self) -> u64 {
+        self.content_hash()
+    }
+
+    // FNV-1a over the block ids (little endian) and orientations. unlike std's hasher this is the same on every
+    // machine and every build, so it can be stored in saves or compared with another game's chunks
+    pub fn content_hash(&self) -> u64 {
+        const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+        const FNV_PRIME: u64 = 0x100000001b3;
+        let mut hash = FNV_OFFSET;
+        let mut feed = |byte: u8| {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(FNV_PRIME);
+        };
+        for id in self.ids_array.iter() {
+            id.to_le_bytes().into_iter().for_each(&mut feed);
+        }
+        self.orientation_array.iter().copied().for_each(&mut feed);
+        hash
+    }
+
+    // the blocks (ids and orientations) run-length encoded, for saving. terrain is mostly long runs of air and stone,
+    // so a chunk usually comes out a few kilobytes instead of 12. the mesh isn't saved, it's rebuilt on load
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = vec![SAVE_FORMAT_VERSION];
+        let mut push_run = |count: u32, id: BlockID, orientation: u8| {
+            bytes.extend_from_slice(&count.to_le_bytes());
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.push(orientation);
+        };
+        let mut blocks = self.ids_array.iter().copied().zip(self.orientation_array.iter().copied());
+        let Some(mut run) = blocks.next() else { return bytes };
+        let mut count = 1;
+        for block in blocks {
+            if block == run {
+                count += 1;
+            } else {
+                push_run(count, run.0, run.1);
+                (run, count) = (block, 1);
             }
-        ));
-        let indices = self.get_indices(0);
-        self.index_buffer = Some(device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
-                usage: wgpu::BufferUsages::INDEX,
+        }
+        push_run(count, run.0, run.1);
+        bytes
+    }
+
+    // the reverse of serialize. (x, y, z) is the chunk's corner in world coords, like Chunk::new. the chunk comes back unmeshed
+    pub fn deserialize(bytes: &[u8], x: f32, y: f32, z: f32) -> Result<Self, ChunkDecodeError> {
+        let (&version, runs) = bytes.split_first().ok_or(ChunkDecodeError::Truncated)?;
+        if version != SAVE_FORMAT_VERSION {
+            return Err(ChunkDecodeError::UnknownVersion(version));
+        }
+        if runs.len() % SAVE_RUN_BYTES != 0 {
+            return Err(ChunkDecodeError::Truncated);
+        }
+
+        let mut chunk = Self::new(x, y, z);
+        let mut filled = 0;
+        for run in runs.chunks_exact(SAVE_RUN_BYTES) {
+            let count = u32::from_le_bytes([run[0], run[1], run[2], run[3]]) as usize;
+            let id = BlockID::from_le_bytes([run[4], run[5]]);
+            let orientation = run[6];
+            if orientation as usize >= Facing::all().len() {
+                return Err(ChunkDecodeError::BadOrientation(orientation));
             }
-        ));
-        self.index_count = indices.len() as u32;
+            if filled + count > CHUNK_VOLUME {
+                return Err(ChunkDecodeError::WrongBlockCount(filled + count));
+            }
+            chunk.ids_array[filled..filled + count].fill(id);
+            chunk.orientation_array[filled..filled + count].fill(orientation);
+            filled += count;
+        }
+        if filled != CHUNK_VOLUME {
+            return Err(ChunkDecodeError::WrongBlockCount(filled));
+        }
+        Ok(chunk)
+    }
+
+    pub fn is_all_air(&self) -> bool {
+        self.ids_array.iter().all(|&id| id == 0)
+    }
+
+    // sunlight pours straight down every column from the top of the chunk until it hits something opaque, then spreads out
+    // sideways (and under overhangs) one level dimmer per block. glowing blocks (light_emission) spread theirs the same way.
+    // only looks inside this chunk for now, so the top is always treated as open sky and no light comes in through the sides
+    pub fn compute_light(&mut self, block_proto_set: &BlockProtoSet) {
+        let ids = Self::get_view(&self.ids_array);
+        let mut light = Self::get_view_mut(&mut self.light_array);
+        light.fill(0);
+        let lets_light_through = |id: BlockID| id == 0 || block_proto_set.by_id(id).transparent;
+
+        let mut queue = VecDeque::new();
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in (0..CHUNK_SIZE).rev() {
+                    if !lets_light_through(ids[(x, y, z)]) {
+                        break;
+                    }
+                    light[(x, y, z)] = MAX_LIGHT as u8;
+                    queue.push_back((x, y, z));
+                }
+            }
+        }
+        for ((x, y, z), &id) in ids.indexed_iter() {
+            let emission = block_proto_set.by_id(id).light_emission;
+            if emission > light[(x, y, z)] {
+                light[(x, y, z)] = emission;
+                queue.push_back((x, y, z));
+            }
+        }
+
+        while let Some((x, y, z)) = queue.pop_front() {
+            let spread = light[(x, y, z)].saturating_sub(1);
+            if spread == 0 {
+                continue;
+            }
+            for facing in Facing::all() {
+                let n = facing.normal();
+                let (nx, ny, nz) = (x as isize + n.x as isize, y as isize + n.y as isize, z as isize + n.z as isize);
+                if [nx, ny, nz].iter().any(|&c| c < 0 || c >= CHUNK_SIZE as isize) {
+                    continue;
+                }
+                let neighbor = (nx as usize, ny as usize, nz as usize);
+                if light[neighbor] < spread && lets_light_through(ids[neighbor]) {
+                    light[neighbor] = spread;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
     }
 
     pub fn make_mesh(&mut self, block_proto_set: &BlockProtoSet, tp: &rayon::ThreadPool) {
         use glam::Vec3A;
-        let ids = Self::get_view(&self.ids_array);
-        let mut vertices = Mutex::new(vec![]);
+        // most chunks above ground. nothing to tessellate, and they can all share the one empty gpu mesh
+        if self.is_all_air() {
+            self.mesh.clear();
+            self.layer_quads = [0; RenderLayer::COUNT];
+            self.mesh_key = EMPTY_MESH_KEY;
+            self.needs_upload = true;
+            return;
+        }
+
+        self.compute_light(block_proto_set);
+        let keys = ndarray::Zip::from(Self::get_view(&self.ids_array))
+            .and(Self::get_view(&self.orientation_array))
+            .map_collect(|&id, &orientation| face_key(id, orientation));
+        let light = Self::get_view(&self.light_array);
 
-        // just thread this lol, this is 6*size threads easy
+        // each rayon worker keeps its scratch space around between slices and between chunks instead of reallocating
+        thread_local! {
+            static SCRATCH: RefCell<(Array2<FaceKey>, Vec<tessellate::Square>)> = RefCell::new((Array2::zeros((CHUNK_SIZE, CHUNK_SIZE)), vec![]));
+        }
+        // `neighbor` is the slice the faces point into (with its light), None past the edge of the chunk (where everything shows,
+        // fully lit, for now)
+        let mesh_slice = |slice: ArrayView::<FaceKey, Ix2>, neighbor: Option<(ArrayView::<FaceKey, Ix2>, ArrayView::<u8, Ix2>)>, offset: Vec3A, facing: Facing, verts: &mut LayerVertices| {
+            SCRATCH.with(|scratch| {
+                let mut scratch = scratch.borrow_mut();
+                let (exposed, squares) = &mut *scratch;
+                exposed.assign(&slice);
+                if let Some((neighbor, neighbor_light)) = neighbor {
+                    ndarray::Zip::from(&mut *exposed).and(neighbor).and(neighbor_light).for_each(|key, &neighbor_key, &light| {
+                        if *key != 0 {
+                            *key = if block_proto_set.face_hidden_by(face_key_id(*key), face_key_id(neighbor_key)) { 0 } else { face_key_with_light(*key, light) };
+                        }
+                    });
+                } else {
+                    exposed.mapv_inplace(|key| if key != 0 { face_key_with_light(key, MAX_LIGHT as u8) } else { 0 });
+                }
+                tessellate::tessellate_slice(exposed.view(), block_proto_set, squares);
+                tessellate::squares_to_vertices(squares, offset, facing, block_proto_set, verts);
+            });
+        };
 
         // THESE ITERATORS ARE ALL BLOCKING
+        // every task fills its own vec and they're only stitched together at the end, so there's nothing to wait on in between
         use rayon::prelude::*;
-        ids.axis_iter(Axis(2)).enumerate().par_bridge().for_each(|(z, slice)| {
-            let squares = tessellate::tessellate_slice(slice);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x, self.pos.y, self.pos.z + z as f32), Facing::U, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x, self.pos.y, self.pos.z + z as f32), Facing::D, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
-        });
-        ids.axis_iter(Axis(1)).enumerate().par_bridge().for_each(|(y, slice)| {
-            let squares = tessellate::tessellate_slice(slice);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x, self.pos.y + y as f32, self.pos.z), Facing::N, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x, self.pos.y + y as f32, self.pos.z), Facing::S, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
+        // (axis, face pointing up the axis, face pointing down it)
+        let axes = [(2, Facing::U, Facing::D), (1, Facing::N, Facing::S), (0, Facing::E, Facing::W)];
+        let slices = axes.into_par_iter().flat_map(|(axis, up_face, down_face)| {
+            (0..CHUNK_SIZE).into_par_iter().map(move |k| (axis, k, up_face, down_face))
         });
-        ids.axis_iter(Axis(0)).enumerate().par_bridge().for_each(|(x, slice)| {
-            let squares = tessellate::tessellate_slice(slice);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x + x as f32, self.pos.y, self.pos.z), Facing::E, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
-            let verts = tessellate::squares_to_vertices(&squares, Vec3A::new(self.pos.x + x as f32, self.pos.y, self.pos.z), Facing::W, block_proto_set);
-            vertices.lock().unwrap().extend(verts);
-        });
-
-        //println!("{:?}", vertices.len());
-
-        self.mesh = (vertices.get_mut().unwrap()).to_vec();
-        if self.vertex_buffer.is_some() || self.index_buffer.is_some() {
-            self.vertex_buffer.as_ref().unwrap().destroy();
-            self.index_buffer.as_ref().unwrap().destroy();
-            self.vertex_buffer = None;
-            self.index_buffer = None;
-        }
+        let mesh = slices
+            .fold(LayerVertices::default, |mut verts, (axis, k, up_face, down_face)| {
+                let slice = keys.index_axis(Axis(axis), k);
+                let mut offset = Vec3A::ZERO;
+                offset[axis] = k as f32;
+                let above = (k + 1 < CHUNK_SIZE).then(|| (keys.index_axis(Axis(axis), k + 1), light.index_axis(Axis(axis), k + 1)));
+                let below = k.checked_sub(1).map(|j| (keys.index_axis(Axis(axis), j), light.index_axis(Axis(axis), j)));
+                mesh_slice(slice, above, offset, up_face, &mut verts);
+                mesh_slice(slice, below, offset, down_face, &mut verts);
+                verts
+            })
+            .reduce(LayerVertices::default, |mut a, mut b| {
+                for (a, b) in a.iter_mut().zip(&mut b) {
+                    // append the shorter one onto the longer one
+                    if a.len() < b.len() {
+                        std::mem::swap(a, b);
+                    }
+                    a.append(b);
+                }
+                a
+            });
+
+        self.layer_quads = mesh.each_ref().map(|verts| (verts.len() / 4) as u32);
+        self.mesh = mesh.concat();
+        self.mesh_key = self.compute_mesh_key();
+        // the old buffers stay up (and keep rendering) until the new mesh is uploaded
+        self.needs_upload = true;
     }
 
     pub fn get_indices(&self, indices_offset: u32) -> Vec<u32> {
@@ -149,15 +403,49 @@ impl<'a> Chunk {
         return indices
     }
 
-    pub fn generate_planet(&mut self) {
-        let scale = 0.05;
-        let noise_gen = noise::Perlin::new(0);
+    // a layer of bedrock at height z (world coords), if it passes through this chunk
+    pub fn generate_void_floor(&mut self, z: f32) {
+        let local_z = z.floor() - self.pos.z;
+        if local_z >= 0.0 && local_z < CHUNK_SIZE_F {
+            Self::get_view_mut(&mut self.ids_array).slice_mut(s![.., .., local_z as usize]).fill(1); // block ID 1 is bedrock
+        }
+    }
+
+    // swap stone for ore wherever each ore's 3d noise is high enough. the noise only depends on world coords (and the ore's id),
+    // so veins come out the same every time the chunk is generated and line up across chunk borders
+    // each ore gets its own noise, offset from the world seed by its id so veins of different ores don't line up
+    pub fn generate_ores(&mut self, block_proto_set: &BlockProtoSet, seed: u32) {
+        let origin = self.pos;
+        let mut ids = Self::get_view_mut(&mut self.ids_array);
+        for (ore_id, ore) in block_proto_set.ores() {
+            // nothing to do if the chunk is entirely outside the ore's depth range
+            if origin.z > ore.max_z || origin.z + CHUNK_SIZE_F <= ore.min_z {
+                continue;
+            }
+            let noise_gen = noise::Perlin::new(seed.wrapping_add(ore_id as u32));
+            let threshold = 1.0 - ore.density as f64;
+            let scale = ore.scale as f64;
+            for ((x, y, z), id) in ids.indexed_iter_mut() {
+                let world_z = origin.z + z as f32;
+                // ores only replace stone, so they're always underground
+                if *id != 2 || world_z < ore.min_z || world_z > ore.max_z {
+                    continue;
+                }
+                let p = [(origin.x as f64 + x as f64) * scale, (origin.y as f64 + y as f64) * scale, world_z as f64 * scale];
+                if noise_gen.get(p) > threshold {
+                    *id = ore_id;
+                }
+            }
+        }
+    }
+
+    // `terrain` is the world's height map, made once from its seed and shared by every chunk
+    pub fn generate_planet(&mut self, terrain: &Terrain) {
         let mut ids = Self::get_view_mut(&mut self.ids_array);
         
         for x in 0..CHUNK_SIZE {
             for y in 0..CHUNK_SIZE {
-                let z = noise_gen.get([(self.pos.x as f64 + x as f64) * scale, (self.pos.y as f64 + y as f64) * scale]);
-                let scaled_z = (z * 16.0).floor() as f32;
+                let scaled_z = terrain.height_at(self.pos.x as f64 + x as f64, self.pos.y as f64 + y as f64);
                 if scaled_z >= self.pos.z {
                     let top_z = (scaled_z - self.pos.z) as usize;
                     if scaled_z < CHUNK_SIZE_F + self.pos.z {
@@ -188,36 +476,47 @@ mod tessellate {
     use crate::geometry::{Vertex, Facing};
     use crate::block::BlockProtoSet;
 
-    pub fn tessellate_slice(slice: ArrayView::<BlockID, Ix2>) -> Vec<(usize, usize, usize, usize, BlockID)> {
-        let mut squares: Vec<(usize, usize, usize, usize, BlockID)> = vec![];
+    // (x1, y1, x2, y2, key), x2 and y2 exclusive
+    pub type Square = (usize, usize, usize, usize, FaceKey);
+
+    // the biggest slice side tessellate_slice takes; anything bigger couldn't be packed into vertices anyway
+    const MAX_SLICE_SIZE: usize = crate::geometry::MAX_PACKED_POS as usize;
+
+    // fills `squares` (cleared first) so callers can reuse the allocation.
+    // works off the slice's own shape rather than CHUNK_SIZE, so it can be tested at other chunk sizes
+    pub fn tessellate_slice(slice: ArrayView::<FaceKey, Ix2>, block_proto_set: &BlockProtoSet, squares: &mut Vec<Square>) {
+        squares.clear();
+        let (width, height) = (slice.shape()[0], slice.shape()[1]);
+        assert!(width <= MAX_SLICE_SIZE && height <= MAX_SLICE_SIZE, "{}x{} slice is too big to tessellate", width, height);
         let (mut x1, mut y1, mut x2, mut y2) = (0, 0, 0, 0);
         let mut found_new_square_anchor;
 
-        fn in_square(x: usize, y: usize, sq: &(usize, usize, usize, usize, BlockID)) -> bool {
-            x >= sq.0 && y >= sq.1 && x < sq.2 && y < sq.3
+        // one bit per cell, set once a square covers it. checking this is O(1) where scanning every square wasn't
+        let mut churched = [0u64; (MAX_SLICE_SIZE*MAX_SLICE_SIZE).div_ceil(64)];
+        fn in_square(churched: &[u64], height: usize, x: usize, y: usize) -> bool {
+            let i = x * height + y;
+            churched[i / 64] & (1u64 << (i % 64)) != 0
+        }
+        fn church(churched: &mut [u64], height: usize, x: usize, y: usize) {
+            let i = x * height + y;
+            churched[i / 64] |= 1u64 << (i % 64);
         }
 
         let mut i = 0;
         loop {
             i += 1;
-            assert!(i < CHUNK_SIZE*CHUNK_SIZE, "You created an infinite loop in the tessellator. This is a bug, please report.");
+            // worst case every cell is its own square, plus one more pass to find there's nothing left
+            assert!(i <= width*height + 1, "You created an infinite loop in the tessellator. This is a bug, please report.");
 
             found_new_square_anchor = false;
             // find the next unchurched block
             'square_finder_y: for (y, row) in slice.axis_iter(Axis(1)).enumerate() {
                 if y < y1 { continue; } // we're below the last known square so it can't be unchurched - skip
 
-                'square_finder_x: for (x, v) in row.iter().enumerate() {
-                    if *v != 0 {
+                for (x, v) in row.iter().enumerate() {
+                    // if we are in a square, skip to the next block
+                    if *v != 0 && !in_square(&churched, height, x, y) {
                         found_new_square_anchor = true;
-                        // if we are in a square, get out of here and start over with the next block
-                        for square in &squares {
-                            if in_square(x, y, square) {
-                                found_new_square_anchor = false;
-                                continue 'square_finder_x;
-                            }
-                        }
-
                         (x1, y1, x2, y2) = (x, y, x, y);
                         break 'square_finder_y;
                     }
@@ -229,24 +528,26 @@ mod tessellate {
                 break;
             }
 
-            let current_block_id: BlockID = slice[(x1, y1)];
+            let current_block_id: FaceKey = slice[(x1, y1)];
 
-            let mut hit_wall_y = false;
-            let mut hit_wall_x = false;
+            // blocks that stretch their texture can't be merged, so they start out walled in
+            let mergeable = block_proto_set.by_id(face_key_id(current_block_id)).tile;
+            let mut hit_wall_y = !mergeable;
+            let mut hit_wall_x = !mergeable;
             
             // grow the block
             while !(hit_wall_x && hit_wall_y) {
                 if !hit_wall_x {
                     x2 += 1;
 
-                    if x2 == slice.shape()[0]  // if you hit the edge of the chunk
-                        || (y1..=y2).any(|y| squares.iter().any(|sq| in_square(x2, y, sq))) // if you hit a square we already built
+                    if x2 == width  // if you hit the edge of the chunk
+                        || (y1..=y2).any(|y| in_square(&churched, height, x2, y)) // if you hit a square we already built
                     {
                         hit_wall_x = true;
                     }
                     else
                     {  // if you hit a different block id (hole = 0)
-                        let new_x_sliver: ArrayView::<BlockID, Ix1> = slice.slice(s![x2, y1..=y2]);
+                        let new_x_sliver: ArrayView::<FaceKey, Ix1> = slice.slice(s![x2, y1..=y2]);
                         hit_wall_x = new_x_sliver.iter().any(|v| *v != current_block_id);
                     }
 
@@ -255,14 +556,14 @@ mod tessellate {
                 if !hit_wall_y {
                     y2 += 1;
 
-                    if y2 == slice.shape()[1]
-                        || (x1..=x2).any(|x| squares.iter().any(|sq| in_square(x, y2, sq)))
+                    if y2 == height
+                        || (x1..=x2).any(|x| in_square(&churched, height, x, y2))
                     {
                         hit_wall_y = true;
                     }
                     else
                     {
-                        let new_y_sliver: ArrayView::<BlockID, Ix1> = slice.slice(s![x1..=x2, y2]);
+                        let new_y_sliver: ArrayView::<FaceKey, Ix1> = slice.slice(s![x1..=x2, y2]);
                         hit_wall_y = new_y_sliver.iter().any(|v| *v != current_block_id);
                     }
 
@@ -270,14 +571,19 @@ mod tessellate {
                 }
             }
 
+            for x in x1..=x2 {
+                for y in y1..=y2 {
+                    church(&mut churched, height, x, y);
+                }
+            }
             squares.push((x1, y1, x2+1, y2+1, current_block_id));
         }
-        squares
     }
 
-    pub fn squares_to_vertices(squares: &Vec<(usize, usize, usize, usize, BlockID)>, offset: glam::Vec3A, facing: Facing, block_proto_set: &BlockProtoSet) -> Vec<Vertex> {
+    // appends to `vertices`
+    // each square's quad goes into the vec for its block's render layer
+    pub fn squares_to_vertices(squares: &[Square], offset: glam::Vec3A, facing: Facing, block_proto_set: &BlockProtoSet, vertices: &mut LayerVertices) {
         use glam::Vec3A;
-        let mut vertices: Vec<Vertex> = Vec::with_capacity(4*squares.len());
 
         // basis vectors for the subspace :)
         let e1 = match facing {
@@ -291,82 +597,302 @@ mod tessellate {
             _ => Vec3A::Z,
         };
 
-        // to bottom left of chunk
-        let offset = match facing {
-            Facing::N => offset + Vec3A::Y,
-            Facing::E => offset + Vec3A::X,
-            Facing::U => offset + Vec3A::Z,
-            _ => offset,
-        };
+        // to bottom left of chunk. faces pointing along +axis sit on the far side of their block
+        let offset = offset + Vec3A::from(facing.normal()).max(Vec3A::ZERO);
+
+        // positions are chunk-local, so they're small non-negative integers and pack losslessly
+        fn to_packed_pos(v: Vec3A) -> [u32; 3] {
+            [v.x as u32, v.y as u32, v.z as u32]
+        }
+
+        // e1 points to the viewer's left on N, W and D when looking at the face from outside,
+        // so those faces walk their corners the other way round (for ccw winding) and measure u from the far edge
+        let flipped = matches!(facing, Facing::N | Facing::W | Facing::D);
+        let shade = facing.ambient_shade();
 
         for sq in squares {
-            let verts_raw = [
-                (e1 * sq.0 as f32 + e2 * sq.3 as f32 + offset).to_array(),
-                (e1 * sq.0 as f32 + e2 * sq.1 as f32 + offset).to_array(),
-                (e1 * sq.2 as f32 + e2 * sq.1 as f32 + offset).to_array(),
-                (e1 * sq.2 as f32 + e2 * sq.3 as f32 + offset).to_array(),
-            ];
-
-            let w = (sq.2 - sq.0) as f32;
-            let h = (sq.3 - sq.1) as f32;
-            let uvs_raw = [
-                [0.0, 0.0],
-                [0.0, h],
-                [w, h],
-                [w, 0.0],
-            ];
+            let (a1, b1, a2, b2) = (sq.0 as u32, sq.1 as u32, sq.2 as u32, sq.3 as u32);
+
+            // corners in face space (along e1, along e2), counterclockwise as seen from outside
+            let corners = if flipped {
+                [(a2, b2), (a2, b1), (a1, b1), (a1, b2)]
+            } else {
+                [(a1, b2), (a1, b1), (a2, b1), (a2, b2)]
+            };
 
             // sq.4 will never be 0 because the mesher ignores blocks with id 0
-            let tex_id = block_proto_set.get_tex_id(sq.4 as BlockID, facing.clone());
+            let block_id = face_key_id(sq.4);
+            let tex_id = block_proto_set.get_tex_id(block_id, facing, face_key_orientation(sq.4));
+            let block = block_proto_set.by_id(block_id);
+            let tile = block.tile;
             //let tex_id = (rand::random::<f64>()*5.0+1.0) as u32;
 
-            let face = (match facing {
-                // fix normals and uvs by changing the order of the vertices, which are flipped for NWD
-                Facing::N | Facing::W => [(3, 0), (2, 1), (1, 2), (0, 3)],
-                Facing::D =>             [(3, 2), (2, 3), (1, 0), (0, 1)],
-                _ =>                     [(0, 0), (1, 1), (2, 2), (3, 3)]
-            }).map(|(vti, uvi)|
-                Vertex{
-                    pos: verts_raw[vti],
-                    uv: uvs_raw[uvi],
-                    tex_id: tex_id.try_into().unwrap()
+            // uvs come straight from each corner's position on the face: u runs left to right and v top to bottom as seen from outside,
+            // one unit per block so merged quads tile the texture the same way on every face
+            let face = corners.map(|(a, b)| {
+                let u = if flipped { a2 - a } else { a - a1 };
+                let v = b2 - b;
+                // stretched faces span exactly one copy of the texture
+                let (u, v) = if tile { (u, v) } else { (u.min(1), v.min(1)) };
+                Vertex::new(to_packed_pos(e1 * a as f32 + e2 * b as f32 + offset), facing.clone() as u32, [u, v], tex_id.try_into().unwrap())
+                    .with_shade(shade)
+                    .with_light(face_key_light(sq.4))
+            });
+
+            vertices[block.render_layer as usize].extend(face);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn face_key_round_trip() {
+        for id in [1, 2, 1234, BlockID::MAX] {
+            for orientation in Facing::all() {
+                for light in 0..=MAX_LIGHT as u8 {
+                    let key = face_key_with_light(face_key(id, orientation as u8), light);
+                    assert_eq!(face_key_id(key), id);
+                    assert_eq!(face_key_orientation(key), orientation);
+                    assert_eq!(face_key_light(key), light as u32);
+                }
+            }
+        }
+    }
+
+    fn quad_uvs(square: tessellate::Square, facing: Facing) -> Vec<[u32; 2]> {
+        let block_proto_set = BlockProtoSet::builtin().unwrap();
+        let mut verts = LayerVertices::default();
+        tessellate::squares_to_vertices(&[square], glam::Vec3A::ZERO, facing, &block_proto_set, &mut verts);
+        verts[RenderLayer::Opaque as usize].iter().map(|v| v.uv()).collect()
+    }
+
+    #[test]
+    fn merged_quad_uvs_tile_per_block() {
+        let stone = face_key(2, Facing::U as u8);
+        assert_eq!(quad_uvs((0, 0, 1, 1, stone), Facing::N), vec![[0, 0], [0, 1], [1, 1], [1, 0]]);
+        // twice as wide, so u runs to 2 and the texture repeats
+        assert_eq!(quad_uvs((0, 0, 2, 1, stone), Facing::N), vec![[0, 0], [0, 1], [2, 1], [2, 0]]);
+    }
+
+    fn tessellate(slice: &Array2<FaceKey>) -> Vec<tessellate::Square> {
+        let block_proto_set = BlockProtoSet::builtin().unwrap();
+        let mut squares = vec![];
+        tessellate::tessellate_slice(slice.view(), &block_proto_set, &mut squares);
+        squares
+    }
+
+    // every solid cell covered exactly once, by a square of its own key
+    fn assert_covers_exactly(slice: &Array2<FaceKey>, squares: &[tessellate::Square]) {
+        let mut covered = Array2::<u32>::zeros(slice.raw_dim());
+        for &(x1, y1, x2, y2, key) in squares {
+            assert_ne!(key, 0);
+            for x in x1..x2 {
+                for y in y1..y2 {
+                    assert_eq!(slice[(x, y)], key);
+                    covered[(x, y)] += 1;
                 }
-            );
+            }
+        }
+        ndarray::Zip::from(slice).and(&covered).for_each(|&key, &n| assert_eq!(n, if key == 0 { 0 } else { 1 }));
+    }
+
+    #[test]
+    fn tessellate_slice_output_is_unchanged() {
+        let stone = face_key(2, Facing::U as u8);
+        let mut slice = Array2::from_elem((CHUNK_SIZE, CHUNK_SIZE), stone);
+        slice[(7, 0)] = 0;
+        assert_eq!(tessellate(&slice), vec![(0, 0, 7, 16, stone), (8, 0, 16, 16, stone), (7, 1, 8, 16, stone)]);
+    }
 
-            //println!("{} {}", offset, tex_id);
-            //panic!();
+    #[test]
+    fn tessellate_slice_covers_mixed_slice() {
+        let slice = Array2::from_shape_fn((CHUNK_SIZE, CHUNK_SIZE), |(x, y)| {
+            if (x * x + y * 3) % 7 == 0 { 0 } else { face_key(1 + ((x / 3 + y / 5) % 3) as BlockID, Facing::U as u8) }
+        });
+        assert_covers_exactly(&slice, &tessellate(&slice));
+    }
+
+    // CHUNK_SIZE is a const, so other sizes are tried out on the tessellator directly
+    #[test]
+    fn tessellate_other_chunk_sizes() {
+        let block_proto_set = BlockProtoSet::builtin().unwrap();
+        let stone = face_key(2, Facing::U as u8);
+        for size in [8, 32] {
+            let full = Array2::from_elem((size, size), stone);
+            let squares = tessellate(&full);
+            assert_eq!(squares, vec![(0, 0, size, size, stone)]);
+
+            let mut verts = LayerVertices::default();
+            let top = glam::Vec3A::new(0.0, 0.0, (size - 1) as f32);
+            tessellate::squares_to_vertices(&squares, top, Facing::U, &block_proto_set, &mut verts);
+            let corners = verts[RenderLayer::Opaque as usize].iter().map(|v| v.pos()).collect::<Vec<_>>();
+            let size = size as u32;
+            assert_eq!(corners.len(), 4);
+            for corner in [[0, 0, size], [size, 0, size], [size, size, size], [0, size, size]] {
+                assert!(corners.contains(&corner), "{:?} missing from {:?}", corner, corners);
+            }
 
-            // W, N, D have flipped UVs
-            vertices.extend(face);
+            let mixed = Array2::from_shape_fn((size as usize, size as usize), |(x, y)| {
+                if (x + y) % 5 == 0 { 0 } else { face_key(1 + ((x / 2 + y / 3) % 4) as BlockID, Facing::U as u8) }
+            });
+            assert_covers_exactly(&mixed, &tessellate(&mixed));
         }
+    }
 
-        vertices
+    #[test]
+    fn quads_wind_ccw_around_their_normal() {
+        let block_proto_set = BlockProtoSet::builtin().unwrap();
+        let stone = face_key(2, Facing::U as u8);
+        for facing in Facing::all() {
+            let mut verts = LayerVertices::default();
+            tessellate::squares_to_vertices(&[(0, 0, 2, 1, stone)], glam::Vec3A::ZERO, facing, &block_proto_set, &mut verts);
+            let corners = verts[RenderLayer::Opaque as usize].iter().map(|v| Vec3::from(v.pos().map(|c| c as f32))).collect::<Vec<_>>();
+            // both triangles get_indices makes out of the quad
+            for [a, b, c] in [[0, 1, 2], [2, 3, 0]] {
+                let winding = (corners[b] - corners[a]).cross(corners[c] - corners[a]).normalize();
+                assert_eq!(winding, facing.normal(), "{:?} winds the wrong way", facing);
+            }
+        }
+    }
+
+    fn solid_below(height: usize) -> Chunk {
+        let mut chunk = Chunk::new(0.0, 0.0, 0.0);
+        for x in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..height {
+                    chunk.set_block_id_at(Vec3::new(x as f32, y as f32, z as f32), 2);
+                }
+            }
+        }
+        chunk
     }
-}
 
+    // flat terrain is where merging pays off: one quad per side no matter how many blocks are behind it
+    #[test]
+    fn solid_and_flat_chunks_mesh_to_one_quad_per_side() {
+        let block_proto_set = BlockProtoSet::builtin().unwrap();
+        let tp = rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap();
+        for height in [CHUNK_SIZE, 4] {
+            let mut chunk = solid_below(height);
+            chunk.make_mesh(&block_proto_set, &tp);
+            assert_eq!(chunk.mesh.len(), 24, "{} blocks deep", height);
+            assert_eq!(chunk.layer_quads, [6, 0, 0]);
+        }
+    }
 
+    #[test]
+    fn content_hash_follows_the_blocks() {
+        let mut chunk = Chunk::new(0.0, 0.0, 0.0);
+        let empty = chunk.content_hash();
+        let pos = Vec3::new(3.0, 4.0, 5.0);
+
+        chunk.set_block_id_at(pos, 2);
+        let stone = chunk.content_hash();
+        assert_ne!(stone, empty);
+        // orientation counts too
+        chunk.set_block_at(pos, 2, Facing::N);
+        assert_ne!(chunk.content_hash(), stone);
+
+        chunk.set_block_id_at(pos, 0);
+        assert_eq!(chunk.content_hash(), empty);
+        // and where the chunk is doesn't
+        assert_eq!(Chunk::new(16.0, -32.0, 48.0).content_hash(), empty);
+    }
 
+    #[test]
+    fn glowing_blocks_light_up_sealed_caves() {
+        let toml = r#"
+            [[blocks]]
+            name = "Lamp"
+            textures = ["gold_block.png"]
+            light_emission = 12
+
+            [[blocks]]
+            name = "Stone"
+            textures = ["stone.png"]
+        "#;
+        let block_proto_set = BlockProtoSet::from_toml_str(toml, "test").unwrap();
+        // a tunnel along x, walled off from the sky
+        let mut chunk = solid_below(CHUNK_SIZE);
+        for x in 2..12 {
+            chunk.set_block_id_at(Vec3::new(x as f32, 8.0, 8.0), 0);
+        }
+        let light_at = |chunk: &Chunk, x: usize| Chunk::get_view(&chunk.light_array)[(x, 8, 8)];
 
-//#[test]
-/*
-fn main() {
-    load_from_toml("config/blocks.toml");
-    //let a: Array::<u32, Dim> = Array::<u32, _>::zeros((CHUNK_SIZE, CHUNK_SIZE, CHUNK_SIZE,).f()).dimension;
-    let mut c = Chunk::new(0.0, 0.0, 0.0);
-    c.ids.slice_mut(s![.., .., 0]).fill(1u32);
-    c.ids[(7, 0, 0)] = 0u32;
-    c.ids[(4, 6, 0)] = 0u32;
-    c.ids[(14, 12, 0)] = 2u32;
-    let slc = c.ids.slice(s![.., .., 0]);
+        chunk.compute_light(&block_proto_set);
+        assert!((2..12).all(|x| light_at(&chunk, x) == 0));
 
-    use std::time::Instant;
-    let now = Instant::now();
+        chunk.set_block_id_at(Vec3::new(2.0, 8.0, 8.0), 1);
+        chunk.compute_light(&block_proto_set);
+        assert_eq!(light_at(&chunk, 2), 12);
+        // one dimmer per block out from the lamp
+        for x in 3..12 {
+            assert_eq!(light_at(&chunk, x), 12 - (x - 2) as u8);
+        }
+    }
 
-    let t = tessellate::tessellate_slice(slc);
-    //println!("{}/9", t.len());
+    // a bit of everything: long runs, single blocks, and the same id in different orientations
+    fn mixed_chunk() -> Chunk {
+        let mut chunk = solid_below(5);
+        chunk.set_block_id_at(Vec3::new(0.0, 0.0, 0.0), 1);
+        chunk.set_block_id_at(Vec3::new(3.0, 9.0, 2.0), 7);
+        chunk.set_block_at(Vec3::new(4.0, 9.0, 2.0), 2, Facing::N);
+        chunk.set_block_at(Vec3::new(15.0, 15.0, 15.0), 9, Facing::D);
+        chunk.set_block_id_at(Vec3::new(8.0, 8.0, 4.0), 0);
+        chunk
+    }
 
-    let elapsed = now.elapsed().mul_f32(16.0*6.0);
-    println!("Elapsed: {:.2?}", elapsed);
+    #[test]
+    fn save_format_round_trip() {
+        let chunk = mixed_chunk();
+        let bytes = chunk.serialize();
+        assert_eq!(bytes[0], SAVE_FORMAT_VERSION);
+        assert_eq!((bytes.len() - 1) % SAVE_RUN_BYTES, 0);
+        // under a byte a block, against three without the runs
+        assert!(bytes.len() < CHUNK_VOLUME, "{} bytes", bytes.len());
+
+        let loaded = Chunk::deserialize(&bytes, 16.0, -32.0, 0.0).unwrap();
+        assert_eq!(loaded.pos, Vec3::new(16.0, -32.0, 0.0));
+        assert_eq!(loaded.ids_array, chunk.ids_array);
+        assert_eq!(loaded.orientation_array, chunk.orientation_array);
+        assert_eq!(loaded.get_orientation_at(Vec3::new(20.0, -23.0, 2.0)), Facing::N);
+        assert_eq!(loaded.serialize(), bytes);
+    }
 
-    println!("{:?}", t);
-}*/
\ No newline at end of file
+    #[test]
+    fn empty_chunk_saves_as_one_run() {
+        let bytes = Chunk::new(0.0, 0.0, 0.0).serialize();
+        assert_eq!(bytes.len(), 1 + SAVE_RUN_BYTES);
+        assert!(Chunk::deserialize(&bytes, 0.0, 0.0, 0.0).unwrap().is_all_air());
+    }
+
+    #[test]
+    fn other_save_versions_are_refused() {
+        let mut bytes = mixed_chunk().serialize();
+        bytes[0] = SAVE_FORMAT_VERSION + 1;
+        assert!(matches!(Chunk::deserialize(&bytes, 0.0, 0.0, 0.0), Err(ChunkDecodeError::UnknownVersion(v)) if v == SAVE_FORMAT_VERSION + 1));
+    }
+
+    #[test]
+    fn broken_saves_are_refused() {
+        let bytes = mixed_chunk().serialize();
+        assert!(matches!(Chunk::deserialize(&[], 0.0, 0.0, 0.0), Err(ChunkDecodeError::Truncated)));
+        assert!(matches!(Chunk::deserialize(&bytes[..bytes.len() - 3], 0.0, 0.0, 0.0), Err(ChunkDecodeError::Truncated)));
+        // a whole run missing off the end
+        assert!(matches!(Chunk::deserialize(&bytes[..bytes.len() - SAVE_RUN_BYTES], 0.0, 0.0, 0.0), Err(ChunkDecodeError::WrongBlockCount(n)) if n < CHUNK_VOLUME));
+
+        let mut bad_orientation = bytes.clone();
+        bad_orientation[SAVE_RUN_BYTES] = 6;
+        assert!(matches!(Chunk::deserialize(&bad_orientation, 0.0, 0.0, 0.0), Err(ChunkDecodeError::BadOrientation(6))));
+    }
+
+    #[test]
+    fn air_face_key_is_zero() {
+        for orientation in Facing::all() {
+            assert_eq!(face_key(0, orientation as u8), 0);
+        }
+    }
+}
@@ -7,10 +7,71 @@ use std::alloc::{alloc, dealloc, Layout, handle_alloc_error, alloc_zeroed};
 use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::mem::{size_of, align_of};
 use crate::block;
+use crate::block::BlockID;
+use crate::geometry::Facing;
+use crate::terrain::Terrain;
+use crate::save;
+use std::path::Path;
+use std::collections::HashMap;
+use std::collections::BinaryHeap;
+use std::cmp::Reverse;
 
 
 pub type ChunkCoord = (isize, isize, isize);
 
+// a block change aimed at a chunk that isn't loaded yet. pos is in world coords
+#[derive(Clone, Copy, Debug)]
+pub struct PendingEdit {
+    pub pos: Vec3,
+    pub id: BlockID,
+    pub orientation: Facing,
+}
+
+// squared, since it's only ever compared
+pub fn chunk_distance_squared(a: ChunkCoord, b: ChunkCoord) -> isize {
+    let (dx, dy, dz) = (a.0 - b.0, a.1 - b.1, a.2 - b.2);
+    dx*dx + dy*dy + dz*dz
+}
+
+// chunk coords, nearest to `center` first, so the world fills in around the player instead of in the order it was asked for.
+// priorities are worked out when a coord is pushed, so call reprioritize when the center moves
+pub struct ChunkQueue {
+    heap: BinaryHeap<Reverse<(isize, ChunkCoord)>>,
+    center: ChunkCoord,
+}
+
+impl ChunkQueue {
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            center: (0, 0, 0),
+        }
+    }
+
+    pub fn push(&mut self, chunk_coord: ChunkCoord) {
+        self.heap.push(Reverse((chunk_distance_squared(chunk_coord, self.center), chunk_coord)));
+    }
+
+    pub fn pop(&mut self) -> Option<ChunkCoord> {
+        self.heap.pop().map(|Reverse((_, chunk_coord))| chunk_coord)
+    }
+
+    pub fn reprioritize(&mut self, center: ChunkCoord) {
+        if center == self.center {
+            return;
+        }
+        self.center = center;
+        let queued = std::mem::take(&mut self.heap);
+        for Reverse((_, chunk_coord)) in queued {
+            self.push(chunk_coord);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+}
+
 pub struct ChunkSet {
     chunks: MemoryBlock<RwLock<Chunk>>,
     pub center: ChunkCoord,
@@ -18,6 +79,8 @@ pub struct ChunkSet {
     pub arr_length: usize,
     arr_area: usize,
     arr_vol: usize,
+    // applied on top of the generated terrain when the chunk loads
+    pending_edits: HashMap<ChunkCoord, Vec<PendingEdit>>,
 }
 
 impl ChunkSet {
@@ -32,35 +95,94 @@ impl ChunkSet {
             arr_length,
             arr_area,
             arr_vol,
+            pending_edits: HashMap::new(),
         }
     }
     pub fn recenter(&mut self, center: ChunkCoord) {
         self.center = center;
     }
 
-    pub fn generate_chunk(&mut self, chunk_coord: ChunkCoord, tp: &rayon::ThreadPool, block_proto_set: &block::BlockProtoSet, device: &wgpu::Device) {
-        let i = self.arr_index_to_real_index(self.chunk_coord_to_arr_index(chunk_coord));
-        unsafe {
-            if self.chunks.is_allocated(i) {self.chunks.drop(i);}
-            self.chunks.write(i, RwLock::new(Chunk::new(
+    // resize the ring buffer. every slot moves since indices depend on its size; chunks still in range are carried over
+    // and the rest are dropped, which frees them. pending edits are kept either way
+    pub fn set_render_distance(&mut self, render_distance: usize) {
+        let mut resized = Self::new(self.center, render_distance);
+        resized.pending_edits = std::mem::take(&mut self.pending_edits);
+        for i in 0..self.arr_vol {
+            unsafe {
+                if !self.chunks.is_allocated(i) {
+                    continue;
+                }
+                let mut chunk = self.chunks.take(i);
+                let chunk_coord = self.world_to_chunk_coords(chunk.get_mut().unwrap().pos);
+                if resized.check_in_bounds(chunk_coord) {
+                    let j = resized.chunk_coord_to_real_index(chunk_coord);
+                    resized.chunks.write(j, chunk);
+                }
+            }
+        }
+        *self = resized;
+    }
+
+    // hold onto an edit until its chunk is generated
+    pub fn queue_edit(&mut self, edit: PendingEdit) {
+        let chunk_coord = self.world_to_chunk_coords(edit.pos);
+        self.pending_edits.entry(chunk_coord).or_default().push(edit);
+    }
+    pub fn pending_edit_count(&self) -> usize {
+        self.pending_edits.values().map(|edits| edits.len()).sum()
+    }
+
+    // everything held for this chunk so far. the edits are applied in order, so later edits to the same block win
+    pub fn take_pending_edits(&mut self, chunk_coord: ChunkCoord) -> Vec<PendingEdit> {
+        self.pending_edits.remove(&chunk_coord).unwrap_or_default()
+    }
+
+    // loads (from save_dir) or generates, then meshes on the cpu; the mesh goes to the gpu later in World::upload_meshes.
+    // doesn't touch the set, so it can run on any thread. put the result in with insert_chunk
+    // `void_floor` is the height of the bedrock floor under the world, if there is one
+    pub fn generate_chunk(chunk_coord: ChunkCoord, tp: &rayon::ThreadPool, block_proto_set: &block::BlockProtoSet, terrain: &Terrain, seed: u32, void_floor: Option<f32>, save_dir: &Path, edits: Vec<PendingEdit>) -> Chunk {
+        let saved = save::load_chunk(save_dir, chunk_coord).unwrap_or_else(|e| {
+            // a broken save only costs that chunk's edits, the terrain comes back the same
+            log::error!("{}, regenerating it", e);
+            None
+        });
+        let mut chunk = saved.unwrap_or_else(|| {
+            let mut chunk = Chunk::new(
                 chunk_coord.0 as f32 * CHUNK_SIZE_F,
                 chunk_coord.1 as f32 * CHUNK_SIZE_F,
                 chunk_coord.2 as f32 * CHUNK_SIZE_F
-            )));
-        }
-        let lock = unsafe{self.chunks.read(i)};
-        tp.install(||{
-            let mut chunk = lock.write().unwrap();
-            chunk.generate_planet();
-            chunk.make_mesh(block_proto_set, tp);
-            chunk.make_vertex_buffer(device);
-            chunk.ready_to_display = true;
-            drop(chunk);
+            );
+            chunk.generate_planet(terrain);
+            chunk.generate_ores(block_proto_set, seed);
+            if let Some(z) = void_floor {
+                chunk.generate_void_floor(z);
+            }
+            chunk
         });
+        for edit in edits {
+            chunk.set_block_at(edit.pos, edit.id, edit.orientation);
+        }
+        chunk.make_mesh(block_proto_set, tp);
+        chunk
     }
+
+    // replaces whatever was in the chunk's slot
+    pub fn insert_chunk(&mut self, chunk_coord: ChunkCoord, chunk: Chunk) {
+        let i = self.chunk_coord_to_real_index(chunk_coord);
+        unsafe {
+            if self.chunks.is_allocated(i) {self.chunks.drop(i);}
+            self.chunks.write(i, RwLock::new(chunk));
+        }
+    }
+    // frees the slot. this takes &mut self, so nothing can be drawing from it at the time, and iter() skips freed slots after.
+    // the chunk is usually out of bounds by now, which is why this goes straight to the slot instead of get_chunk_at_chunk_coords
     pub fn mark_unloaded(&mut self, chunk_coord: ChunkCoord) {
-        //self.get_chunk_at_chunk_coords(chunk_coord).unwrap().write().unwrap().ready_to_display = false;
-        unsafe {self.chunks.drop(self.arr_index_to_real_index(self.chunk_coord_to_arr_index(chunk_coord)));}
+        let i = self.chunk_coord_to_real_index(chunk_coord);
+        unsafe {
+            if self.chunks.is_allocated(i) {
+                self.chunks.drop(i);
+            }
+        }
     }
     pub fn is_unloaded(&self, chunk_coord: ChunkCoord) -> bool {
         unsafe {!self.chunks.is_allocated(self.chunk_coord_to_real_index(chunk_coord))}
@@ -74,7 +196,6 @@ impl ChunkSet {
         )
     }
     fn chunk_coord_to_arr_index(&self, coord: ChunkCoord) -> (usize, usize, usize) {
-        //println!("{:?} {}", coord, self.arr_length);
         (
             coord.0.rem_euclid(self.arr_length as isize).try_into().unwrap(),
             coord.1.rem_euclid(self.arr_length as isize).try_into().unwrap(),
@@ -110,8 +231,145 @@ impl ChunkSet {
         self.get_chunk_at_chunk_coords(c)
     }
 
+    pub fn loaded_count(&self) -> usize {
+        self.chunks.count_allocated()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = &RwLock<Chunk>> {
         self.chunks.iter()
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::CHUNK_SIZE;
+    use crate::settings::TerrainSettings;
+
+    fn thread_pool() -> rayon::ThreadPool {
+        rayon::ThreadPoolBuilder::new().num_threads(2).build().unwrap()
+    }
+
+    // somewhere nothing was ever saved, so generate_chunk always generates. never created, only read from
+    fn no_saves() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("voxelgame-test-no-saves-{}", std::process::id()))
+    }
+
+    fn generate(chunk_coord: ChunkCoord, block_proto_set: &block::BlockProtoSet) -> Chunk {
+        let terrain = Terrain::new(1, TerrainSettings::default());
+        ChunkSet::generate_chunk(chunk_coord, &thread_pool(), block_proto_set, &terrain, 1, None, &no_saves(), vec![])
+    }
+
+    #[test]
+    fn single_block_mesh() {
+        let block_proto_set = block::BlockProtoSet::builtin().unwrap();
+        let mut chunk = Chunk::new(0.0, 0.0, 0.0);
+        chunk.set_block_id_at(Vec3::new(5.0, 5.0, 5.0), 2);
+        chunk.make_mesh(&block_proto_set, &thread_pool());
+        assert_eq!(chunk.mesh.len(), 24);
+        assert_eq!(chunk.get_indices(0).len(), 36);
+        assert_eq!(chunk.layer_quads, [6, 0, 0]);
+    }
+
+    #[test]
+    fn generated_sky_chunk_is_empty() {
+        let block_proto_set = block::BlockProtoSet::builtin().unwrap();
+        // the hills top out at amplitude (16) blocks above sea level
+        let chunk = generate((0, 0, 2), &block_proto_set);
+        assert!(chunk.is_all_air());
+        assert!(chunk.mesh.is_empty());
+        assert!(chunk.get_indices(0).is_empty());
+    }
+
+    #[test]
+    fn generated_underground_chunk_only_shows_its_outside() {
+        let block_proto_set = block::BlockProtoSet::builtin().unwrap();
+        // entirely below the lowest valley, so solid stone (and ore) all the way through
+        let chunk = generate((0, 0, -3), &block_proto_set);
+        assert_eq!(chunk.mesh.len() % 4, 0);
+        assert_eq!(chunk.get_indices(0).len(), chunk.mesh.len() / 4 * 6);
+        assert_eq!(chunk.layer_quads.iter().sum::<u32>() as usize * 4, chunk.mesh.len());
+
+        // the edges of the chunk always show for now, and nothing inside does
+        let mut area = 0;
+        for quad in chunk.mesh.chunks(4) {
+            let axis = Facing::from_index(quad[0].face() as u8).normal().abs().to_array().iter().position(|&n| n == 1.0).unwrap();
+            let plane = quad[0].pos()[axis];
+            assert!(plane == 0 || plane == CHUNK_SIZE as u32);
+            assert!(quad.iter().all(|v| v.pos()[axis] == plane));
+            let extent = |i: usize| quad.iter().map(|v| v.pos()[i]).max().unwrap() - quad.iter().map(|v| v.pos()[i]).min().unwrap();
+            area += (0..3).filter(|&i| i != axis).map(extent).product::<u32>();
+        }
+        assert_eq!(area as usize, 6 * CHUNK_SIZE * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn arr_index_wraps_negative_coords() {
+        let chunk_set = ChunkSet::new((0, 0, 0), 2);
+        assert_eq!(chunk_set.chunk_coord_to_arr_index((0, 0, 0)), (0, 0, 0));
+        assert_eq!(chunk_set.chunk_coord_to_arr_index((-1, -2, -5)), (4, 3, 0));
+        assert_eq!(chunk_set.chunk_coord_to_arr_index((-6, 7, -11)), (4, 2, 4));
+        for c in -12..12 {
+            let index = chunk_set.chunk_coord_to_arr_index((c, c, c));
+            assert!(index.0 < chunk_set.arr_length);
+            assert_eq!(index.0, index.1);
+            assert_eq!(index.1, index.2);
+        }
+    }
+
+    #[test]
+    fn coords_a_ring_apart_share_a_slot() {
+        let chunk_set = ChunkSet::new((0, 0, 0), 2);
+        let n = chunk_set.arr_length as isize;
+        for coord in [(0, 0, 0), (-1, 3, -2), (2, -2, 1)] {
+            let i = chunk_set.chunk_coord_to_real_index(coord);
+            assert_eq!(chunk_set.chunk_coord_to_real_index((coord.0 + n, coord.1, coord.2)), i);
+            assert_eq!(chunk_set.chunk_coord_to_real_index((coord.0, coord.1 - n, coord.2)), i);
+            assert_eq!(chunk_set.chunk_coord_to_real_index((coord.0 - 3*n, coord.1 + n, coord.2 + 2*n)), i);
+            assert_ne!(chunk_set.chunk_coord_to_real_index((coord.0 + 1, coord.1, coord.2)), i);
+        }
+    }
+
+    fn coords_around(center: ChunkCoord, render_distance: isize) -> Vec<ChunkCoord> {
+        let mut coords = vec![];
+        for x in center.0 - render_distance..=center.0 + render_distance {
+            for y in center.1 - render_distance..=center.1 + render_distance {
+                for z in center.2 - render_distance..=center.2 + render_distance {
+                    coords.push((x, y, z));
+                }
+            }
+        }
+        coords
+    }
+
+    // lookups go straight to a slot, so every coord in range needs one of its own
+    #[test]
+    fn every_coord_in_range_gets_its_own_slot() {
+        let chunk_set = ChunkSet::new((3, -20, 7), 6);
+        let slots = coords_around(chunk_set.center, chunk_set.render_distance).into_iter()
+            .map(|coord| chunk_set.chunk_coord_to_real_index(coord))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(slots.len(), chunk_set.arr_vol);
+        assert!(slots.iter().all(|&i| i < chunk_set.arr_vol));
+    }
+
+    #[test]
+    fn lookups_find_the_chunk_at_that_coord() {
+        let center = (-2, 5, 1);
+        let mut chunk_set = ChunkSet::new(center, 3);
+        for coord in coords_around(center, 3) {
+            chunk_set.insert_chunk(coord, Chunk::new(coord.0 as f32 * CHUNK_SIZE_F, coord.1 as f32 * CHUNK_SIZE_F, coord.2 as f32 * CHUNK_SIZE_F));
+        }
+        assert_eq!(chunk_set.loaded_count(), chunk_set.arr_vol);
+        for coord in coords_around(center, 3) {
+            let pos = chunk_set.get_chunk_at_chunk_coords(coord).unwrap().read().unwrap().pos;
+            assert_eq!(chunk_set.world_to_chunk_coords(pos), coord);
+            let inside = pos + Vec3::splat(CHUNK_SIZE_F - 0.5);
+            assert_eq!(chunk_set.get_chunk_at_world_coords(inside).unwrap().read().unwrap().pos, pos);
+        }
+        // one past the edge shares a slot with a loaded chunk, but isn't it
+        assert!(chunk_set.get_chunk_at_chunk_coords((center.0 + 4, center.1, center.2)).is_none());
+        assert!(chunk_set.get_chunk_at_chunk_coords((center.0, center.1 - 4, center.2)).is_none());
+    }
+}
@@ -1,15 +1,34 @@
 use std::time::{Duration, SystemTime};
+
+// most sim ticks run in one frame; after a long stall the rest are dropped instead of trying to catch up all at once
+const MAX_SIM_TICKS_PER_FRAME: u32 = 10;
+// same for physics steps. a frame slower than this many steps (1/15s at 120/s) runs the player in slow motion rather than
+// spending even longer on physics next frame and falling further behind
+const MAX_PHYSICS_STEPS_PER_FRAME: u32 = 8;
+
+// `tick` is once per rendered frame. the world also steps at a fixed rate (sim ticks) that doesn't care about the frame rate
 pub struct Clock {
     pub tick: u64, // ticks since clock creation
     pub time: f32, // seconds since clock creation, updated every tick
-    pub tps: f32, // ticks per second, updated every tps_update_interval to take a substantial average (0.5s by default)
+    // ticks are frames, so this is the frame rate. updated every fps_update_interval to take a substantial average (0.5s by default)
+    pub fps: f32,
+    pub frame_time_ms: f32, // the average frame over the same interval, which is easier to reason about than fps
 
     pub tick_time: f32, // time between this tick and the last
     time_at_last_tick: f32,
 
-    tick_at_last_tps_update: u64,
-    time_at_last_tps_update: f32,
-    tps_update_interval: f32,
+    tick_at_last_fps_update: u64,
+    time_at_last_fps_update: f32,
+    fps_update_interval: f32,
+
+    pub sim_tick: u64, // sim ticks since clock creation
+    pub sim_tps: f32,
+    // time that hasn't been spent on sim ticks yet
+    sim_accumulator: f32,
+
+    // physics steps at its own fixed rate, much faster than sim ticks, so jumps and collisions come out the same at any frame rate
+    pub physics_tps: f32,
+    physics_accumulator: f32,
 
     start_time: SystemTime,
 }
@@ -18,14 +37,22 @@ impl Clock {
         Self {
             tick: 0u64,
             time: 0f32,
-            tps: 0f32,
+            fps: 0f32,
+            frame_time_ms: 0f32,
 
             tick_time: 0f32,
             time_at_last_tick: 0f32,
 
-            time_at_last_tps_update: 0f32,
-            tick_at_last_tps_update: 0u64,
-            tps_update_interval: 0.5f32,
+            time_at_last_fps_update: 0f32,
+            tick_at_last_fps_update: 0u64,
+            fps_update_interval: 0.5f32,
+
+            sim_tick: 0u64,
+            sim_tps: 20f32,
+            sim_accumulator: 0f32,
+
+            physics_tps: 120f32,
+            physics_accumulator: 0f32,
 
             start_time: SystemTime::now(),
         }
@@ -36,17 +63,111 @@ impl Clock {
     }
 
     pub fn tick(&mut self) {
-        self.time = Clock::duration_to_s(self.start_time.elapsed().unwrap());
+        self.tick_at(Clock::duration_to_s(self.start_time.elapsed().unwrap()));
+    }
+
+    // tick() with the time given instead of read off the system clock
+    pub fn tick_at(&mut self, time: f32) {
+        self.time = time;
         self.tick += 1;
 
         self.tick_time = self.time - self.time_at_last_tick;
         self.time_at_last_tick = self.time;
+        self.sim_accumulator += self.tick_time;
+        self.physics_accumulator += self.tick_time;
+
+        let time_since_last_fps_update = self.time - self.time_at_last_fps_update;
+        if time_since_last_fps_update >= self.fps_update_interval {
+            let frames = (self.tick - self.tick_at_last_fps_update) as f32;
+            self.fps = frames / time_since_last_fps_update;
+            self.frame_time_ms = time_since_last_fps_update * 1000.0 / frames;
+            self.tick_at_last_fps_update = self.tick;
+            self.time_at_last_fps_update = self.time;
+        }
+    }
+
+    // seconds since the last tick(), e.g. how long this frame has taken so far
+    pub fn time_since_tick(&self) -> f32 {
+        Clock::duration_to_s(self.start_time.elapsed().unwrap()) - self.time_at_last_tick
+    }
+
+    pub fn sim_tick_time(&self) -> f32 {
+        1.0 / self.sim_tps
+    }
+
+    // how many sim ticks are owed since the last call. call once a frame, after tick(), and run that many
+    pub fn take_sim_ticks(&mut self) -> u32 {
+        let due = Self::take_steps(&mut self.sim_accumulator, self.sim_tick_time(), MAX_SIM_TICKS_PER_FRAME);
+        self.sim_tick += due as u64;
+        due
+    }
 
-        let time_since_last_tps_update = self.time - self.time_at_last_tps_update;
-        if time_since_last_tps_update >= self.tps_update_interval {
-            self.tps = ((self.tick - self.tick_at_last_tps_update) as f32) / time_since_last_tps_update;
-            self.tick_at_last_tps_update = self.tick;
-            self.time_at_last_tps_update = self.time;
+    pub fn physics_step_time(&self) -> f32 {
+        1.0 / self.physics_tps
+    }
+
+    // like take_sim_ticks, for physics_step. whatever's left over carries into the next frame
+    pub fn take_physics_steps(&mut self) -> u32 {
+        Self::take_steps(&mut self.physics_accumulator, self.physics_step_time(), MAX_PHYSICS_STEPS_PER_FRAME)
+    }
+
+    // whole steps of `dt` out of the accumulator, at most `max`. if that's not enough to catch up the backlog is dropped
+    fn take_steps(accumulator: &mut f32, dt: f32, max: u32) -> u32 {
+        let mut due = 0;
+        while *accumulator >= dt && due < max {
+            *accumulator -= dt;
+            due += 1;
+        }
+        if due == max {
+            *accumulator = accumulator.min(dt);
         }
+        due
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-3, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn fps_averages_over_the_update_interval() {
+        let mut clock = Clock::new();
+        // nothing until half a second has gone by
+        for i in 1..30 {
+            clock.tick_at(i as f32 / 60.0);
+            assert_eq!(clock.fps, 0.0);
+        }
+        clock.tick_at(0.5);
+        assert_close(clock.fps, 60.0);
+        assert_close(clock.frame_time_ms, 1000.0 / 60.0);
+        assert_close(clock.tick_time, 1.0 / 60.0);
+
+        // one long frame in amongst short ones shows up in the average, not on its own
+        for time in [0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95, 1.0] {
+            clock.tick_at(time);
+        }
+        assert_close(clock.fps, 9.0 / 0.5);
+        assert_close(clock.frame_time_ms, 500.0 / 9.0);
+        assert_eq!(clock.tick, 39);
+    }
+
+    #[test]
+    fn sim_ticks_keep_their_own_rate() {
+        let mut clock = Clock::new();
+        clock.tick_at(0.12);
+        // 20 a second, with the extra 0.02s carried into the next frame
+        assert_eq!(clock.take_sim_ticks(), 2);
+        clock.tick_at(0.16);
+        assert_eq!(clock.take_sim_ticks(), 1);
+        assert_eq!(clock.sim_tick, 3);
+
+        // a long stall only owes so many
+        clock.tick_at(60.0);
+        assert_eq!(clock.take_sim_ticks(), MAX_SIM_TICKS_PER_FRAME);
+        assert_eq!(clock.take_physics_steps(), MAX_PHYSICS_STEPS_PER_FRAME);
+        assert!(clock.take_sim_ticks() <= 1);
+    }
+}
@@ -0,0 +1,19 @@
+// the crosshair, in clip space so it doesn't need the camera
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+const CROSSHAIR_COLOR: vec4<f32> = vec4<f32>(1.0, 1.0, 1.0, 0.8);
+
+@vertex
+fn vs_main(@location(0) position: vec2<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4<f32>(position, 0.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return CROSSHAIR_COLOR;
+}
@@ -2,12 +2,20 @@ extern crate glam;
 
 use crate::block::BlockID;
 use glam::{
-    Mat3, Vec2, Vec3,
+    Vec2, Vec3,
 };
+use std::f32::consts::{PI, TAU};
 
 use crate::world;
+use crate::geometry::Aabb;
+use crate::settings::PlayerSettings;
 
 const DEG_TO_RAD: f32 = 0.0174532925;
+// how far up or down you can look. just shy of straight up, where the look direction and the camera's up would line up
+const MAX_PITCH: f32 = 89.9 * DEG_TO_RAD;
+// how hard you can push yourself around on the ground (or flying) vs. mid-jump
+const GROUND_ACC_RATE: f32 = 150.0;
+const AIR_ACC_RATE: f32 = 10.0;
 
 #[derive(Clone)]
 pub struct DesiredMovement {
@@ -35,20 +43,35 @@ pub struct Entity {
     pub pos: Vec3,
     pub vel: Vec3,
     pub acc: Vec3,
-    pub facing: Vec3,
+    // where the head points, in radians: yaw ccw from +x (east), pitch up from level. the camera and block picking go off
+    // look_dir(), which is rebuilt from these every time so it can't drift away from unit length the way a vector we kept
+    // rotating would
+    pub yaw: f32,
+    pub pitch: f32,
+    // which way the body points in the xy plane, radians ccw from +x (east). walking is relative to this
+    pub body_yaw: f32,
+    // the player's body just turns with its head; mobs turn it off to strafe while looking elsewhere
+    pub body_follows_look: bool,
 
     pub eye_height: f32,
     pub height: f32,
     pub width: f32,
+    // tallest ledge the entity walks up without jumping. 0.5 clears slabs, 1.0 full blocks
+    pub step_height: f32,
 
     pub move_speed: f32,
     pub jump_height: f32,
     pub acc_rate: f32,
+    // horizontal drag per second; top speed works out to acc_rate / friction
+    pub ground_friction: f32,
+    pub air_friction: f32, // only when falling/jumping, flying uses ground_friction for tight control
     pub gravity: f32,
     pub desired_movement: DesiredMovement,
     
     pub flying: bool,
     pub in_air: bool,
+    // ground covered since the last footstep
+    pub step_distance: f32,
 }
 impl Entity {
     pub fn new(pos: glam::f32::Vec3) -> Self {
@@ -56,71 +79,142 @@ impl Entity {
             pos: pos,
             vel: Vec3::new(0.0, 0.0, 0.0),
             acc: Vec3::new(0.0, 0.0, 0.0),
-            facing: Vec3::new(0.0, 1.0, 0.0).normalize(),
+            yaw: 90.0 * DEG_TO_RAD,
+            pitch: 0.0,
+            body_yaw: 90.0 * DEG_TO_RAD,
+            body_follows_look: true,
 
             eye_height: 1.6,
             height: 1.8,
             width: 0.1,
+            step_height: 0.6,
 
             move_speed: 4.3,
             jump_height: 1.3,
             acc_rate: 130.0,
+            ground_friction: GROUND_ACC_RATE/4.3,
+            air_friction: AIR_ACC_RATE/4.3,
             gravity: 9.8*2.5,
             desired_movement: NO_MOVEMENT,
 
             flying: false,
             in_air: true,
+            step_distance: 0.0,
         }
     }
 
+    pub fn apply_settings(&mut self, settings: &PlayerSettings) {
+        self.gravity = settings.gravity;
+        self.jump_height = settings.jump_height;
+        self.set_move_speed(settings.move_speed);
+    }
+
+    // top speed is acc_rate / friction, so friction is what actually sets it
+    pub fn set_move_speed(&mut self, move_speed: f32) {
+        self.move_speed = move_speed;
+        self.ground_friction = GROUND_ACC_RATE / move_speed;
+        self.air_friction = AIR_ACC_RATE / move_speed;
+    }
+
+    // upward speed that gets you exactly jump_height up under this gravity: v^2 = 2gh
+    pub fn jump_impulse(&self) -> f32 {
+        (self.jump_height * self.gravity * 2.0).sqrt()
+    }
+
+    // put the entity down at `pos` as if it just spawned: standing still, looking north
+    pub fn reset_to(&mut self, pos: Vec3) {
+        self.pos = pos;
+        self.vel = Vec3::ZERO;
+        self.acc = Vec3::ZERO;
+        self.yaw = 90.0 * DEG_TO_RAD;
+        self.pitch = 0.0;
+        self.body_yaw = 90.0 * DEG_TO_RAD;
+        self.in_air = true;
+    }
+
+    // the box we collide and get picked with. pos is the middle of the feet
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(
+            self.pos - Vec3::new(self.width, self.width, 0.0),
+            self.pos + Vec3::new(self.width, self.width, self.height),
+        )
+    }
+
+    pub fn eye_pos(&self) -> Vec3 {
+        self.pos + Vec3::Z * self.eye_height
+    }
+
     pub fn get_block_looking_at(&self, world: &world::World) -> (Vec3, Vec3, BlockID) {
-        world.cast_ray_to_first_non_air_block(self.pos + Vec3::Z * self.eye_height, self.facing, 4.0)
+        world.cast_ray_to_first_non_air_block(self.pos + Vec3::Z * self.eye_height, self.look_dir(), 4.0)
+    }
+
+    pub fn look_dir(&self) -> Vec3 {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        Vec3::new(cos_pitch * cos_yaw, cos_pitch * sin_yaw, sin_pitch)
     }
 
     pub fn facing_in_degrees(&self) -> Vec2 {
         Vec2::new(
-            self.facing.z.asin() / DEG_TO_RAD, // vertical
-            self.facing.y.atan2(self.facing.x) / DEG_TO_RAD, // horizontal
+            self.pitch / DEG_TO_RAD, // vertical
+            self.yaw / DEG_TO_RAD, // horizontal
         )
     }
+    pub fn look_yaw(&self) -> f32 {
+        self.yaw
+    }
+    // right of where the head is looking, level with the ground
     pub fn get_rightward_vector(&self) -> Vec3 {
-        self.facing.cross(Vec3::Z).normalize()
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        Vec3::new(sin_yaw, -cos_yaw, 0.0)
+    }
+    // forward and right of the body, level with the ground
+    pub fn body_forward(&self) -> Vec3 {
+        Vec3::new(self.body_yaw.cos(), self.body_yaw.sin(), 0.0)
+    }
+    pub fn body_rightward(&self) -> Vec3 {
+        self.body_forward().cross(Vec3::Z)
     }
 
     pub fn turn_horizontal(&mut self, amount_deg: f32) {
-        // these rot matrices can be cached because we only receive integer (or integer * look_sensitivity) inputs from the mouse events
-        self.facing = Mat3::from_rotation_z(-amount_deg * DEG_TO_RAD) * self.facing;
+        // kept in -180..180 degrees
+        self.yaw = (self.yaw - amount_deg * DEG_TO_RAD + PI).rem_euclid(TAU) - PI;
+        if self.body_follows_look {
+            self.body_yaw = self.look_yaw();
+        }
     }
+    // stops at MAX_PITCH either way instead of refusing the turn, so a fast flick still ends up looking straight up
     pub fn turn_vertical(&mut self, amount_deg: f32) {
-        // these rot matrices can be cached because we only receive integer (or integer * look_sensitivity) inputs from the mouse events
-        let new_facing = Mat3::from_axis_angle(self.get_rightward_vector(), -amount_deg * DEG_TO_RAD) * self.facing;
-        if new_facing.dot(Vec3::Z).abs() < 0.999 {
-            self.facing = new_facing;
-        }
+        self.pitch = (self.pitch - amount_deg * DEG_TO_RAD).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+    // turn the body without moving the head
+    pub fn turn_body_to(&mut self, yaw: f32) {
+        self.body_yaw = yaw;
     }
 
     pub fn update_time_independent_acceleration(&mut self){
         self.acc = Vec3::ZERO;
 
         if self.in_air && !self.flying {
-            self.acc_rate = 10.0;
+            self.acc_rate = AIR_ACC_RATE;
         } else {
-            self.acc_rate = 150.0;
+            self.acc_rate = GROUND_ACC_RATE;
         }
 
-        let sprint_factor = if self.desired_movement.SPRINT { 2.0 } else { 1.0 };
+        // sprinting only boosts going forward, and you can't kick it in mid-jump unless you're flying
+        let sprint_factor = if self.is_sprinting() { 2.0 } else { 1.0 };
 
         if self.desired_movement.FORWARD {
             self.acc += self.get_moving_forward_xy(1.0) * sprint_factor;
         }
         if self.desired_movement.BACKWARD {
-            self.acc += self.get_moving_forward_xy(-1.0) * sprint_factor;
+            self.acc += self.get_moving_forward_xy(-1.0);
         }
         if self.desired_movement.RIGHT {
-            self.acc += self.get_moving_rightward(1.0) * sprint_factor;
+            self.acc += self.get_moving_rightward(1.0);
         }
         if self.desired_movement.LEFT {
-            self.acc += self.get_moving_rightward(-1.0) * sprint_factor;
+            self.acc += self.get_moving_rightward(-1.0);
         }
 
         if self.flying {
@@ -134,26 +228,174 @@ impl Entity {
             if self.in_air {
                 self.acc.z -= self.gravity;
             } else if self.desired_movement.UP {
-                self.vel.z = (self.jump_height * self.gravity * 2.0).sqrt(); //sqrt(9.8*2) = 4.427188
+                self.vel.z = self.jump_impulse();
             }
         }
     }
 
+    pub fn friction(&self) -> f32 {
+        if self.in_air && !self.flying {
+            self.air_friction
+        } else {
+            self.ground_friction
+        }
+    }
+
+    pub fn is_sprinting(&self) -> bool {
+        self.desired_movement.SPRINT && self.desired_movement.FORWARD && (self.flying || !self.in_air)
+    }
+
     pub fn get_moving_forward(&self, fac: f32) -> Vec3 {
-        fac * self.acc_rate * self.facing
+        fac * self.acc_rate * self.look_dir()
     }
     pub fn get_moving_forward_xy(&self, fac: f32) -> Vec3 {
         // moves in the xy plane only
-        fac * self.acc_rate * self.facing.with_z(0f32).normalize()
+        fac * self.acc_rate * self.body_forward()
     }
     pub fn get_moving_rightward(&self, fac: f32) -> Vec3 {
-        fac * self.acc_rate * self.get_rightward_vector()
+        fac * self.acc_rate * self.body_rightward()
     }
     pub fn get_moving_up(&self, fac: f32) -> Vec3 {
         fac * self.acc_rate * Vec3::Z
 
     }
+    // switching modes drops any vertical intent so a held space doesn't turn into a jump (or a climb) on the other side.
+    // taking off also kills the fall so you hover where you are, landing is left to gravity and the next physics step
+    pub fn set_flying(&mut self, flying: bool) {
+        if flying == self.flying {
+            return;
+        }
+        self.flying = flying;
+        self.desired_movement.UP = false;
+        self.desired_movement.DOWN = false;
+        if flying {
+            self.vel.z = 0.0;
+        }
+    }
+    pub fn toggle_flying(&mut self) {
+        self.set_flying(!self.flying);
+    }
     pub fn clear_moving(&mut self) {
         self.desired_movement = NO_MOVEMENT;
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn air_friction_is_lower_than_ground_friction() {
+        let mut entity = Entity::new(Vec3::ZERO);
+        entity.in_air = false;
+        let ground = entity.friction();
+        entity.in_air = true;
+        let air = entity.friction();
+        assert!(air < ground);
+        // flying keeps tight control even though nothing's underfoot
+        entity.set_flying(true);
+        assert_eq!(entity.friction(), ground);
+    }
+
+    #[test]
+    fn doubling_jump_height_scales_impulse_by_root_two() {
+        let mut entity = Entity::new(Vec3::ZERO);
+        entity.apply_settings(&PlayerSettings { jump_height: 1.0, ..PlayerSettings::default() });
+        let impulse = entity.jump_impulse();
+        entity.apply_settings(&PlayerSettings { jump_height: 2.0, ..PlayerSettings::default() });
+        assert!((entity.jump_impulse() / impulse - 2f32.sqrt()).abs() < 1e-5);
+        // and the peak of the jump, v^2 / 2g, is the height asked for
+        assert!((entity.jump_impulse().powi(2) / (2.0 * entity.gravity) - 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn top_speed_is_move_speed_on_ground_and_in_air() {
+        let mut entity = Entity::new(Vec3::ZERO);
+        entity.set_move_speed(6.0);
+        for in_air in [false, true] {
+            entity.in_air = in_air;
+            entity.update_time_independent_acceleration();
+            assert!((entity.acc_rate / entity.friction() - 6.0).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn thousands_of_turns_dont_drift() {
+        let mut entity = Entity::new(Vec3::ZERO);
+        // what the angles should be, kept in f64 degrees alongside
+        let (mut yaw, mut pitch) = (90.0f64, 0.0f64);
+        let max_pitch = MAX_PITCH as f64 / DEG_TO_RAD as f64;
+        for i in 0..10_000 {
+            // a jittery mouse that wanders everywhere, and hits the pitch limits now and then
+            let dx = ((i * 37) % 101 - 50) as f32 * 0.73;
+            let dy = ((i * 53) % 61 - 30) as f32 * 0.91;
+            entity.turn_horizontal(dx);
+            entity.turn_vertical(dy);
+            yaw = (yaw - dx as f64 + 180.0).rem_euclid(360.0) - 180.0;
+            pitch = (pitch - dy as f64).clamp(-max_pitch, max_pitch);
+
+            let look = entity.look_dir();
+            assert!((look.length() - 1.0).abs() < 1e-5, "look_dir is {} long after {} turns", look.length(), i);
+        }
+
+        let look = entity.look_dir();
+        let (yaw_rad, pitch_rad) = ((yaw as f32) * DEG_TO_RAD, (pitch as f32) * DEG_TO_RAD);
+        let expected = Vec3::new(pitch_rad.cos() * yaw_rad.cos(), pitch_rad.cos() * yaw_rad.sin(), pitch_rad.sin());
+        assert!((look - expected).length() < 1e-3, "{} != {}", look, expected);
+        assert_eq!(look, Vec3::new(entity.pitch.cos() * entity.yaw.cos(), entity.pitch.cos() * entity.yaw.sin(), entity.pitch.sin()));
+
+        // f32 rounding on every turn adds up to around a hundredth of a degree by now, which is fine. a vector being
+        // rotated over and over would have wandered off unit length instead
+        let degrees = entity.facing_in_degrees();
+        assert!((degrees.x as f64 - pitch).abs() < 0.05, "pitch {} != {}", degrees.x, pitch);
+        // either side of the wrap at 180 is the same heading
+        let yaw_error = (degrees.y as f64 - yaw + 180.0).rem_euclid(360.0) - 180.0;
+        assert!(yaw_error.abs() < 0.05, "yaw {} != {}", degrees.y, yaw);
+        assert!(degrees.y.abs() <= 180.0 + 1e-3);
+    }
+
+    // walking goes by the body, not the head, so a mob can walk one way while watching another
+    #[test]
+    fn walks_where_the_body_points_not_where_it_looks() {
+        let mut world = world::World::new_with_assets(true);
+        let mut mob = Entity::new(Vec3::ZERO);
+        mob.yaw = 0.0; // looking east
+        mob.body_follows_look = false;
+        mob.turn_body_to(90.0 * DEG_TO_RAD); // walking north
+        // flying so it holds its height without any ground loaded
+        mob.set_flying(true);
+        mob.desired_movement.FORWARD = true;
+        let mob = world.spawn_entity(mob).unwrap();
+
+        for _ in 0..120 {
+            world.physics_step(1.0 / 120.0);
+        }
+        let mob = world.entities.read_lock(mob).unwrap();
+        assert!(mob.pos.y > 1.0, "only got to {}", mob.pos);
+        assert!(mob.pos.x.abs() < 1e-3, "drifted to {}", mob.pos);
+        assert!((mob.look_dir() - Vec3::X).length() < 1e-6);
+    }
+
+    #[test]
+    fn turning_past_vertical_stops_at_the_pole() {
+        let mut entity = Entity::new(Vec3::ZERO);
+        entity.turn_vertical(-80.0);
+        let near_top = entity.look_dir().z;
+        // would go past straight up, so it stops just short instead of not turning at all
+        entity.turn_vertical(-20.0);
+        assert!(entity.look_dir().z > near_top);
+        assert!((entity.look_dir().z - MAX_PITCH.sin()).abs() < 1e-6);
+        assert!(entity.look_dir().z < 1.0);
+        entity.turn_vertical(-500.0);
+        assert!((entity.look_dir().z - MAX_PITCH.sin()).abs() < 1e-6);
+
+        // still free to turn around while looking up
+        let before = entity.look_dir();
+        entity.turn_horizontal(90.0);
+        assert!((entity.look_dir().truncate() - before.truncate()).length() > 1e-4);
+        assert_eq!(entity.look_dir().z, before.z);
+
+        entity.turn_vertical(1000.0);
+        assert!((entity.look_dir().z + MAX_PITCH.sin()).abs() < 1e-6);
+    }
+}
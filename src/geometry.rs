@@ -1,14 +1,85 @@
 use glam::f32::Vec3A;
 
+// vertices are packed into two words to keep chunk meshes small
+// word 0: x (bits 0-5), y (6-11), z (12-17), face (18-20), shade (21-24), light (25-28) -- chunk-local positions, so CHUNK_SIZE must stay below 64
+// word 1: u (bits 0-7), v (8-15), tex_id (16-31)
+const POS_BITS: u32 = 6;
+const POS_MASK: u32 = (1 << POS_BITS) - 1;
+// the far corner of a chunk sits at CHUNK_SIZE, so that's the biggest local coordinate a vertex can hold
+pub const MAX_PACKED_POS: u32 = POS_MASK;
+const FACE_SHIFT: u32 = 3 * POS_BITS;
+const FACE_MASK: u32 = 0b111;
+const SHADE_SHIFT: u32 = FACE_SHIFT + 3;
+// shade is stored in 16 steps, 15 being full brightness
+const SHADE_MAX: u32 = 0xf;
+const LIGHT_SHIFT: u32 = SHADE_SHIFT + 4;
+// block light levels, see Chunk::compute_light. 15 is full sunlight
+pub const MAX_LIGHT: u32 = 0xf;
+const UV_MASK: u32 = 0xff;
+const TEX_SHIFT: u32 = 16;
+
 #[repr(C)]
-#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
-    pub pos: [f32; 3],
-    pub uv: [f32; 2],
-    pub tex_id: u32,
+    pub packed: [u32; 2],
 }
 
 impl Vertex {
+    pub const fn new(pos: [u32; 3], face: u32, uv: [u32; 2], tex_id: u32) -> Self {
+        debug_assert!(pos[0] <= POS_MASK && pos[1] <= POS_MASK && pos[2] <= POS_MASK);
+        debug_assert!(uv[0] <= UV_MASK && uv[1] <= UV_MASK);
+        debug_assert!(tex_id <= u16::MAX as u32);
+        Self {
+            packed: [
+                pos[0] | pos[1] << POS_BITS | pos[2] << (2 * POS_BITS) | (face & FACE_MASK) << FACE_SHIFT | SHADE_MAX << SHADE_SHIFT | MAX_LIGHT << LIGHT_SHIFT,
+                uv[0] | uv[1] << 8 | tex_id << TEX_SHIFT,
+            ]
+        }
+    }
+
+    pub fn pos(&self) -> [u32; 3] {
+        [
+            self.packed[0] & POS_MASK,
+            (self.packed[0] >> POS_BITS) & POS_MASK,
+            (self.packed[0] >> (2 * POS_BITS)) & POS_MASK,
+        ]
+    }
+    pub fn face(&self) -> u32 {
+        (self.packed[0] >> FACE_SHIFT) & FACE_MASK
+    }
+    pub fn uv(&self) -> [u32; 2] {
+        [self.packed[1] & UV_MASK, (self.packed[1] >> 8) & UV_MASK]
+    }
+    pub fn tex_id(&self) -> u32 {
+        self.packed[1] >> TEX_SHIFT
+    }
+    pub fn shade(&self) -> f32 {
+        ((self.packed[0] >> SHADE_SHIFT) & SHADE_MAX) as f32 / SHADE_MAX as f32
+    }
+    pub fn light(&self) -> u32 {
+        (self.packed[0] >> LIGHT_SHIFT) & MAX_LIGHT
+    }
+
+    pub const fn with_tex_id(mut self, tex_id: u32) -> Self {
+        debug_assert!(tex_id <= u16::MAX as u32);
+        self.packed[1] = (self.packed[1] & !((u16::MAX as u32) << TEX_SHIFT)) | tex_id << TEX_SHIFT;
+        self
+    }
+
+    // brightness multiplier from 0 to 1, rounded to the nearest of the 16 levels we have room for. new() starts at 1
+    pub fn with_shade(mut self, shade: f32) -> Self {
+        let level = (shade.clamp(0.0, 1.0) * SHADE_MAX as f32).round() as u32;
+        self.packed[0] = (self.packed[0] & !(SHADE_MAX << SHADE_SHIFT)) | level << SHADE_SHIFT;
+        self
+    }
+
+    // light level of the space the face looks out into, 0 to MAX_LIGHT. new() starts fully lit
+    pub const fn with_light(mut self, light: u32) -> Self {
+        debug_assert!(light <= MAX_LIGHT);
+        self.packed[0] = (self.packed[0] & !(MAX_LIGHT << LIGHT_SHIFT)) | light << LIGHT_SHIFT;
+        self
+    }
+
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
@@ -17,24 +88,167 @@ impl Vertex {
                 wgpu::VertexAttribute {
                     offset: 0,
                     shader_location: 0,
-                    format: wgpu::VertexFormat::Float32x3,
+                    format: wgpu::VertexFormat::Uint32x2,
                 },
+            ]
+        }
+    }
+}
+
+// per-draw data for a chunk; vertices are chunk-local so the shader needs the chunk's world position
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ChunkInstance {
+    pub origin: [f32; 3],
+    // 0 to 1 while the chunk fades in after first showing up, main.wgsl dithers it out below 1
+    pub fade: f32,
+}
+
+impl ChunkInstance {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ChunkInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    offset: 0,
                     shader_location: 1,
-                    format: wgpu::VertexFormat::Float32x2,
+                    format: wgpu::VertexFormat::Float32x3,
                 },
                 wgpu::VertexAttribute {
-                    offset: std::mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
                     shader_location: 2,
-                    format: wgpu::VertexFormat::Uint32,
-                }
+                    format: wgpu::VertexFormat::Float32,
+                },
             ]
         }
     }
 }
 
-#[derive(Clone)]
+// axis-aligned box, min inclusive and max exclusive
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glam::Vec3,
+    pub max: glam::Vec3,
+}
+
+impl Aabb {
+    // one whole block, in block-local coords
+    pub const UNIT: Aabb = Aabb { min: glam::Vec3::ZERO, max: glam::Vec3::ONE };
+
+    pub fn new(min: glam::Vec3, max: glam::Vec3) -> Self {
+        Self { min, max }
+    }
+
+    pub fn contains(&self, p: glam::Vec3) -> bool {
+        p.cmpge(self.min).all() && p.cmplt(self.max).all()
+    }
+
+    // boxes that only touch don't count
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.cmplt(other.max).all() && other.min.cmplt(self.max).all()
+    }
+
+    pub fn offset(&self, by: glam::Vec3) -> Aabb {
+        Aabb { min: self.min + by, max: self.max + by }
+    }
+
+    // the bottom `height` of a unit block, turned so its top faces `top` (a slab placed against a wall hugs that wall)
+    pub fn unit_slab(height: f32, top: Facing) -> Aabb {
+        use glam::Vec3;
+        let axis = top.normal().abs();
+        let thick = Vec3::ONE - axis * (1.0 - height); // `height` along the slab's axis, 1 along the others
+        match top {
+            Facing::N | Facing::E | Facing::U => Aabb { min: Vec3::ZERO, max: thick },
+            Facing::S | Facing::W | Facing::D => Aabb { min: Vec3::ONE - thick, max: Vec3::ONE },
+        }
+    }
+}
+
+// distance along the ray to where it enters the box, if it hits within max_distance. dir must be normalized
+// (slab method; a ray starting inside the box hits at 0)
+pub fn ray_aabb(start: glam::Vec3, dir: glam::Vec3, aabb: &Aabb, max_distance: f32) -> Option<f32> {
+    let inv_dir = dir.recip(); // axis-parallel rays give inf here, which the min/max below handle
+    let t1 = (aabb.min - start) * inv_dir;
+    let t2 = (aabb.max - start) * inv_dir;
+    let t_enter = t1.min(t2).max_element().max(0.0);
+    let t_exit = t1.max(t2).min_element();
+    if t_enter <= t_exit && t_enter <= max_distance {
+        Some(t_enter)
+    } else {
+        None
+    }
+}
+
+// screen-space point in clip coordinates, for flat ui drawn over the scene (crosshair.wgsl)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Vertex2D {
+    pub pos: [f32; 2],
+}
+
+impl Vertex2D {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Vertex2D>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x2],
+        }
+    }
+}
+
+// world-space colored line endpoint for the debug overlays (debug_lines.wgsl)
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 3],
+}
+
+impl LineVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ]
+        }
+    }
+
+    // the 12 edges of an axis-aligned box as a line list
+    pub fn box_edges(min: glam::Vec3, max: glam::Vec3, color: [f32; 3]) -> [LineVertex; 24] {
+        let corner = |i: usize| glam::Vec3::new(
+            if i & 1 == 0 { min.x } else { max.x },
+            if i & 2 == 0 { min.y } else { max.y },
+            if i & 4 == 0 { min.z } else { max.z },
+        ).to_array();
+        // corners are numbered by which axes are at max (bit 0 x, bit 1 y, bit 2 z); an edge joins corners one bit apart
+        const EDGES: [(usize, usize); 12] = [
+            (0, 1), (2, 3), (4, 5), (6, 7), // along x
+            (0, 2), (1, 3), (4, 6), (5, 7), // along y
+            (0, 4), (1, 5), (2, 6), (3, 7), // along z
+        ];
+        let mut lines = [LineVertex { pos: [0.0; 3], color }; 24];
+        for (i, (a, b)) in EDGES.into_iter().enumerate() {
+            lines[2*i].pos = corner(a);
+            lines[2*i+1].pos = corner(b);
+        }
+        lines
+    }
+}
+
+// N is +y, E is +x, U is +z
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Facing {
     N = 0,
     E = 1,
@@ -44,42 +258,181 @@ pub enum Facing {
     D = 5,
 }
 
-const UVS: [[f32; 2]; 4] = [
-    [0.0, 0.0],
-    [0.0, 1.0],
-    [1.0, 1.0],
-    [1.0, 0.0],
+impl Facing {
+    pub fn from_index(i: u8) -> Self {
+        match i {
+            0 => Facing::N,
+            1 => Facing::E,
+            2 => Facing::W,
+            3 => Facing::S,
+            4 => Facing::U,
+            5 => Facing::D,
+            _ => panic!("{} is not a valid facing", i),
+        }
+    }
+
+    // in discriminant order, so `Facing::all()[f as usize] == f`
+    pub const fn all() -> [Facing; 6] {
+        [Facing::N, Facing::E, Facing::W, Facing::S, Facing::U, Facing::D]
+    }
+
+    // outward unit vector of the face. squares_to_vertices winds every quad ccw when looked at from this side
+    pub const fn normal(&self) -> glam::Vec3 {
+        use glam::Vec3;
+        match self {
+            Facing::N => Vec3::Y,
+            Facing::E => Vec3::X,
+            Facing::W => Vec3::NEG_X,
+            Facing::S => Vec3::NEG_Y,
+            Facing::U => Vec3::Z,
+            Facing::D => Vec3::NEG_Z,
+        }
+    }
+
+    // fake directional light baked into faces so blocks don't look flat before there's real lighting
+    pub const fn ambient_shade(&self) -> f32 {
+        match self {
+            Facing::U => 1.0,
+            Facing::N | Facing::E | Facing::W | Facing::S => 0.8,
+            Facing::D => 0.6,
+        }
+    }
+
+    pub const fn opposite(&self) -> Facing {
+        match self {
+            Facing::N => Facing::S,
+            Facing::E => Facing::W,
+            Facing::W => Facing::E,
+            Facing::S => Facing::N,
+            Facing::U => Facing::D,
+            Facing::D => Facing::U,
+        }
+    }
+
+    // the face pointing along an axis-aligned unit vector, e.g. the difference between two neighboring block positions
+    pub fn from_normal(normal: glam::Vec3) -> Option<Self> {
+        Facing::all().into_iter().find(|f| f.normal() == normal)
+    }
+
+    // which of a block's own faces shows on this world face when the block is rotated so its top points toward `top`
+    // (the rotation carries the block's top to `top` by the shortest turn, so e.g. a log on its side shows end grain on `top` and its opposite)
+    pub fn to_block_local(&self, top: Facing) -> Facing {
+        use Facing::*;
+        match top {
+            U => *self,
+            D => match self { U => D, D => U, N => S, S => N, f => *f },
+            N => match self { N => U, D => N, S => D, U => S, f => *f },
+            S => match self { S => U, D => S, N => D, U => N, f => *f },
+            E => match self { E => U, D => E, W => D, U => W, f => *f },
+            W => match self { W => U, D => W, E => D, U => E, f => *f },
+        }
+    }
+}
+
+const UVS: [[u32; 2]; 4] = [
+    [0, 0],
+    [0, 1],
+    [1, 1],
+    [1, 0],
 ];
 
 pub const CUBE: [Vertex; 24] = [
     // N
-    Vertex { pos: [1.0, 1.0, 1.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [1.0, 1.0, 0.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [0.0, 1.0, 0.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [0.0, 1.0, 1.0], uv: UVS[3], tex_id: 0},
+    Vertex::new([1, 1, 1], Facing::N as u32, UVS[0], 0),
+    Vertex::new([1, 1, 0], Facing::N as u32, UVS[1], 0),
+    Vertex::new([0, 1, 0], Facing::N as u32, UVS[2], 0),
+    Vertex::new([0, 1, 1], Facing::N as u32, UVS[3], 0),
     // E
-    Vertex { pos: [1.0, 0.0, 1.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [1.0, 0.0, 0.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [1.0, 1.0, 0.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [1.0, 1.0, 1.0], uv: UVS[3], tex_id: 0},
+    Vertex::new([1, 0, 1], Facing::E as u32, UVS[0], 0),
+    Vertex::new([1, 0, 0], Facing::E as u32, UVS[1], 0),
+    Vertex::new([1, 1, 0], Facing::E as u32, UVS[2], 0),
+    Vertex::new([1, 1, 1], Facing::E as u32, UVS[3], 0),
     // W
-    Vertex { pos: [0.0, 1.0, 1.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [0.0, 1.0, 0.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [0.0, 0.0, 0.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [0.0, 0.0, 1.0], uv: UVS[3], tex_id: 0},
+    Vertex::new([0, 1, 1], Facing::W as u32, UVS[0], 0),
+    Vertex::new([0, 1, 0], Facing::W as u32, UVS[1], 0),
+    Vertex::new([0, 0, 0], Facing::W as u32, UVS[2], 0),
+    Vertex::new([0, 0, 1], Facing::W as u32, UVS[3], 0),
     // S
-    Vertex { pos: [0.0, 0.0, 1.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [0.0, 0.0, 0.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [1.0, 0.0, 0.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [1.0, 0.0, 1.0], uv: UVS[3], tex_id: 0},
+    Vertex::new([0, 0, 1], Facing::S as u32, UVS[0], 0),
+    Vertex::new([0, 0, 0], Facing::S as u32, UVS[1], 0),
+    Vertex::new([1, 0, 0], Facing::S as u32, UVS[2], 0),
+    Vertex::new([1, 0, 1], Facing::S as u32, UVS[3], 0),
     // U
-    Vertex { pos: [0.0, 1.0, 1.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [0.0, 0.0, 1.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [1.0, 0.0, 1.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [1.0, 1.0, 1.0], uv: UVS[3], tex_id: 0},
+    Vertex::new([0, 1, 1], Facing::U as u32, UVS[0], 0),
+    Vertex::new([0, 0, 1], Facing::U as u32, UVS[1], 0),
+    Vertex::new([1, 0, 1], Facing::U as u32, UVS[2], 0),
+    Vertex::new([1, 1, 1], Facing::U as u32, UVS[3], 0),
     // D
-    Vertex { pos: [0.0, 0.0, 0.0], uv: UVS[0], tex_id: 0},
-    Vertex { pos: [0.0, 1.0, 0.0], uv: UVS[1], tex_id: 0},
-    Vertex { pos: [1.0, 1.0, 0.0], uv: UVS[2], tex_id: 0},
-    Vertex { pos: [1.0, 0.0, 0.0], uv: UVS[3], tex_id: 0},
-];
\ No newline at end of file
+    Vertex::new([0, 0, 0], Facing::D as u32, UVS[0], 0),
+    Vertex::new([0, 1, 0], Facing::D as u32, UVS[1], 0),
+    Vertex::new([1, 1, 0], Facing::D as u32, UVS[2], 0),
+    Vertex::new([1, 0, 0], Facing::D as u32, UVS[3], 0),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_fields_round_trip() {
+        for face in Facing::all() {
+            for light in 0..=MAX_LIGHT {
+                let v = Vertex::new([0, 31, MAX_PACKED_POS], face as u32, [255, 3], u16::MAX as u32).with_light(light);
+                assert_eq!(v.pos(), [0, 31, MAX_PACKED_POS]);
+                assert_eq!(v.face(), face as u32);
+                assert_eq!(v.uv(), [255, 3]);
+                assert_eq!(v.tex_id(), u16::MAX as u32);
+                assert_eq!(v.light(), light);
+                assert_eq!(v.shade(), 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn facing_opposites_and_normals() {
+        for (i, facing) in Facing::all().into_iter().enumerate() {
+            assert_eq!(facing as usize, i);
+            assert_eq!(Facing::from_index(i as u8), facing);
+            assert_eq!(facing.opposite().opposite(), facing);
+            assert_ne!(facing.opposite(), facing);
+            assert_eq!(facing.opposite().normal(), -facing.normal());
+            assert_eq!(Facing::from_normal(facing.normal()), Some(facing));
+        }
+        assert_eq!(Facing::N.normal(), glam::Vec3::Y);
+        assert_eq!(Facing::E.normal(), glam::Vec3::X);
+        assert_eq!(Facing::U.normal(), glam::Vec3::Z);
+    }
+
+    #[test]
+    fn ray_hits_and_misses_a_box() {
+        use glam::Vec3;
+        let aabb = Aabb::UNIT.offset(Vec3::new(3.0, 0.0, 0.0));
+        let start = Vec3::new(0.0, 0.5, 0.5);
+        assert_eq!(ray_aabb(start, Vec3::X, &aabb, 10.0), Some(3.0));
+        assert_eq!(ray_aabb(start, Vec3::NEG_X, &aabb, 10.0), None);
+        assert_eq!(ray_aabb(start, Vec3::Y, &aabb, 10.0), None);
+        // out of reach
+        assert_eq!(ray_aabb(start, Vec3::X, &aabb, 2.5), None);
+        // starting inside counts as a hit right away
+        assert_eq!(ray_aabb(Vec3::new(3.5, 0.5, 0.5), Vec3::Z, &aabb, 10.0), Some(0.0));
+        // diagonal, in through the corner edge
+        let diagonal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let t = ray_aabb(Vec3::new(1.0, -2.0, 0.5), diagonal, &aabb, 10.0).unwrap();
+        assert!((t - 2.0 * 2f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn vertex_setters_leave_other_fields_alone() {
+        let v = Vertex::new([1, 2, 3], Facing::W as u32, [4, 5], 6)
+            .with_shade(0.6)
+            .with_light(7)
+            .with_tex_id(1000)
+            .with_light(2);
+        assert_eq!(v.pos(), [1, 2, 3]);
+        assert_eq!(v.face(), Facing::W as u32);
+        assert_eq!(v.uv(), [4, 5]);
+        assert_eq!(v.tex_id(), 1000);
+        assert_eq!(v.light(), 2);
+        assert_eq!(v.shade(), 9.0 / 15.0);
+    }
+}
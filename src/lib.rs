@@ -0,0 +1,20 @@
+// everything but the window and event loop (main.rs), so benches and tests can build worlds and mesh chunks without a gpu
+pub mod renderer;
+pub mod geometry;
+pub mod world;
+pub mod clock;
+pub mod entity;
+pub mod camera;
+pub mod texturing;
+pub mod block;
+pub mod chunk;
+pub mod memarena;
+pub mod chunkset;
+pub mod memblock;
+pub mod meshcache;
+pub mod occlusion;
+pub mod settings;
+pub mod schematic;
+pub mod audio;
+pub mod terrain;
+pub mod save;
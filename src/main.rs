@@ -3,30 +3,128 @@ use winit::event::ElementState;
 use winit::keyboard::PhysicalKey;
 use winit::keyboard::KeyCode;
 use winit::event::DeviceId;
+use winit::event::MouseButton;
+use winit::event::MouseScrollDelta;
 use std::sync::Arc;
+use std::sync::mpsc;
 use winit::application::ApplicationHandler;
 use winit::event::{WindowEvent, DeviceEvent, Event};
 use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoop};
 use winit::window::{Window, WindowId};
 use glam::{Vec3};
+use log::{info, warn, error};
 
-mod renderer;
-mod geometry;
-mod world;
-mod clock;
-mod entity;
-mod camera;
-mod texturing;
-mod block;
-mod chunk;
-mod memarena;
-mod chunkset;
-mod memblock;
+use voxelgame::{renderer, world, clock, entity, camera, block, settings, audio};
+
+// index of the debug overlay in the renderer's text objects (it's the first one made)
+const DEBUG_TEXT: usize = 0;
+// what the debug keys cycle through, as multiples of the gravity and move speed in settings.toml
+const GRAVITY_PRESETS: [(&str, f32); 4] = [("normal", 1.0), ("moon", 0.165), ("mars", 0.38), ("heavy", 2.0)];
+const MOVE_SPEED_PRESETS: [f32; 3] = [1.0, 2.0, 4.0];
+// middle click blows up whatever you're looking at
+const EXPLOSION_RADIUS: f32 = 4.0;
+// slots on the hotbar, picked with the number keys
+const HOTBAR_SIZE: usize = 9;
+
+// a mouse button that repeats its action while held
+#[derive(Default)]
+struct HeldButton {
+    held: bool,
+    // clock time the action can fire again at
+    next_repeat: f32,
+}
+impl HeldButton {
+    fn set(&mut self, held: bool, now: f32) {
+        self.held = held;
+        self.next_repeat = now;
+    }
+
+    // whether the action should fire again this frame, at most once every `interval` seconds (the first repeat waits a full
+    // interval after the press). never catches up on missed repeats, since they'd all act on the same raycast.
+    // an interval of 0 or less turns repeating off
+    fn repeat_due(&mut self, now: f32, interval: f32) -> bool {
+        if !self.held || interval <= 0.0 || now < self.next_repeat + interval {
+            return false;
+        }
+        self.next_repeat = now;
+        true
+    }
+}
+
+// how long to nap between redraws while minimized instead of spinning on them
+const MINIMIZED_SLEEP: std::time::Duration = std::time::Duration::from_millis(50);
+// longest we'll hold up closing the window to save
+const SHUTDOWN_SAVE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
 
 #[derive(Default)]
 pub struct GameState {
     pub paused: bool,
     pub in_game: bool,
+    pub show_debug: bool,
+    // blocks you can pick from with 1-9. a slot with air in it places nothing
+    pub hotbar: [block::BlockID; HOTBAR_SIZE],
+    pub hotbar_index: usize,
+    // wheel movement that hasn't added up to a whole slot yet
+    pub hotbar_scroll: f32,
+}
+
+impl GameState {
+    // the first blocks in the config, in order
+    fn default_hotbar(block_properties: &block::BlockProtoSet) -> [block::BlockID; HOTBAR_SIZE] {
+        let mut hotbar = [0; HOTBAR_SIZE];
+        for (slot, id) in hotbar.iter_mut().zip(1..block_properties.len() as block::BlockID) {
+            *slot = id;
+        }
+        hotbar
+    }
+
+    // positive lines scroll up, which goes back a slot. wraps around at either end
+    pub fn scroll_hotbar(&mut self, lines: f32) {
+        self.hotbar_scroll += lines;
+        let slots = self.hotbar_scroll.trunc();
+        self.hotbar_scroll -= slots;
+        self.hotbar_index = (self.hotbar_index as isize - slots as isize).rem_euclid(HOTBAR_SIZE as isize) as usize;
+    }
+
+    // the block right click places
+    pub fn selected_block(&self) -> block::BlockID {
+        self.hotbar[self.hotbar_index]
+    }
+}
+
+// a touchpad scrolls in pixels, this many make one notch of a mouse wheel
+const SCROLL_PIXELS_PER_LINE: f64 = 40.0;
+
+// movement keys hold their action for as long as they're down, the rest fire once on the press
+fn apply_key_action(player: &mut entity::Entity, action: settings::KeyAction, pressed: bool) {
+    use settings::KeyAction;
+    let movement = &mut player.desired_movement;
+    match action {
+        KeyAction::Forward => movement.FORWARD = pressed,
+        KeyAction::Back => movement.BACKWARD = pressed,
+        KeyAction::Left => movement.LEFT = pressed,
+        KeyAction::Right => movement.RIGHT = pressed,
+        KeyAction::Jump => movement.UP = pressed,
+        KeyAction::Descend => movement.DOWN = pressed,
+        KeyAction::Sprint => movement.SPRINT = pressed,
+        KeyAction::ToggleFlying => if pressed { player.toggle_flying() },
+    }
+}
+
+// Digit1 is the first slot
+fn hotbar_slot(key: KeyCode) -> Option<usize> {
+    match key {
+        KeyCode::Digit1 => Some(0),
+        KeyCode::Digit2 => Some(1),
+        KeyCode::Digit3 => Some(2),
+        KeyCode::Digit4 => Some(3),
+        KeyCode::Digit5 => Some(4),
+        KeyCode::Digit6 => Some(5),
+        KeyCode::Digit7 => Some(6),
+        KeyCode::Digit8 => Some(7),
+        KeyCode::Digit9 => Some(8),
+        _ => None,
+    }
 }
 
 struct Game<'a> {
@@ -37,21 +135,47 @@ struct Game<'a> {
 
     hold_cursor: bool,
     cursor_moved_by: (f64, f64),
+    // nothing to draw into, so redraws are skipped until we're restored
+    minimized: bool,
+    // keys and buttons come in two kinds: toggles and one-off actions act on the press event alone (and ignore key repeats),
+    // while movement and these buttons are "held" state that gets acted on every frame until it's released
+    mouse_left: HeldButton,
+    mouse_right: HeldButton,
 
     world: world::World,
     clock: clock::Clock,
+    audio: audio::Audio,
+    settings: settings::Settings,
+    settings_path: String,
+    gravity_preset: usize,
+    move_speed_preset: usize,
 }
 
 impl Game<'_> {
     pub async fn new(event_loop: &EventLoop<()>) -> Self {
         //let window = Arc::new(event_loop.create_window(Window::default_attributes()).unwrap());
 
-        let world = world::World::new();
+        let mut world = world::World::new();
+        let settings_path = settings::settings_path();
+        let settings = settings::Settings::load(&settings_path);
+        let mut audio = audio::Audio::new();
+        audio.volume = settings.audio.volume;
+        audio.load_sound_sets(world.block_properties.sound_sets().into_iter());
+        let mut clock = clock::Clock::new();
+        clock.sim_tps = settings.world.tick_rate.max(1.0);
+        clock.physics_tps = settings.world.physics_rate.max(1.0);
+        world.apply_settings(&settings.world);
+        world.set_render_distance(settings.graphics.render_distance);
+        world.entities.write_lock(world.player).unwrap().apply_settings(&settings.player);
 
         Game {
             game_state: GameState {
                 paused: false,
                 in_game: true,
+                show_debug: true,
+                hotbar: GameState::default_hotbar(&world.block_properties),
+                hotbar_index: 0,
+                hotbar_scroll: 0.0,
             },
 
             window: None,
@@ -59,9 +183,17 @@ impl Game<'_> {
 
             hold_cursor: true,
             cursor_moved_by:  (0.0, 0.0), // for macos use only
+            minimized: false,
+            mouse_left: HeldButton::default(),
+            mouse_right: HeldButton::default(),
 
             world,
-            clock: clock::Clock::new(),
+            clock,
+            audio,
+            settings,
+            settings_path,
+            gravity_preset: 0,
+            move_speed_preset: 0,
         }
     }
 
@@ -84,10 +216,77 @@ impl Game<'_> {
             
         }
     }
+    // hot reload config/blocks.toml and the block textures, then remesh so the changes show up right away.
+    // the [ui] settings come along too since they're just as cheap to swap out
+    pub fn reload_content(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        self.settings.ui = settings::Settings::load(&self.settings_path).ui;
+        renderer.text_manager.set_font_family(&self.settings.ui.font_family);
+        match self.world.reload_block_properties() {
+            Ok(()) => {
+                renderer.reload_texture_set(0, self.world.block_properties.collect_textures());
+                self.audio.load_sound_sets(self.world.block_properties.sound_sets().into_iter());
+                self.world.remesh_all_chunks();
+                info!("Reloaded block properties and textures.");
+            }
+            Err(e) => error!("Couldn't reload block properties: {}", e),
+        }
+    }
+
+    // put anything toggled in game back into the settings and write them out, if that changed anything.
+    // saving happens on its own thread with a timeout so a stuck disk can't keep the window from closing
+    pub fn save_on_exit(&mut self) {
+        match self.world.save_region() {
+            Ok(saved) => info!("Saved {} edited chunks to {}", saved, self.world.save_dir.display()),
+            Err(e) => error!("{}", e),
+        }
+
+        let Some(renderer) = &self.renderer else { return };
+        let mut settings = self.settings.clone();
+        settings.camera.view_bobbing = renderer.camera.view_bobbing;
+        settings.camera.look_sensitivity = renderer.camera.look_sensitivity;
+        settings.graphics.placement_ghost = renderer.show_placement_ghost;
+        settings.graphics.texture_filter = renderer.texture_filter();
+        if settings == self.settings {
+            return; // rewriting the file would just throw away its comments
+        }
+
+        let path = self.settings_path.clone();
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let _ = tx.send(settings.save(&path));
+        });
+        match rx.recv_timeout(SHUTDOWN_SAVE_TIMEOUT) {
+            Ok(Ok(())) => info!("Saved settings to {}", self.settings_path),
+            Ok(Err(e)) => error!("{}", e),
+            Err(_) => error!("Gave up on saving settings after {:?}", SHUTDOWN_SAVE_TIMEOUT),
+        }
+    }
+
+    // what a left or right click does. takes the fields it needs rather than &mut self since the renderer is usually borrowed
+    fn do_mouse_action(world: &mut world::World, audio: &audio::Audio, button: MouseButton, selected_block: block::BlockID) {
+        match button {
+            MouseButton::Left => {
+                if let Some(broken) = world.player_break_block() {
+                    audio.play_block(&world.block_properties, broken, audio::SoundEvent::Break);
+                }
+            }
+            MouseButton::Right => {
+                if world.player_place_block(selected_block) {
+                    audio.play_block(&world.block_properties, selected_block, audio::SoundEvent::Place);
+                }
+            }
+            _ => (),
+        }
+    }
+
     pub fn on_defocus(&mut self) {
         let window = self.window.clone().unwrap();
         self.hold_cursor = false;
         window.set_cursor_visible(true);
+        // the release won't reach us once the cursor's let go, so don't keep breaking blocks behind the pause menu
+        self.mouse_left.set(false, self.clock.time);
+        self.mouse_right.set(false, self.clock.time);
 
         #[cfg(any(target_os = "macos", target_os = "linux"))]
         window.set_cursor_grab(winit::window::CursorGrabMode::None);
@@ -98,21 +297,25 @@ impl ApplicationHandler for Game<'_> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let t = std::time::Instant::now();
 
-        println!("Initializing window...");
+        info!("Initializing window...");
         let window = event_loop.create_window(Window::default_attributes()).unwrap();
         window.set_title("Minecraft");
         window.request_redraw();
         window.focus_window();
         self.window = Some(Arc::new(window));
 
-        println!("Initializing renderer... ({:.2?})", t.elapsed());
+        info!("Initializing renderer... ({:.2?})", t.elapsed());
         let mut renderer = pollster::block_on(renderer::Renderer::new(self.window.clone().unwrap()));
+        renderer.set_texture_filter(self.settings.graphics.texture_filter);
+        renderer.set_present_options(self.settings.graphics.vsync, self.settings.graphics.max_frame_latency);
         renderer.load_texture_set(self.world.block_properties.collect_textures());
-        
-        // println!("Generating chunks... ({:.2?})", t.elapsed());
-        // self.world.generate_all_chunks_around_player();
+        renderer.build_pipeline();
+        renderer.camera.apply_settings(&self.settings.camera);
+        renderer.chunk_fade_time = self.settings.graphics.chunk_fade_time;
+        renderer.show_placement_ghost = self.settings.graphics.placement_ghost;
+        renderer.text_manager.set_font_family(&self.settings.ui.font_family);
 
-        println!("Done! ({:.2?})", t.elapsed());
+        info!("Done! ({:.2?})", t.elapsed());
 
         self.renderer = Some(renderer);
     }
@@ -134,7 +337,6 @@ impl ApplicationHandler for Game<'_> {
                                 window.set_cursor_position(renderer.window_center_px).unwrap();
                             }
                         }
-                        //println!("Mouse moved: {:?} {} {} {}", delta, self.game_state.in_game, self.game_state.paused, self.hold_cursor);
                     },
                     _ => ()
                 }
@@ -152,20 +354,35 @@ impl ApplicationHandler for Game<'_> {
                 match event {
                     //WindowEvent::CursorMoved { position, .. } => { }
 
+                    WindowEvent::MouseWheel { delta, .. } => {
+                        if !self.game_state.paused {
+                            let lines = match delta {
+                                MouseScrollDelta::LineDelta(_, y) => y,
+                                MouseScrollDelta::PixelDelta(pos) => (pos.y / SCROLL_PIXELS_PER_LINE) as f32,
+                            };
+                            self.game_state.scroll_hotbar(lines);
+                        }
+                    }
+
+                    // break and place fire once on the press, then keep repeating while the button is held (see HeldButton)
+                    WindowEvent::MouseInput { state, button, .. } if matches!(button, MouseButton::Left | MouseButton::Right) => {
+                        let pressed = state == ElementState::Pressed && !self.game_state.paused;
+                        let held = if button == MouseButton::Left { &mut self.mouse_left } else { &mut self.mouse_right };
+                        held.set(pressed, self.clock.time);
+                        if pressed {
+                            Self::do_mouse_action(&mut self.world, &self.audio, button, self.game_state.selected_block());
+                        }
+                    }
+
                     WindowEvent::MouseInput { state: ElementState::Pressed, button, .. } => {
                         if !self.game_state.paused {
-                            let (destroy_location, place_location, _) = self.world.entities.read_lock(self.world.player).unwrap().get_block_looking_at(&self.world);
+                            let (destroy_location, _, looking_at_id) = self.world.entities.read_lock(self.world.player).unwrap().get_block_looking_at(&self.world);
                             match button {
-                                winit::event::MouseButton::Left => {
-                                    self.world.set_block_id_at(destroy_location, 0, &renderer.device);
-                                },
-                                winit::event::MouseButton::Right => {
-                                    let player_pos = self.world.entities.read_lock(self.world.player).unwrap().pos.floor();
-                                    if place_location != player_pos && place_location != player_pos + Vec3::Z{
-                                        self.world.set_block_id_at(place_location, 6, &renderer.device);
+                                MouseButton::Middle => {
+                                    if looking_at_id != 0 && self.world.explode(destroy_location + Vec3::splat(0.5), EXPLOSION_RADIUS) > 0 {
+                                        renderer.camera.shake(0.15);
                                     }
                                 },
-                                winit::event::MouseButton::Middle => (),
                                 _ => ()
                             }
                         }
@@ -173,19 +390,64 @@ impl ApplicationHandler for Game<'_> {
 
                     WindowEvent::KeyboardInput {event: KeyEvent{physical_key, state: ElementState::Pressed, repeat:false, ..}, is_synthetic: false, ..} => {
                         if !self.game_state.paused {
-                            let mut player = self.world.entities.write_lock(self.world.player).unwrap();
-                            match physical_key {
-                                PhysicalKey::Code(KeyCode::KeyW) => {player.desired_movement.FORWARD = true;}
-                                PhysicalKey::Code(KeyCode::KeyS) => {player.desired_movement.BACKWARD = true;}
-                                PhysicalKey::Code(KeyCode::KeyD) => {player.desired_movement.RIGHT = true;}
-                                PhysicalKey::Code(KeyCode::KeyA) => {player.desired_movement.LEFT = true;}
-                                PhysicalKey::Code(KeyCode::Space) => {player.desired_movement.UP = true;}
-                                PhysicalKey::Code(KeyCode::ShiftLeft) => {player.desired_movement.DOWN = true;}
-                                PhysicalKey::Code(KeyCode::KeyR) => {player.desired_movement.SPRINT = true;}
-                                _ => ()
+                            if let Some(action) = match physical_key { PhysicalKey::Code(key) => self.settings.controls.keys.action_for(key), _ => None } {
+                                let mut player = self.world.entities.write_lock(self.world.player).unwrap();
+                                apply_key_action(&mut player, action, true);
                             }
                         }
+                        if let Some(slot) = match physical_key { PhysicalKey::Code(key) => hotbar_slot(key), _ => None } {
+                            self.game_state.hotbar_index = slot;
+                        }
                         match physical_key {
+                            PhysicalKey::Code(KeyCode::F1) => {
+                                renderer.show_crosshair = !renderer.show_crosshair;
+                            }
+                            PhysicalKey::Code(KeyCode::F3) => {
+                                self.game_state.show_debug = !self.game_state.show_debug;
+                                renderer.text_manager.set_visible_on(DEBUG_TEXT, self.game_state.show_debug);
+                            }
+                            PhysicalKey::Code(KeyCode::F4) => {
+                                renderer.show_chunk_borders = !renderer.show_chunk_borders;
+                            }
+                            PhysicalKey::Code(KeyCode::F5) => {
+                                self.reload_content();
+                            }
+                            PhysicalKey::Code(KeyCode::F6) => {
+                                renderer.camera.view_bobbing = !renderer.camera.view_bobbing;
+                                info!("View bobbing {}", if renderer.camera.view_bobbing { "on" } else { "off" });
+                            }
+                            PhysicalKey::Code(KeyCode::F7) => {
+                                self.gravity_preset = (self.gravity_preset + 1) % GRAVITY_PRESETS.len();
+                                let (name, factor) = GRAVITY_PRESETS[self.gravity_preset];
+                                let mut player = self.world.entities.write_lock(self.world.player).unwrap();
+                                player.gravity = self.settings.player.gravity * factor;
+                                info!("Gravity: {} ({:.1})", name, player.gravity);
+                            }
+                            PhysicalKey::Code(KeyCode::F8) => {
+                                self.move_speed_preset = (self.move_speed_preset + 1) % MOVE_SPEED_PRESETS.len();
+                                let mut player = self.world.entities.write_lock(self.world.player).unwrap();
+                                player.set_move_speed(self.settings.player.move_speed * MOVE_SPEED_PRESETS[self.move_speed_preset]);
+                                info!("Move speed: {:.1}", player.move_speed);
+                            }
+                            PhysicalKey::Code(KeyCode::F9) => {
+                                self.world.respawn_player();
+                            }
+                            PhysicalKey::Code(KeyCode::F10) => {
+                                renderer.show_placement_ghost = !renderer.show_placement_ghost;
+                            }
+                            PhysicalKey::Code(KeyCode::BracketLeft) => {
+                                renderer.camera.look_sensitivity /= camera::LOOK_SENSITIVITY_STEP;
+                                info!("Look sensitivity: {:.3}", renderer.camera.look_sensitivity);
+                            }
+                            PhysicalKey::Code(KeyCode::BracketRight) => {
+                                renderer.camera.look_sensitivity *= camera::LOOK_SENSITIVITY_STEP;
+                                info!("Look sensitivity: {:.3}", renderer.camera.look_sensitivity);
+                            }
+                            PhysicalKey::Code(KeyCode::F12) => {
+                                let filter = renderer.texture_filter().next();
+                                renderer.set_texture_filter(filter);
+                                info!("Texture filter: {:?}", filter);
+                            }
                             PhysicalKey::Code(KeyCode::Escape) => {
                                 self.game_state.paused = !self.game_state.paused;  
                                 if !self.game_state.paused { // inverse because we unpaused on the line above. necessary because on_focus queries pause state
@@ -198,30 +460,36 @@ impl ApplicationHandler for Game<'_> {
                         }
                     }
                     WindowEvent::KeyboardInput {event: KeyEvent{physical_key, state: ElementState::Released, repeat:false, ..}, is_synthetic: false, ..} => {
-                        let mut player = self.world.entities.write_lock(self.world.player).unwrap();
-                        match physical_key {
-                            PhysicalKey::Code(KeyCode::KeyW) => {player.desired_movement.FORWARD = false;}
-                            PhysicalKey::Code(KeyCode::KeyS) => {player.desired_movement.BACKWARD = false;}
-                            PhysicalKey::Code(KeyCode::KeyD) => {player.desired_movement.RIGHT = false;}
-                            PhysicalKey::Code(KeyCode::KeyA) => {player.desired_movement.LEFT = false;}
-                            PhysicalKey::Code(KeyCode::Space) => {player.desired_movement.UP = false;}
-                            PhysicalKey::Code(KeyCode::ShiftLeft) => {player.desired_movement.DOWN = false;}
-                            PhysicalKey::Code(KeyCode::KeyR) => {player.desired_movement.SPRINT = false;}
-                            _ => ()
+                        if let Some(action) = match physical_key { PhysicalKey::Code(key) => self.settings.controls.keys.action_for(key), _ => None } {
+                            let mut player = self.world.entities.write_lock(self.world.player).unwrap();
+                            apply_key_action(&mut player, action, false);
                         }
                     }
 
                     WindowEvent::CloseRequested => {
-                        println!("User exited.");
+                        info!("User exited.");
+                        // failing to save shouldn't stop you from quitting, it only gets logged
+                        self.save_on_exit();
                         event_loop.exit();
                     },
                     WindowEvent::Resized(physical_size) => {
+                        // some platforms minimize by resizing to 0x0
+                        self.minimized = physical_size.width == 0 || physical_size.height == 0;
                         renderer.resize(physical_size);
                     }
-                    //WindowEvent::ScaleFactorChanged{scale_factor, ..} => {
-                    //    renderer.ui_scale = scale_factor as f32;
-                    //}
+                    WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                        // not every platform follows this with a Resized, so redo the size-dependent state here too
+                        renderer.set_scale_factor(scale_factor as f32, window.inner_size());
+                        if self.hold_cursor {
+                            // some platforms won't let us move the cursor at all, which isn't worth crashing over
+                            if let Err(e) = window.set_cursor_position(renderer.window_center_px) {
+                                warn!("Couldn't recenter the cursor: {}", e);
+                            }
+                        }
+                    }
                     WindowEvent::Focused(f) => {
+                        // others only tell us through focus, so ask the window
+                        self.minimized = window.is_minimized().unwrap_or(false) || renderer.size.width == 0 || renderer.size.height == 0;
                         if f {
                             self.on_focus();
                         } else {
@@ -230,24 +498,39 @@ impl ApplicationHandler for Game<'_> {
                     }
                     // ...
                     WindowEvent::RedrawRequested => {
+                        if self.minimized {
+                            // keep the clock going so the first frame back doesn't see one enormous tick
+                            self.clock.tick();
+                            std::thread::sleep(MINIMIZED_SLEEP);
+                            window.request_redraw();
+                            return;
+                        }
+
                         let player = self.world.entities.read_lock(self.world.player).unwrap();
 
                         self.clock.tick();
 
                         //let looking_at2 = player.get_last_air_looking_at(&self.world);
                         //if self.clock.tick % 5 == 0 {
-                        if true {
+                        // no point laying out text nobody will see
+                        if self.game_state.show_debug {
                             let (looking_at_pos, last_air_pos, looking_at_id) = player.get_block_looking_at(&self.world);
                             let facing = player.facing_in_degrees();
+                            let chunk_coords = self.world.chunks.world_to_chunk_coords(self.world.block_pos(player.pos));
+                            // shown absolute, the way a player would give directions
+                            let absolute_pos = player.pos + self.world.origin.as_vec3();
+                            let stats = self.world.stats();
                             renderer.text_manager.set_text_on(
-                                0,
+                                DEBUG_TEXT,
                                 format!(
-                                    "Frame={} Time={:.1} FPS={:.1}\nX=({:.2}, {:.2}, {:.2})\nV=({:.2}, {:.2}, {:.2})\nφ={:.0}° ϴ={:.0}°\nLooking: {} ({:.0}, {:.0}, {:.0})\nW={} H={}\nPAUSED = {}",
-                                    self.clock.tick, self.clock.time, self.clock.tps,
-                                    player.pos.x, player.pos.y, player.pos.z,
+                                    "Frame={} Time={:.1} FPS={:.1} ({:.1} ms)\nX=({:.2}, {:.2}, {:.2})\nV=({:.2}, {:.2}, {:.2})\nChunk=({}, {}, {}) Loaded={} GenQ={} MeshQ={} Verts={}\nφ={:.0}° ϴ={:.0}°\nLooking: {} ({:.0}, {:.0}, {:.0})\nHolding: {} [{}]\nW={} H={}\nPAUSED = {}",
+                                    self.clock.tick, self.clock.time, self.clock.fps, self.clock.frame_time_ms,
+                                    absolute_pos.x, absolute_pos.y, absolute_pos.z,
                                     player.vel.x, player.vel.y, player.vel.z,
+                                    chunk_coords.0, chunk_coords.1, chunk_coords.2, stats.loaded_chunks, stats.queued_gen, stats.queued_mesh, stats.total_vertices,
                                     facing.x, facing.y,
-                                    self.world.block_properties.by_id(looking_at_id).name, looking_at_pos.x, looking_at_pos.y, looking_at_pos.z,
+                                    self.world.block_properties.by_id(looking_at_id).name, looking_at_pos.x + self.world.origin.x as f32, looking_at_pos.y + self.world.origin.y as f32, looking_at_pos.z + self.world.origin.z as f32,
+                                    self.world.block_properties.by_id(self.game_state.selected_block()).name, self.game_state.hotbar_index + 1,
                                     //last_air_pos.x, last_air_pos.y, last_air_pos.z,
                                     renderer.size.width, renderer.size.height,
                                     self.game_state.paused
@@ -257,9 +540,39 @@ impl ApplicationHandler for Game<'_> {
 
                         drop(player);
 
-                        self.world.physics_step(self.clock.tick_time);
+                        for _ in 0..self.clock.take_sim_ticks() {
+                            self.world.sim_step(self.clock.sim_tick_time());
+                        }
+                        // held buttons act on wherever you're looking now, so sweeping the camera mines or builds along the way
+                        let controls = &self.settings.controls;
+                        let break_interval = if controls.repeat_rate > 0.0 { 1.0 / controls.repeat_rate } else { 0.0 };
+                        if self.mouse_left.repeat_due(self.clock.time, break_interval) {
+                            Self::do_mouse_action(&mut self.world, &self.audio, MouseButton::Left, self.game_state.selected_block());
+                        }
+                        if self.mouse_right.repeat_due(self.clock.time, controls.place_cooldown) {
+                            Self::do_mouse_action(&mut self.world, &self.audio, MouseButton::Right, self.game_state.selected_block());
+                        }
+
+                        for _ in 0..self.clock.take_physics_steps() {
+                            self.world.physics_step(self.clock.physics_step_time());
+                        }
+                        for block_id in self.world.take_footsteps() {
+                            self.audio.play_block(&self.world.block_properties, block_id, audio::SoundEvent::Step);
+                        }
+                        renderer.camera.update(self.clock.tick_time, &self.world.entities.read_lock(self.world.player).unwrap());
+
+                        self.world.update_loaded_chunks();
+                        self.world.spawn_chunk_updates();
+                        self.world.update_meshes();
+                        self.world.upload_meshes(&renderer.device);
 
-                        self.world.update_loaded_chunks(&renderer.device);
+                        // only where a right click would actually put something
+                        let (_, place_location, looking_at_id) = self.world.entities.read_lock(self.world.player).unwrap().get_block_looking_at(&self.world);
+                        renderer.placement_ghost = if looking_at_id != 0 && self.game_state.selected_block() != 0 && self.world.can_place_at(place_location) {
+                            Some((place_location, self.game_state.selected_block()))
+                        } else {
+                            None
+                        };
 
                         match renderer.render(&self.world) {
                             Ok(_) => {}
@@ -269,7 +582,16 @@ impl ApplicationHandler for Game<'_> {
                                 renderer.resize(size);
                             },
                             // All other errors (Outdated, Timeout) should be resolved by the next frame
-                            Err(e) => eprintln!("{:?}", e),
+                            Err(e) => warn!("{:?}", e),
+                        }
+
+                        // vsync paces us already; without it, sleep off whatever's left of the frame
+                        let fps_limit = self.settings.graphics.fps_limit;
+                        if fps_limit > 0.0 && !renderer.vsync() {
+                            let left = 1.0 / fps_limit - self.clock.time_since_tick();
+                            if left > 0.0 {
+                                std::thread::sleep(std::time::Duration::from_secs_f32(left));
+                            }
                         }
                         window.request_redraw();
                     }
@@ -284,6 +606,9 @@ impl ApplicationHandler for Game<'_> {
 
 
 fn main() {
+    // quiet by default; RUST_LOG=debug (or e.g. RUST_LOG=voxelgame::world=trace) for more. this file logs as VoxelGame, the library as voxelgame
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,voxelgame=info,VoxelGame=info")).init();
+
     let event_loop = EventLoop::new().unwrap();
     event_loop.set_control_flow(ControlFlow::Poll);
 
@@ -1,37 +1,117 @@
 struct VertexInput {
-    @location(0) position: vec3<f32>,
-    @location(1) uv: vec2<f32>,
-    @location(2) tex_id: u32,
+    // see geometry::Vertex for the bit layout
+    @location(0) packed: vec2<u32>,
+};
+
+struct InstanceInput {
+    @location(1) origin: vec3<f32>,
+    @location(2) fade: f32,
 };
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
     @location(0) uv: vec2<f32>,
     @location(1) tex_id: u32,
+    @location(2) shade: f32,
+    @location(3) fade: f32,
+    @location(4) normal: vec3<f32>,
+    @location(5) light: f32,
 };
 
+// faces in geometry::Facing order, N E W S U D
+var<private> NORMALS: array<vec3<f32>, 6> = array<vec3<f32>, 6>(
+    vec3<f32>(0.0, 1.0, 0.0),
+    vec3<f32>(1.0, 0.0, 0.0),
+    vec3<f32>(-1.0, 0.0, 0.0),
+    vec3<f32>(0.0, -1.0, 0.0),
+    vec3<f32>(0.0, 0.0, 1.0),
+    vec3<f32>(0.0, 0.0, -1.0),
+);
+// how lit faces facing away from the sun (and everything at night) still are
+const AMBIENT: f32 = 0.4;
+
 struct FrameData {
     projview: mat4x4<f32>,
+    sun_dir: vec3<f32>, // the way the light travels
 };
  // 1.
 @group(0) @binding(0) var<uniform> frame_data: FrameData;
 
+// whichever texture set the chunk being drawn uses; the renderer rebinds this per draw
 @group(1) @binding(0) var textures: texture_2d_array<f32>;
 @group(1) @binding(1) var texture_sampler: sampler;
 
 @vertex
-fn vs_main(model: VertexInput) -> VertexOutput {
+fn vs_main(model: VertexInput, chunk: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
-    out.position = frame_data.projview*vec4<f32>(model.position, 1.0);
-    out.uv = model.uv;
-    out.tex_id = model.tex_id;
+    let local_pos = vec3<f32>(
+        f32(model.packed.x & 0x3fu),
+        f32((model.packed.x >> 6u) & 0x3fu),
+        f32((model.packed.x >> 12u) & 0x3fu),
+    );
+    out.position = frame_data.projview*vec4<f32>(chunk.origin + local_pos, 1.0);
+    out.uv = vec2<f32>(f32(model.packed.y & 0xffu), f32((model.packed.y >> 8u) & 0xffu));
+    out.tex_id = model.packed.y >> 16u;
+    out.shade = f32((model.packed.x >> 21u) & 0xfu) / 15.0;
+    out.fade = chunk.fade;
+    out.normal = NORMALS[(model.packed.x >> 18u) & 0x7u];
+    out.light = f32((model.packed.x >> 25u) & 0xfu) / 15.0;
     return out;
 }
 
 // Fragment shader
 
+// 4x4 ordered dither thresholds. fading chunks drop a growing share of their pixels instead of blending,
+// so they stay in the opaque pass and nothing needs sorting
+var<private> BAYER: array<f32, 16> = array<f32, 16>(
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0,
+);
+
+// lit block colour, shared by the chunk and ghost passes
+fn shade_block(in: VertexOutput) -> vec4<f32> {
+    let color = textureSample(textures, texture_sampler, in.uv, in.tex_id);
+    let diffuse = max(dot(in.normal, -frame_data.sun_dir), AMBIENT);
+    return vec4<f32>(color.rgb * in.shade * diffuse * in.light, color.a);
+}
+
+// drops this pixel if it's part of the share a fading-in chunk doesn't show yet
+fn dither_fade(in: VertexOutput) {
+    if in.fade < 1.0 {
+        let p = vec2<u32>(in.position.xy) % 4u;
+        if in.fade * 16.0 <= BAYER[p.y * 4u + p.x] {
+            discard;
+        }
+    }
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    dither_fade(in);
     //return vec4<f32>(in.uv, 1.0, 1.0);
-    return textureSample(textures, texture_sampler, in.uv, in.tex_id);
-}
\ No newline at end of file
+    return shade_block(in);
+}
+
+// cutout blocks (leaves) are either fully there or not at all, so they can write depth and skip sorting
+const CUTOUT_ALPHA: f32 = 0.5;
+
+@fragment
+fn fs_cutout(in: VertexOutput) -> @location(0) vec4<f32> {
+    dither_fade(in);
+    let color = shade_block(in);
+    if color.a < CUTOUT_ALPHA {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+// how solid the placement preview looks
+const GHOST_ALPHA: f32 = 0.4;
+
+@fragment
+fn fs_ghost(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = shade_block(in);
+    return vec4<f32>(color.rgb, color.a * GHOST_ALPHA);
+}
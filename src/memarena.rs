@@ -12,6 +12,16 @@ pub enum ArenaError {
     OutOfMemory,
     PoisonedLock,
 }
+impl std::fmt::Display for ArenaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BoundsExceeded => write!(f, "handle is past the end of the arena"),
+            Self::DoesNotExist => write!(f, "nothing is stored at that handle"),
+            Self::OutOfMemory => write!(f, "the arena is full"),
+            Self::PoisonedLock => write!(f, "the object's lock was poisoned"),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct ArenaHandle<T> {
@@ -32,6 +42,13 @@ impl<T> Clone for ArenaHandle<T> {
         *self
     }
 }
+// handles are equal if they point at the same slot, whatever T is
+impl<T> PartialEq for ArenaHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index
+    }
+}
+impl<T> Eq for ArenaHandle<T> {}
 unsafe impl<T> Send for ArenaHandle<T> {}
 unsafe impl<T> Sync for ArenaHandle<T> {}
 // impl<T> std::fmt::Debug for ArenaHandle<T> where T: std::fmt::Debug {
@@ -105,7 +122,11 @@ impl<T> Arena<T> {
 
     // create an object at the next available space - if no space is free, sad!
     pub fn create(&mut self, obj: T) -> Result<ArenaHandle<T>, ArenaError> {
-        for i in self.last_known_free..self.memory.length {
+        if self.is_full() {
+            return Err(ArenaError::OutOfMemory);
+        }
+        // start where the last free slot was but wrap around, slots before it may have been freed since
+        for i in (self.last_known_free..self.memory.length).chain(0..self.last_known_free) {
             if unsafe {self.memory.allocated.add(i).read()} { continue; } // already allocated to that slot, keep going
 
             // we found one that was free!
@@ -115,14 +136,21 @@ impl<T> Arena<T> {
             }
 
             self.count += 1;
-            self.last_known_free += 1;
+            self.last_known_free = i + 1;
             return self.new_handle(i);
         }
         Err(ArenaError::OutOfMemory)
     }
 
     // destroy the object at a certain index so that space can be used again (e.g. entity dies)
+    // destroying a handle twice (or one that was never created) is an error rather than a double drop
     pub fn destroy(&mut self, handle: ArenaHandle<T>) -> Result<(), ArenaError> {
+        if handle.index >= self.memory.length {
+            return Err(ArenaError::BoundsExceeded);
+        }
+        if !unsafe {self.memory.is_allocated(handle.index)} {
+            return Err(ArenaError::DoesNotExist);
+        }
         unsafe {
             self.memory.drop(handle.index);
         }
@@ -131,6 +159,16 @@ impl<T> Arena<T> {
         Ok(())
     }
 
+    pub fn capacity(&self) -> usize {
+        self.memory.length
+    }
+    pub fn remaining_capacity(&self) -> usize {
+        self.memory.length - self.count
+    }
+    pub fn is_full(&self) -> bool {
+        self.remaining_capacity() == 0
+    }
+
     // get the object at a certain index, wrapped in a RwLock
     pub fn fetch_lock(&self, handle: ArenaHandle<T>) -> Result<&RwLock<T>, ArenaError> {
         unsafe {Ok(&*self.memory.memory.add(handle.index))}
@@ -159,62 +197,95 @@ pub struct ArenaIterator<'a, T> {
 impl<'a, T> Iterator for ArenaIterator<'a, T> {
     type Item = ArenaHandle<T>;
     fn next(&mut self) -> std::option::Option<Self::Item> {
-        
-        if self.i < self.arena.memory.length {
-            match self.arena.new_handle(self.i) {
-                Ok(handle) => {self.i += 1; return Some(handle)}
-                _ => {self.i += 1; return self.next()}
+        // walk forward to the next allocated slot. a loop, not recursion, so big gaps can't blow the stack
+        while self.i < self.arena.memory.length {
+            let i = self.i;
+            self.i += 1;
+            if let Ok(handle) = self.arena.new_handle(i) {
+                return Some(handle);
             }
-        } else {
-            return None;
         }
-        
+        None
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    #[test]
+    fn iterates_across_a_big_gap() {
+        const LENGTH: usize = 100_000;
+        let mut arena = Arena::<usize>::from_iter(LENGTH, 0..LENGTH);
+        for i in 1..LENGTH - 1 {
+            arena.destroy(ArenaHandle::new(i)).unwrap();
+        }
+        let left = arena.iter().map(|handle| *arena.read_lock(handle).unwrap()).collect::<Vec<_>>();
+        assert_eq!(left, vec![0, LENGTH - 1]);
+    }
 
-/*
-#[derive(Debug)]
-struct Test<'a> {
-    a: &'a str,
-    b: i32,
-}
-
-
+    #[test]
+    fn full_arena_refuses_new_objects() {
+        let mut arena = Arena::<u32>::new(4);
+        for i in 0..4 {
+            assert_eq!(arena.remaining_capacity(), 4 - i);
+            arena.create(i as u32).unwrap();
+        }
+        assert!(arena.is_full());
+        assert_eq!(arena.remaining_capacity(), 0);
+        assert!(matches!(arena.create(99), Err(ArenaError::OutOfMemory)));
+        // nothing was overwritten by the failed create
+        assert_eq!(arena.iter().map(|handle| *arena.read_lock(handle).unwrap()).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
 
-#[test]
-fn main() {
-    let t = Test {a: "hello", b:1};
-    //std::fmt::Debug::fmt(t);
+    #[test]
+    fn create_wraps_around_to_freed_slots() {
+        let mut arena = Arena::<u32>::new(4);
+        let handles = (0..4).map(|i| arena.create(i).unwrap()).collect::<Vec<_>>();
+        arena.destroy(handles[1]).unwrap();
+        arena.destroy(handles[3]).unwrap();
+        // the search starts at the last freed slot and wraps back round to the other one
+        let a = arena.create(10).unwrap();
+        let b = arena.create(11).unwrap();
+        assert!(a == handles[3] && b == handles[1]);
+        assert!(arena.is_full());
+    }
 
-    let mut a = Arena::<Test>::new(3);
-    a.create(Test {
-        a: "hello world 1",
-        b: -5,
-    }).unwrap();
-    a.create(Test {
-        a: "hello world 2",
-        b: -3,
-    }).unwrap();
-    a.create(Test {
-        a: "hello world 3",
-        b: -1,
-    }).unwrap();
+    #[test]
+    fn destroying_twice_is_an_error_not_a_double_drop() {
+        let counter = std::sync::Arc::new(());
+        let mut arena = Arena::<std::sync::Arc<()>>::new(4);
+        let handle = arena.create(counter.clone()).unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&counter), 2);
 
-    a.destroy(0);
-    a.create(Test {
-        a: "hello world 8",
-        b: 100,
-    }).unwrap();
+        arena.destroy(handle).unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&counter), 1);
+        assert!(matches!(arena.destroy(handle), Err(ArenaError::DoesNotExist)));
+        assert_eq!(std::sync::Arc::strong_count(&counter), 1);
+        assert_eq!(arena.count, 0);
+    }
 
-    a.obtain(2).unwrap().write().unwrap().b = 7;
-    a.destroy(1);
+    #[test]
+    fn destroy_checks_bounds() {
+        let mut arena = Arena::<u32>::new(4);
+        assert!(matches!(arena.destroy(ArenaHandle::new(4)), Err(ArenaError::BoundsExceeded)));
+        assert!(matches!(arena.destroy(ArenaHandle::new(0)), Err(ArenaError::DoesNotExist)));
+    }
 
-    for obj in a.iter() {
-        println!("{:?}", obj);
+    #[test]
+    fn dropping_the_arena_drops_what_is_left_once() {
+        let counter = std::sync::Arc::new(());
+        let mut arena = Arena::<std::sync::Arc<()>>::new(8);
+        let handles = (0..5).map(|_| arena.create(counter.clone()).unwrap()).collect::<Vec<_>>();
+        arena.destroy(handles[2]).unwrap();
+        assert_eq!(std::sync::Arc::strong_count(&counter), 5);
+        drop(arena);
+        assert_eq!(std::sync::Arc::strong_count(&counter), 1);
     }
 
-    println!("{:?}", a);
+    #[test]
+    fn iterates_nothing_when_empty() {
+        let arena = Arena::<usize>::new(16);
+        assert_eq!(arena.iter().count(), 0);
+    }
 }
-*/
\ No newline at end of file
@@ -21,8 +21,11 @@ pub struct MemoryBlock<T> {
 impl<T> Drop for MemoryBlock<T> {
     fn drop(&mut self) {
         unsafe {
+            // empty slots were never written, so there's nothing in them to drop
             for i in 0..self.length {
-                std::ptr::drop_in_place(self.memory.add(i));
+                if self.is_allocated(i) {
+                    std::ptr::drop_in_place(self.memory.add(i));
+                }
             }
             dealloc(self.memory as *mut u8, self.layout_memory);
             dealloc(self.allocated as *mut u8, self.layout_allocated);
@@ -36,7 +39,7 @@ impl<T> MemoryBlock<T> {
         let layout_memory = Layout::from_size_align(size_of::<T>()*length, align_of::<T>()).unwrap();
         let layout_allocated = Layout::from_size_align(size_of::<bool>()*length, align_of::<bool>()).unwrap();
 
-        println!("Block allocation: {:?} bytes, {:?} objects", layout_memory.size(), layout_allocated.size());
+        log::debug!("Block allocation: {:?} bytes, {:?} objects", layout_memory.size(), layout_allocated.size());
 
         unsafe {
             let ptr_memory = alloc(layout_memory);
@@ -85,6 +88,13 @@ impl<T> MemoryBlock<T> {
         }
         //Ok(())
     }
+    // move the object out and leave the slot empty, without dropping it
+    pub unsafe fn take(&mut self, index: usize) -> T {
+        unsafe {
+            self.allocated.add(index).write(false);
+            self.memory.add(index).read()
+        }
+    }
     // YOU MUST CALL DROP IF OVERWRITING SOMETHING
     pub unsafe fn write(&mut self, index: usize, object: T) {
         //self.bounds_check(index)?;
@@ -111,7 +121,55 @@ impl<T> MemoryBlock<T> {
     pub unsafe fn as_slice(&self) -> &[T] {
         unsafe {std::slice::from_raw_parts(self.memory, self.length)}
     }
+    // number of slots in use
+    pub fn count_allocated(&self) -> usize {
+        unsafe {std::slice::from_raw_parts(self.allocated, self.length)}.iter().filter(|a| **a).count()
+    }
     pub fn iter(&self) -> impl Iterator<Item = &T> {
         unsafe{self.as_slice().iter().enumerate().filter(|(i, _)| self.is_allocated(*i)).map(|(_, x)| x)}
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn take_moves_out_without_dropping() {
+        let counter = Arc::new(());
+        let mut block = MemoryBlock::<Arc<()>>::new(4);
+        unsafe {
+            block.write(1, counter.clone());
+            assert!(block.is_allocated(1));
+            let taken = block.take(1);
+            assert!(!block.is_allocated(1));
+            // still alive, just owned by us now
+            assert_eq!(Arc::strong_count(&counter), 2);
+            drop(taken);
+        }
+        assert_eq!(Arc::strong_count(&counter), 1);
+        // and the block won't drop it again on the way out
+        drop(block);
+        assert_eq!(Arc::strong_count(&counter), 1);
+    }
+
+    #[test]
+    fn count_allocated_tracks_writes_and_drops() {
+        let mut block = MemoryBlock::<u64>::new(8);
+        assert_eq!(block.count_allocated(), 0);
+        unsafe {
+            block.write(0, 10);
+            block.write(5, 15);
+            block.write(7, 17);
+            assert_eq!(block.count_allocated(), 3);
+            block.drop(5);
+            assert_eq!(block.count_allocated(), 2);
+            block.take(0);
+        }
+        assert_eq!(block.count_allocated(), 1);
+        assert_eq!(block.iter().copied().collect::<Vec<_>>(), vec![17]);
+        assert!(matches!(block.get_ptr(8), Err(MemoryError::BoundsExceeded)));
+        assert!(matches!(block.get_ptr(5), Err(MemoryError::DoesNotExist)));
+    }
+}
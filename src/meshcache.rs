@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use wgpu::util::DeviceExt;
+use crate::geometry::Vertex;
+use crate::block::RenderLayer;
+
+// a chunk mesh living on the gpu. vertices are chunk-local so any chunk with the same blocks can draw the same one
+#[derive(Debug)]
+pub struct GpuMesh {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub index_count: u32,
+    pub index_format: wgpu::IndexFormat,
+    // quads per render layer, in order. kept with the buffers since a chunk's cpu mesh can run ahead of what's uploaded
+    pub layer_quads: [u32; RenderLayer::COUNT],
+}
+
+impl GpuMesh {
+    pub fn new(device: &wgpu::Device, vertices: &[Vertex], indices: &[u32]) -> Self {
+        let vertex_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Vertex Buffer"),
+                contents: bytemuck::cast_slice(vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            }
+        );
+        let index_format = Self::index_format_for(vertices.len());
+        // most chunks have well under 65536 vertices, so halve the index buffer when we can
+        let indices_u16: Vec<u16>;
+        let contents: &[u8] = match index_format {
+            wgpu::IndexFormat::Uint16 => {
+                indices_u16 = Self::narrow_indices(indices);
+                bytemuck::cast_slice(&indices_u16)
+            },
+            wgpu::IndexFormat::Uint32 => bytemuck::cast_slice(indices),
+        };
+        let index_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Index Buffer"),
+                contents,
+                usage: wgpu::BufferUsages::INDEX,
+            }
+        );
+        Self {
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            index_format,
+            layer_quads: [indices.len() as u32 / 6, 0, 0],
+        }
+    }
+
+    // everything counts as opaque until told otherwise
+    pub fn with_layer_quads(mut self, layer_quads: [u32; RenderLayer::COUNT]) -> Self {
+        self.layer_quads = layer_quads;
+        self
+    }
+
+    // the part of the index buffer that draws this layer
+    pub fn layer_indices(&self, layer: RenderLayer) -> std::ops::Range<u32> {
+        let start = self.layer_quads[..layer as usize].iter().sum::<u32>() * 6;
+        start..start + self.layer_quads[layer as usize] * 6
+    }
+
+    // the smallest index format that can address every vertex in the mesh
+    pub fn index_format_for(vertex_count: usize) -> wgpu::IndexFormat {
+        if vertex_count <= u16::MAX as usize + 1 {
+            wgpu::IndexFormat::Uint16
+        } else {
+            wgpu::IndexFormat::Uint32
+        }
+    }
+
+    // only valid when index_format_for picked Uint16
+    pub fn narrow_indices(indices: &[u32]) -> Vec<u16> {
+        indices.iter().map(|&i| i as u16).collect()
+    }
+}
+
+// uploaded meshes keyed by a hash of the blocks that made them (Chunk::mesh_key)
+// chunks hold the Arcs, the cache only holds Weaks, so a mesh is freed as soon as the last chunk using it lets go
+// (i.e. it was edited, remeshed into something else, or unloaded)
+pub struct MeshCache {
+    entries: HashMap<u64, Weak<GpuMesh>>,
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl MeshCache {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    // hands back the mesh already uploaded for `key` if some chunk still uses it, otherwise uploads a new one with `upload`
+    pub fn get_or_upload(&mut self, key: u64, upload: impl FnOnce() -> GpuMesh) -> Arc<GpuMesh> {
+        if let Some(mesh) = self.entries.get(&key).and_then(Weak::upgrade) {
+            self.hits += 1;
+            return mesh;
+        }
+        self.misses += 1;
+        let mesh = Arc::new(upload());
+        self.entries.insert(key, Arc::downgrade(&mesh));
+        mesh
+    }
+
+    // forget entries nobody is drawing anymore
+    pub fn prune(&mut self) {
+        self.entries.retain(|_, mesh| mesh.strong_count() > 0);
+    }
+
+    // meshes are built from the block properties too, so anything cached is stale once those change
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 { 0.0 } else { self.hits as f32 / total as f32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::geometry::CUBE;
+
+    #[test]
+    fn index_format_boundary() {
+        assert_eq!(GpuMesh::index_format_for(0), wgpu::IndexFormat::Uint16);
+        assert_eq!(GpuMesh::index_format_for(u16::MAX as usize + 1), wgpu::IndexFormat::Uint16);
+        assert_eq!(GpuMesh::index_format_for(u16::MAX as usize + 2), wgpu::IndexFormat::Uint32);
+    }
+
+    #[test]
+    fn small_chunk_uses_u16_indices() {
+        let mut chunk = Chunk::new(0.0, 0.0, 0.0);
+        chunk.mesh = CUBE.to_vec();
+        let indices = chunk.get_indices(0);
+        assert_eq!(indices.len(), 36);
+        assert_eq!(&indices[..12], &[0, 1, 2, 2, 3, 0, 4, 5, 6, 6, 7, 4]);
+        assert_eq!(*indices.iter().max().unwrap(), 23);
+
+        assert_eq!(GpuMesh::index_format_for(chunk.mesh.len()), wgpu::IndexFormat::Uint16);
+        let narrowed = GpuMesh::narrow_indices(&indices);
+        assert!(narrowed.iter().zip(&indices).all(|(&a, &b)| a as u32 == b));
+    }
+}
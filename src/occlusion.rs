@@ -0,0 +1,256 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU8, Ordering};
+use wgpu::util::DeviceExt;
+use wgpu::include_wgsl;
+use glam::Vec3;
+use crate::chunk::CHUNK_SIZE_F;
+use crate::geometry::ChunkInstance;
+
+// compiled in, but only switched on with `--features occlusion-culling` since it shows chunks a frame or two late
+pub const ENABLED: bool = cfg!(feature = "occlusion-culling");
+
+// what the map_async callback reports back through readback_status
+const MAP_PENDING: u8 = 0;
+const MAP_OK: u8 = 1;
+const MAP_FAILED: u8 = 2;
+
+#[derive(PartialEq)]
+enum Readback {
+    Idle,
+    Copied, // results copied into readback_buffer this frame, map it once the frame is submitted
+    Mapping,
+}
+
+// hardware occlusion culling for chunks.
+// every drawn chunk is wrapped in a query; chunks that came back with zero samples stop being drawn and instead get their
+// bounding box tested (invisibly) each frame until it shows up again. results are read back whenever the gpu is done with them
+// and never waited on, so visibility lags a frame or so behind instead of stalling the render loop
+pub struct OcclusionCuller {
+    pub query_set: wgpu::QuerySet,
+    capacity: u32,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    readback: Readback,
+    readback_status: Arc<AtomicU8>,
+    // chunk origins in query order, for this frame and for the frame being read back
+    queried: Vec<[i32; 3]>,
+    in_flight: Vec<[i32; 3]>,
+    occluded: HashSet<[i32; 3]>,
+
+    box_pipeline: wgpu::RenderPipeline,
+    box_vertex_buffer: wgpu::Buffer,
+    box_vertex_count: u32,
+}
+
+impl OcclusionCuller {
+    // capacity is the most chunks that get queried in one frame; anything past it is just drawn normally
+    pub fn new(device: &wgpu::Device, capacity: u32, frame_data_bind_group_layout: &wgpu::BindGroupLayout, surface_format: wgpu::TextureFormat, depth_stencil_state: Option<wgpu::DepthStencilState>) -> Self {
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Chunk Occlusion Queries"),
+            ty: wgpu::QueryType::Occlusion,
+            count: capacity,
+        });
+        let results_size = capacity as u64 * std::mem::size_of::<u64>() as u64;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Resolve Buffer"),
+            size: results_size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Occlusion Readback Buffer"),
+            size: results_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let box_vertices = Self::chunk_box_vertices();
+        let box_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Chunk Box Vertex Buffer"),
+            contents: bytemuck::cast_slice(&box_vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("occlusion.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Occlusion Pipeline Layout"),
+            bind_group_layouts: &[frame_data_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // test against the depth of what's already drawn but don't touch it
+        let depth_stencil = depth_stencil_state.map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            ..state
+        });
+        let box_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Occlusion Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    ChunkInstance::desc(),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: surface_format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::empty(),
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // no culling so the box still counts when the camera is inside it
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            query_set,
+            capacity,
+            resolve_buffer,
+            readback_buffer,
+            readback: Readback::Idle,
+            readback_status: Arc::new(AtomicU8::new(MAP_PENDING)),
+            queried: vec![],
+            in_flight: vec![],
+            occluded: HashSet::new(),
+
+            box_pipeline,
+            box_vertex_buffer,
+            box_vertex_count: box_vertices.len() as u32,
+        }
+    }
+
+    // 12 triangles covering a whole chunk, relative to its origin
+    fn chunk_box_vertices() -> Vec<[f32; 3]> {
+        // corners are numbered by which axes are at max (bit 0 x, bit 1 y, bit 2 z)
+        let corner = |i: usize| [
+            if i & 1 == 0 { 0.0 } else { CHUNK_SIZE_F },
+            if i & 2 == 0 { 0.0 } else { CHUNK_SIZE_F },
+            if i & 4 == 0 { 0.0 } else { CHUNK_SIZE_F },
+        ];
+        const QUADS: [[usize; 4]; 6] = [
+            [0, 2, 6, 4], [1, 3, 7, 5], // -x +x
+            [0, 1, 5, 4], [2, 3, 7, 6], // -y +y
+            [0, 1, 3, 2], [4, 5, 7, 6], // -z +z
+        ];
+        QUADS.iter().flat_map(|[a, b, c, d]| [*a, *b, *c, *a, *c, *d]).map(corner).collect()
+    }
+
+    fn key(origin: Vec3) -> [i32; 3] {
+        origin.as_ivec3().to_array()
+    }
+
+    pub fn is_occluded(&self, origin: Vec3) -> bool {
+        self.occluded.contains(&Self::key(origin))
+    }
+
+    // pick up whatever results have arrived. call at the start of the frame, before anything asks is_occluded
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        self.queried.clear();
+        if self.readback != Readback::Mapping {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        match self.readback_status.swap(MAP_PENDING, Ordering::AcqRel) {
+            MAP_PENDING => return, // still in flight, keep going on the old results
+            MAP_FAILED => {
+                log::warn!("Couldn't read back occlusion query results");
+                self.readback = Readback::Idle;
+                return;
+            }
+            _ => (),
+        }
+
+        let results_size = self.in_flight.len() as u64 * std::mem::size_of::<u64>() as u64;
+        {
+            let data = self.readback_buffer.slice(..results_size).get_mapped_range();
+            let samples: &[u64] = bytemuck::cast_slice(&data);
+            for (origin, passed) in self.in_flight.iter().zip(samples) {
+                if *passed == 0 {
+                    self.occluded.insert(*origin);
+                } else {
+                    self.occluded.remove(origin);
+                }
+            }
+        }
+        self.readback_buffer.unmap();
+        self.readback = Readback::Idle;
+    }
+
+    // the query index to wrap this chunk's draw in, if there's room left this frame
+    pub fn next_query(&mut self, origin: Vec3) -> Option<u32> {
+        if self.queried.len() as u32 >= self.capacity {
+            return None;
+        }
+        self.queried.push(Self::key(origin));
+        Some(self.queried.len() as u32 - 1)
+    }
+
+    // test the boxes of chunks we skipped because they were hidden. `instances` are their indices into the chunk instance
+    // buffer, which must already be bound at slot 1. draw these after all the real geometry so they're tested against it
+    pub fn draw_hidden_boxes(&mut self, render_pass: &mut wgpu::RenderPass, hidden: &[(Vec3, u32)]) {
+        if hidden.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.box_pipeline);
+        render_pass.set_vertex_buffer(0, self.box_vertex_buffer.slice(..));
+        for (origin, instance) in hidden {
+            let Some(query) = self.next_query(*origin) else { break };
+            render_pass.begin_occlusion_query(query);
+            render_pass.draw(0..self.box_vertex_count, *instance..*instance+1);
+            render_pass.end_occlusion_query();
+        }
+    }
+
+    // copy this frame's results out, unless the last batch is still being read back (then this frame's are just dropped)
+    pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        if self.readback != Readback::Idle || self.queried.is_empty() {
+            return;
+        }
+        let count = self.queried.len() as u32;
+        let results_size = count as u64 * std::mem::size_of::<u64>() as u64;
+        encoder.resolve_query_set(&self.query_set, 0..count, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(&self.resolve_buffer, 0, &self.readback_buffer, 0, results_size);
+        self.in_flight = std::mem::take(&mut self.queried);
+        self.readback = Readback::Copied;
+    }
+
+    // start mapping the results copied by resolve(). call right after the frame is submitted
+    pub fn after_submit(&mut self) {
+        if self.readback != Readback::Copied {
+            return;
+        }
+        let results_size = self.in_flight.len() as u64 * std::mem::size_of::<u64>() as u64;
+        let status = self.readback_status.clone();
+        self.readback_buffer.slice(..results_size).map_async(wgpu::MapMode::Read, move |result| {
+            status.store(if result.is_ok() { MAP_OK } else { MAP_FAILED }, Ordering::Release);
+        });
+        self.readback = Readback::Mapping;
+    }
+}
@@ -0,0 +1,16 @@
+// chunk bounding boxes for occlusion queries. nothing is actually written, we only care whether any sample passes the depth test
+
+struct FrameData {
+    projview: mat4x4<f32>,
+};
+@group(0) @binding(0) var<uniform> frame_data: FrameData;
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, @location(1) origin: vec3<f32>) -> @builtin(position) vec4<f32> {
+    return frame_data.projview*vec4<f32>(origin + position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(0.0);
+}
@@ -1,8 +1,9 @@
 use crate::block;
-use std::path::PathBuf;
 use crate::texturing;
 use wgpu::BufferDescriptor;
 use crate::world;
+use crate::chunk;
+use crate::occlusion;
 use crate::geometry;
 use crate::camera;
 use glam::{Vec3A, Mat4};
@@ -10,19 +11,55 @@ use wgpu::include_wgsl;
 use wgpu::util::DeviceExt;
 use std::sync::Arc;
 use wgpu::PresentMode;
-use crate::geometry::Vertex;
+use crate::geometry::{Vertex, ChunkInstance, LineVertex, Vertex2D, Facing};
+use crate::meshcache::GpuMesh;
+use crate::block::{BlockID, RenderLayer};
+use crate::settings::TextureFilter;
+use log::{debug, info, warn};
+
+// COLOR SPACES
+// - block textures are Rgba8UnormSrgb, so sampling them in main.wgsl hands back linear values
+// - everything the shaders do (shading, fading) is in linear space
+// - we always render into an sRGB view of the surface, so the gpu encodes linear -> sRGB on the way out
+//   (when the surface itself isn't sRGB we ask for an sRGB view of it instead of doing it in the shader)
+// - colors we write down by hand (world.sky_color, text colors) are sRGB like any color picker gives you;
+//   the sky is converted with srgb_to_linear before it's used as the clear color, glyphon does the text itself (ColorMode::Accurate)
+
+// the standard sRGB transfer curve, for turning a hand-picked color into what the shaders work in
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct FrameData {
-    projview: [[f32; 4]; 4]
+    projview: [[f32; 4]; 4],
+    // the way sunlight travels, xyz. w is padding (vec3s take up 16 bytes in a uniform anyway)
+    sun_dir: [f32; 4],
 }
 
+// chunk border overlay colors
+const CHUNK_BORDER_BUSY: [f32; 3] = [1.0, 0.2, 0.2]; // locked for writing, i.e. generating or meshing
+const CHUNK_BORDER_PENDING: [f32; 3] = [1.0, 0.9, 0.2]; // meshed, waiting on upload
+const CHUNK_BORDER_READY: [f32; 3] = [0.2, 1.0, 0.3];
+const RENDER_DISTANCE_BORDER: [f32; 3] = [1.0, 1.0, 1.0];
+// crosshair arm length from the center and line thickness, in pixels at a ui_scale of 1
+const CROSSHAIR_SIZE_PX: f32 = 10.0;
+const CROSSHAIR_THICKNESS_PX: f32 = 2.0;
+// two quads, as triangles
+const CROSSHAIR_VERTEX_COUNT: usize = 12;
+
 
 struct TextObject {
     buffer: glyphon::Buffer,
     x: f32,
     y: f32,
+    // hidden objects keep their text and index, they just aren't drawn
+    visible: bool,
 }
 impl TextObject {
     pub fn new(tm: &mut TextManager, font_size: f32, x: f32, y: f32) -> Self {
@@ -36,12 +73,12 @@ impl TextObject {
         Self {
             buffer,
             x,
-            y
+            y,
+            visible: true,
         }
     }
 
     pub fn get_text_area(&self, tm: &TextManager) -> glyphon::TextArea {
-        //println!("{:?}", tm.ui_scale);
         glyphon::TextArea {
             buffer: &self.buffer,
             left: self.x,
@@ -57,8 +94,15 @@ impl TextObject {
         }
     }
 }
+const FONT_DIR: &str = "assets/fonts/";
+// used when assets/fonts/ is missing or empty so the HUD still renders
+const FALLBACK_FONT: &[u8] = include_bytes!("../assets/fonts/BigBlueTermPlusNerdFontMono-Regular.ttf");
+// the font assets/fonts/ ships with, also the default for ui.font_family in settings.toml
+pub const DEFAULT_FONT_FAMILY: &str = "BigBlueTermPlus Nerd Font Mono";
+
 pub struct TextManager {
     font_system: glyphon::FontSystem,
+    font_family: String,
     swash_cache: glyphon::SwashCache,
     //cache: glyphon::Cache,
     viewport: glyphon::Viewport,
@@ -71,23 +115,33 @@ pub struct TextManager {
     text_objects: Vec<TextObject>
 }
 impl TextManager {
+    // `surface_format` has to be the sRGB format we actually render into, see the color space notes at the top
     pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, surface_format: wgpu::TextureFormat, screen_size: winit::dpi::PhysicalSize<u32>, depth_stencil: Option<wgpu::DepthStencilState>) -> Self {
-        //println!("{:?}",
-        //    std::fs::read_dir("assets/fonts/").unwrap().map(|path| path.unwrap().path()).collect::<Vec<PathBuf>>()
-        //);
-
-        let fonts_to_load = std::fs::read_dir("assets/fonts/").unwrap().map(|path| glyphon::cosmic_text::fontdb::Source::File(path.unwrap().path()));
+        use glyphon::cosmic_text::fontdb::Source;
+        let mut fonts_to_load: Vec<Source> = match std::fs::read_dir(FONT_DIR) {
+            Ok(entries) => entries.filter_map(|entry| entry.ok()).map(|entry| Source::File(entry.path())).collect(),
+            Err(e) => {
+                warn!("Couldn't read {} ({})", FONT_DIR, e);
+                vec![]
+            }
+        };
+        if fonts_to_load.is_empty() {
+            warn!("No fonts found in {}, using the built-in font", FONT_DIR);
+            fonts_to_load.push(Source::Binary(Arc::new(FALLBACK_FONT)));
+        }
         let font_system = glyphon::FontSystem::new_with_fonts(fonts_to_load);
+        let asset_families = Self::asset_font_families(&font_system);
+        debug!("Loaded fonts from {}: {:?}", FONT_DIR, asset_families);
+        let font_family = Self::resolve_font_family(&font_system, DEFAULT_FONT_FAMILY);
         let swash_cache = glyphon::SwashCache::new();
         let cache = glyphon::Cache::new(device);
         let viewport = glyphon::Viewport::new(device, &cache);
         let mut atlas = glyphon::TextAtlas::with_color_mode(device, queue, &cache, surface_format, glyphon::ColorMode::Accurate);
         let text_renderer = glyphon::TextRenderer::new(&mut atlas, device, wgpu::MultisampleState::default(), depth_stencil);
 
-        //println!("{:?}", font_system.get_font_matches(glyphon::Attrs::new().family(glyphon::Family::Name("asjkdhgasjdhgas"))));
-
         Self {
             font_system,
+            font_family,
             swash_cache,
             //cache,
             viewport,
@@ -101,15 +155,52 @@ impl TextManager {
         }
     }
 
+    // families of the fonts we loaded ourselves, as opposed to system fonts the font system picks up on its own
+    fn asset_font_families(font_system: &glyphon::FontSystem) -> Vec<String> {
+        font_system.db().faces()
+            .filter(|face| matches!(&face.source, glyphon::cosmic_text::fontdb::Source::File(path) if path.starts_with(FONT_DIR)))
+            .filter_map(|face| face.families.first().map(|(name, _)| name.clone()))
+            .collect()
+    }
+
+    // the requested family if it's loaded, otherwise the first font from assets/fonts/ (or any font at all) so text never silently fails to shape
+    fn resolve_font_family(font_system: &glyphon::FontSystem, family: &str) -> String {
+        if font_system.db().faces().any(|face| face.families.iter().any(|(name, _)| name == family)) {
+            return family.to_string();
+        }
+        let fallback = Self::asset_font_families(font_system).into_iter().next()
+            .or_else(|| font_system.db().faces().find_map(|face| face.families.first().map(|(name, _)| name.clone())));
+        match fallback {
+            Some(fallback) => {
+                warn!("Font family \"{}\" isn't loaded, falling back to \"{}\"", family, fallback);
+                fallback
+            }
+            None => {
+                warn!("No fonts are loaded, text won't render");
+                family.to_string()
+            }
+        }
+    }
+
+    pub fn set_font_family(&mut self, family: &str) {
+        self.font_family = Self::resolve_font_family(&self.font_system, family);
+    }
+    pub fn font_family(&self) -> &str {
+        &self.font_family
+    }
+
     pub fn new_text_object(&mut self, font_size: f32, x: f32, y: f32){
         let to = TextObject::new(self, font_size, x, y);
         self.text_objects.push(to);
     }
     pub fn set_text_on(&mut self, index: usize, text: &str) {
         let to = &mut self.text_objects[index];
-        to.buffer.set_text(&mut self.font_system, text, glyphon::Attrs::new().family(glyphon::Family::Name("BigBlueTermPlus Nerd Font Mono")), glyphon::Shaping::Basic);
+        to.buffer.set_text(&mut self.font_system, text, glyphon::Attrs::new().family(glyphon::Family::Name(&self.font_family)), glyphon::Shaping::Basic);
         to.buffer.shape_until_scroll(&mut self.font_system, false);
     }
+    pub fn set_visible_on(&mut self, index: usize, visible: bool) {
+        self.text_objects[index].visible = visible;
+    }
 
     pub fn on_resize(&mut self, screen_size: winit::dpi::PhysicalSize<u32>, ui_scale: f32) {
         self.screen_size = (screen_size.width as f32, screen_size.height as f32);
@@ -124,7 +215,7 @@ impl TextManager {
     }
 
     pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
-        let text_areas = self.text_objects.iter().map(|tobj| tobj.get_text_area(&self)).collect::<Vec<glyphon::TextArea>>();
+        let text_areas = self.text_objects.iter().filter(|tobj| tobj.visible).map(|tobj| tobj.get_text_area(&self)).collect::<Vec<glyphon::TextArea>>();
         self.text_renderer
             .prepare(
                 device,
@@ -150,28 +241,61 @@ pub struct Renderer<'a> {
     window: Arc<winit::window::Window>,
     surface: wgpu::Surface<'a>,
     surface_config: wgpu::SurfaceConfiguration,
+    // the sRGB format pipelines target; surface_config.format or an sRGB view of it
+    render_format: wgpu::TextureFormat,
     pub size: winit::dpi::PhysicalSize<u32>,
     pub aspect_ratio: f32,
     pub window_center_px: winit::dpi::PhysicalPosition<u32>,
     pub ui_scale: f32,
     pub ui_scale_manual_adjust: f32,
+    // the window's dpi scale, which changes when it's dragged to a different monitor
+    pub scale_factor: f32,
 
     pub camera: camera::Camera,
+    // seconds new chunks take to dither in instead of popping, 0 to turn it off
+    pub chunk_fade_time: f32,
 
     // for main 3d rendering, not ui stuff (that will be in UILayers)
+    // group 0 is frame data, group 1 is whichever texture set the current draw uses (see main.wgsl)
     pub pipeline: Option<wgpu::RenderPipeline>,
+    // the same shader for the other render layers, see block::RenderLayer
+    cutout_pipeline: Option<wgpu::RenderPipeline>,
+    translucent_pipeline: Option<wgpu::RenderPipeline>,
     pub shader: wgpu::ShaderModule,
     pub index_buffer: Option<wgpu::Buffer>,
     index_counts: Vec<u32>,
+    // see-through preview of the block you're about to place: where, and which block. set every frame
+    pub placement_ghost: Option<(glam::Vec3, BlockID)>,
+    pub show_placement_ghost: bool,
+    ghost_pipeline: Option<wgpu::RenderPipeline>,
+    // rebuilt only when the block changes, or its tex ids might have (see reload_texture_set)
+    ghost_mesh: Option<(BlockID, GpuMesh)>,
+    ghost_instance_buffer: wgpu::Buffer,
+    // debug overlays. nothing extra is built or drawn while they're off
+    pub show_chunk_borders: bool,
+    debug_line_shader: wgpu::ShaderModule,
+    debug_line_pipeline: Option<wgpu::RenderPipeline>,
+    // drawn over the scene but under the text
+    pub show_crosshair: bool,
+    crosshair_shader: wgpu::ShaderModule,
+    crosshair_pipeline: Option<wgpu::RenderPipeline>,
+    // rewritten every frame in render(), it's tiny
+    crosshair_buffer: wgpu::Buffer,
     depth_texture_view: wgpu::TextureView,
     depth_texture_sampler: wgpu::Sampler,
     depth_stencil_state: Option<wgpu::DepthStencilState>,
     frame_data_buffer: wgpu::Buffer,
     frame_data_bind_group: wgpu::BindGroup,
     frame_data_bind_group_layout: wgpu::BindGroupLayout,
+    chunk_instance_buffer: wgpu::Buffer,
+    chunk_instance_capacity: usize,
+    // only with the occlusion-culling feature
+    occlusion: Option<occlusion::OcclusionCuller>,
 
     texture_bind_group_layout: wgpu::BindGroupLayout,
     texture_sets: Vec<texturing::TextureSet>,
+    // what sets are loaded with, change it through set_texture_filter
+    texture_filter: TextureFilter,
 
     pub text_manager: TextManager,
     //debug_text: TextObject,
@@ -197,7 +321,7 @@ impl<'a> Renderer<'a> {
             },
         ).await.expect("Failed to get adapter");
 
-        println!("Using backend {}", adapter.get_info().backend.to_str().to_uppercase());
+        info!("Using backend {}", adapter.get_info().backend.to_str().to_uppercase());
 
         let required_features = wgpu::Features::default();//wgpu::Features::CONSERVATIVE_RASTERIZATION;
 
@@ -212,6 +336,11 @@ impl<'a> Renderer<'a> {
 
         let surface_capabilities = surface.get_capabilities(&adapter);
         let surface_format = surface_capabilities.formats.iter().find(|f| f.is_srgb()).copied().unwrap_or(surface_capabilities.formats[0]);
+        // no sRGB surface on offer (some backends), so draw through an sRGB view of a plain one
+        let render_format = surface_format.add_srgb_suffix();
+        if render_format != surface_format {
+            info!("Surface format {:?} isn't sRGB, rendering through a {:?} view", surface_format, render_format);
+        }
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
             format: surface_format,
@@ -219,7 +348,7 @@ impl<'a> Renderer<'a> {
             height: size.height,
             present_mode: PresentMode::AutoVsync,
             alpha_mode: surface_capabilities.alpha_modes[0],
-            view_formats: vec![],
+            view_formats: if render_format != surface_format { vec![render_format] } else { vec![] },
             desired_maximum_frame_latency: 2,
         };
 
@@ -300,7 +429,8 @@ impl<'a> Renderer<'a> {
             entries: &[
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    // the fragment stage reads the sun direction
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -323,46 +453,90 @@ impl<'a> Renderer<'a> {
         });
 
 
+        // one ChunkInstance per drawn chunk, rewritten every frame. grows on demand in render()
+        let chunk_instance_capacity = 1024;
+        let chunk_instance_buffer = Self::create_chunk_instance_buffer(&device, chunk_instance_capacity);
+        let ghost_instance_buffer = Self::create_chunk_instance_buffer(&device, 1);
+
         let shader = device.create_shader_module(include_wgsl!("main.wgsl"));
+        let occlusion = if occlusion::ENABLED {
+            // every chunk in the render cube gets a query at the default distance. further out, the extra chunks are just drawn
+            let max_chunks = (2*world::DEFAULT_RENDER_DISTANCE+1).pow(3) as u32;
+            Some(occlusion::OcclusionCuller::new(&device, max_chunks, &frame_data_bind_group_layout, render_format, depth_stencil_state.clone()))
+        } else {
+            None
+        };
+        let debug_line_shader = device.create_shader_module(include_wgsl!("debug_lines.wgsl"));
+        let crosshair_shader = device.create_shader_module(include_wgsl!("crosshair.wgsl"));
+        let crosshair_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Crosshair Vertex Buffer"),
+            size: (CROSSHAIR_VERTEX_COUNT * std::mem::size_of::<Vertex2D>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         let texture_bind_group_layout = device.create_bind_group_layout(&texturing::TEXTURE_SET_LAYOUT_DESC);
 
         //let pipeline = Self::create_main_pipeline(&device, &shader, &pipeline_layout, &surface_config);
-        println!("Loading fonts...");
-        let mut text_manager = TextManager::new(&device, &queue, surface_format, size, depth_stencil_state.clone());
+        debug!("Loading fonts...");
+        let mut text_manager = TextManager::new(&device, &queue, render_format, size, depth_stencil_state.clone());
         text_manager.new_text_object(12.0, 10.0, 10.0);
 
-        Self {
+        let mut renderer = Self {
             device,
             queue,
 
             window: window.clone(),
             surface,
             surface_config,
+            render_format,
             size,
             aspect_ratio,
             window_center_px: winit::dpi::PhysicalPosition::new(size.width/2, size.height/2),
-            ui_scale: size.height as f32 / 600.0,
+            ui_scale: 1.0,
             ui_scale_manual_adjust: 1.0,
+            scale_factor: window.scale_factor() as f32,
 
             camera: camera::Camera::new(aspect_ratio),
 
             pipeline: None,
+            cutout_pipeline: None,
+            translucent_pipeline: None,
             shader,
             index_buffer: None,
             index_counts: vec![],
+            chunk_fade_time: 0.5,
+            placement_ghost: None,
+            show_placement_ghost: true,
+            ghost_pipeline: None,
+            ghost_mesh: None,
+            ghost_instance_buffer,
+            show_chunk_borders: false,
+            debug_line_shader,
+            debug_line_pipeline: None,
+            show_crosshair: true,
+            crosshair_shader,
+            crosshair_pipeline: None,
+            crosshair_buffer,
             depth_texture_view,
             depth_texture_sampler,
             depth_stencil_state,
             frame_data_buffer,
             frame_data_bind_group,
             frame_data_bind_group_layout,
+            chunk_instance_buffer,
+            chunk_instance_capacity,
+            occlusion,
 
             texture_bind_group_layout,
             texture_sets: vec![],
+            texture_filter: TextureFilter::Mixed,
 
             text_manager,
-        }
+        };
+        // otherwise text is laid out for the wrong size until the first resize event comes in
+        renderer.update_ui_scale();
+        renderer
     }
 
     pub fn push_indices(&mut self, indices: Vec<u32>, index_offsets: Vec<u32>) {
@@ -376,12 +550,31 @@ impl<'a> Renderer<'a> {
         self.index_counts = index_offsets;
     }
 
-    fn create_main_pipeline(&self) -> wgpu::RenderPipeline {
-        let mut bind_group_layouts: Vec<&wgpu::BindGroupLayout> = vec![];
-        bind_group_layouts.push(&self.frame_data_bind_group_layout);
-        for _ in 0..self.texture_sets.len() {
-            bind_group_layouts.push(&self.texture_bind_group_layout);
-        }
+    fn create_chunk_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(
+            &wgpu::BufferDescriptor {
+                label: Some("Chunk Instance Buffer"),
+                size: (capacity * std::mem::size_of::<ChunkInstance>()) as u64,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }
+        )
+    }
+
+    // cutout is opaque with holes punched in it (fs_cutout); translucent blends and leaves depth alone so it doesn't hide what's behind it
+    fn create_main_pipeline(&self, layer: RenderLayer) -> wgpu::RenderPipeline {
+        let (entry_point, blend, depth_write) = match layer {
+            RenderLayer::Opaque => ("fs_main", wgpu::BlendState::REPLACE, true),
+            RenderLayer::Cutout => ("fs_cutout", wgpu::BlendState::REPLACE, true),
+            RenderLayer::Translucent => ("fs_main", wgpu::BlendState::ALPHA_BLENDING, false),
+        };
+        let depth_stencil = self.depth_stencil_state.clone().map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: depth_write,
+            ..state
+        });
+
+        // main.wgsl only declares one texture group; the set is swapped per draw instead of having a group per set
+        let bind_group_layouts = [&self.frame_data_bind_group_layout, &self.texture_bind_group_layout];
 
         let pipeline_layout = self.device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
@@ -391,23 +584,21 @@ impl<'a> Renderer<'a> {
             }
         );        
 
-        //let vb = &(0..world::RENDER_VOLUME).map(|_| geometry::Vertex::desc()).collect::<Vec<_>>();
-        //println!("{:?}", vb.len());
         self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
+            label: Some(&format!("Render Pipeline ({:?})", layer)),
             layout: Some(&pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &self.shader,
                 entry_point: "vs_main", // 1.
-                buffers: &[geometry::Vertex::desc()], // 2.
+                buffers: &[geometry::Vertex::desc(), geometry::ChunkInstance::desc()], // 2.
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState { // 3.
                 module: &self.shader,
-                entry_point: "fs_main",
+                entry_point,
                 targets: &[Some(wgpu::ColorTargetState { // 4.
-                    format: self.surface_config.format,
-                    blend: Some(wgpu::BlendState::REPLACE),
+                    format: self.render_format,
+                    blend: Some(blend),
                     write_mask: wgpu::ColorWrites::ALL,
                 })],
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
@@ -424,7 +615,7 @@ impl<'a> Renderer<'a> {
                 // Requires Features::CONSERVATIVE_RASTERIZATION
                 conservative: false,
             },    
-            depth_stencil: self.depth_stencil_state.clone(), // 1.
+            depth_stencil, // 1.
             multisample: wgpu::MultisampleState {
                 count: 1, // 2.
                 mask: !0, // 3.
@@ -435,9 +626,340 @@ impl<'a> Renderer<'a> {
         })
     }
 
-    pub fn load_texture_set(&mut self, fp_vec: Vec<String>) {
-        println!("Loading texture set...");
-        self.texture_sets.push(texturing::TextureSet::from_fp_vec(&self.device, &self.queue, &self.texture_bind_group_layout, fp_vec))
+    // builds the main pipeline. call once the first texture set is loaded (render() needs something to bind at group 1)
+    pub fn build_pipeline(&mut self) {
+        self.pipeline = Some(self.create_main_pipeline(RenderLayer::Opaque));
+        self.cutout_pipeline = Some(self.create_main_pipeline(RenderLayer::Cutout));
+        self.translucent_pipeline = Some(self.create_main_pipeline(RenderLayer::Translucent));
+        self.ghost_pipeline = Some(self.create_ghost_pipeline());
+        self.debug_line_pipeline = Some(self.create_debug_line_pipeline());
+        self.crosshair_pipeline = Some(self.create_crosshair_pipeline());
+    }
+
+    // main.wgsl again, but see-through: blended over what's drawn and not writing depth so it never hides anything
+    fn create_ghost_pipeline(&self) -> wgpu::RenderPipeline {
+        let pipeline_layout = self.device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Ghost Pipeline Layout"),
+                bind_group_layouts: &[&self.frame_data_bind_group_layout, &self.texture_bind_group_layout],
+                push_constant_ranges: &[],
+            }
+        );
+        let depth_stencil = self.depth_stencil_state.clone().map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            ..state
+        });
+
+        self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Ghost Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[geometry::Vertex::desc(), geometry::ChunkInstance::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point: "fs_ghost",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.render_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // back faces would double up the alpha
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // one of the non-opaque layers of every chunk in `chunks` (instance index, chunk).
+    // the frame data and the chunk instance buffer (at slot 1) must already be bound
+    fn draw_chunk_layer(&self, render_pass: &mut wgpu::RenderPass, chunks: &[(u32, &chunk::Chunk)], layer: RenderLayer) {
+        let pipeline = match layer {
+            RenderLayer::Opaque => &self.pipeline,
+            RenderLayer::Cutout => &self.cutout_pipeline,
+            RenderLayer::Translucent => &self.translucent_pipeline,
+        };
+        render_pass.set_pipeline(pipeline.as_ref().expect("The pipeline was never built! Call build_pipeline() after loading texture sets"));
+        let mut bound_texture_set = None;
+        for (instance, chunk) in chunks {
+            let Some(gpu_mesh) = &chunk.gpu_mesh else { continue };
+            let indices = gpu_mesh.layer_indices(layer);
+            if indices.is_empty() {
+                continue;
+            }
+            if bound_texture_set != Some(chunk.texture_set) {
+                let Some(texset) = self.texture_sets.get(chunk.texture_set) else { continue };
+                render_pass.set_bind_group(1, &texset.bind_group, &[]);
+                bound_texture_set = Some(chunk.texture_set);
+            }
+            render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(gpu_mesh.index_buffer.slice(..), gpu_mesh.index_format);
+            render_pass.draw_indexed(indices, 0, *instance..*instance+1);
+        }
+    }
+
+    // a single textured cube of `block_id`, in the same vertex format as chunk meshes
+    fn build_ghost_mesh(&self, world: &world::World, block_id: BlockID) -> GpuMesh {
+        let vertices = geometry::CUBE.map(|v| {
+            let facing = Facing::from_index(v.face() as u8);
+            let tex_id = world.block_properties.get_tex_id(block_id, facing, Facing::U) as u32;
+            v.with_tex_id(tex_id).with_shade(facing.ambient_shade())
+        });
+        let indices = (0..6u32).flat_map(|i| [0, 1, 2, 2, 3, 0].map(|j| i * 4 + j)).collect::<Vec<u32>>();
+        GpuMesh::new(&self.device, &vertices, &indices)
+    }
+
+    fn create_debug_line_pipeline(&self) -> wgpu::RenderPipeline {
+        let pipeline_layout = self.device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Debug Line Pipeline Layout"),
+                bind_group_layouts: &[&self.frame_data_bind_group_layout],
+                push_constant_ranges: &[],
+            }
+        );
+        // depth tested so lines hide behind terrain, but they don't write depth themselves
+        let depth_stencil = self.depth_stencil_state.clone().map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            ..state
+        });
+
+        self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Debug Line Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.debug_line_shader,
+                entry_point: "vs_main",
+                buffers: &[LineVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.debug_line_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.render_format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // flat and on top of everything: no depth test, blended so it isn't a solid block of white
+    fn create_crosshair_pipeline(&self) -> wgpu::RenderPipeline {
+        let pipeline_layout = self.device.create_pipeline_layout(
+            &wgpu::PipelineLayoutDescriptor {
+                label: Some("Crosshair Pipeline Layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            }
+        );
+        // the pass has a depth buffer so the pipeline needs a depth state, it just ignores it
+        let depth_stencil = self.depth_stencil_state.clone().map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Always,
+            ..state
+        });
+
+        self.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Crosshair Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.crosshair_shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex2D::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.crosshair_shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: self.render_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil,
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+    }
+
+    // a + at window_center_px, in clip space. scaled with the ui so it's the same size on any window
+    fn crosshair_vertices(&self) -> [Vertex2D; CROSSHAIR_VERTEX_COUNT] {
+        let (width, height) = (self.size.width as f32, self.size.height as f32);
+        let center = [self.window_center_px.x as f32 / width * 2.0 - 1.0, 1.0 - self.window_center_px.y as f32 / height * 2.0];
+        // pixels to clip space on each axis
+        let (px_x, px_y) = (2.0 / width * self.ui_scale, 2.0 / height * self.ui_scale);
+        let quad = |half_w: f32, half_h: f32| {
+            let (x0, x1) = (center[0] - half_w, center[0] + half_w);
+            let (y0, y1) = (center[1] - half_h, center[1] + half_h);
+            [[x0, y0], [x1, y0], [x1, y1], [x1, y1], [x0, y1], [x0, y0]]
+        };
+        let horizontal = quad(CROSSHAIR_SIZE_PX * px_x, CROSSHAIR_THICKNESS_PX / 2.0 * px_y);
+        let vertical = quad(CROSSHAIR_THICKNESS_PX / 2.0 * px_x, CROSSHAIR_SIZE_PX * px_y);
+        let mut vertices = [Vertex2D { pos: [0.0; 2] }; CROSSHAIR_VERTEX_COUNT];
+        for (vertex, pos) in vertices.iter_mut().zip(horizontal.into_iter().chain(vertical)) {
+            vertex.pos = pos;
+        }
+        vertices
+    }
+
+    // outlines of every chunk slot in the render cube colored by what state it's in, plus the render cube itself
+    fn chunk_border_lines(world: &world::World) -> Vec<LineVertex> {
+        use glam::Vec3;
+        let chunks = &world.chunks;
+        let rd = chunks.render_distance;
+        let (cx, cy, cz) = chunks.center;
+        let chunk_extent = Vec3::splat(chunk::CHUNK_SIZE_F);
+        // chunks are absolute but everything is drawn relative to the world origin
+        let origin = world.origin.as_vec3();
+
+        let mut lines = vec![];
+        for x in cx-rd..=cx+rd {
+            for y in cy-rd..=cy+rd {
+                for z in cz-rd..=cz+rd {
+                    let min = Vec3::new(x as f32, y as f32, z as f32) * chunk::CHUNK_SIZE_F;
+                    let color = match chunks.get_chunk_at_chunk_coords((x, y, z)) {
+                        None => continue,
+                        Some(lock) => match lock.try_read() {
+                            Err(_) => CHUNK_BORDER_BUSY,
+                            Ok(chunk) => {
+                                if chunk.pos != min {
+                                    continue; // the ring buffer slot still holds a chunk that's about to be unloaded
+                                }
+                                if chunk.needs_upload || !chunk.ready_to_display {
+                                    CHUNK_BORDER_PENDING
+                                } else if chunk.mesh.is_empty() {
+                                    continue; // empty air chunks would just draw a giant lattice
+                                } else {
+                                    CHUNK_BORDER_READY
+                                }
+                            }
+                        }
+                    };
+                    lines.extend(LineVertex::box_edges(min - origin, min - origin + chunk_extent, color));
+                }
+            }
+        }
+
+        let render_min = Vec3::new((cx-rd) as f32, (cy-rd) as f32, (cz-rd) as f32) * chunk::CHUNK_SIZE_F;
+        let render_max = Vec3::new((cx+rd+1) as f32, (cy+rd+1) as f32, (cz+rd+1) as f32) * chunk::CHUNK_SIZE_F;
+        lines.extend(LineVertex::box_edges(render_min - origin, render_max - origin, RENDER_DISTANCE_BORDER));
+        lines
+    }
+
+    // every set shares the same bind group layout, so adding one doesn't need a pipeline rebuild
+    // returns the index chunks should put in their texture_set to draw with it
+    pub fn load_texture_set(&mut self, fp_vec: Vec<String>) -> usize {
+        debug!("Loading texture set...");
+        self.texture_sets.push(texturing::TextureSet::from_fp_vec(&self.device, &self.queue, &self.texture_bind_group_layout, fp_vec, self.texture_filter));
+        self.texture_sets.len() - 1
+    }
+
+    // swap out a texture set in place. call between frames
+    pub fn reload_texture_set(&mut self, index: usize, fp_vec: Vec<String>) {
+        debug!("Reloading texture set {}...", index);
+        self.texture_sets[index] = texturing::TextureSet::from_fp_vec(&self.device, &self.queue, &self.texture_bind_group_layout, fp_vec, self.texture_filter);
+        // textures are reloaded along with the block set, which can move every tex id the ghost was built with
+        self.ghost_mesh = None;
+    }
+
+    pub fn texture_filter(&self) -> TextureFilter {
+        self.texture_filter
+    }
+
+    // switches every loaded set over without touching the texture data, so it's cheap enough to do mid game
+    pub fn set_texture_filter(&mut self, filter: TextureFilter) {
+        self.texture_filter = filter;
+        for texture_set in &mut self.texture_sets {
+            texture_set.set_filter(&self.device, &self.texture_bind_group_layout, filter);
+        }
+    }
+
+    // lower latency is snappier, higher is smoother when frame times are uneven. takes effect right away
+    pub fn set_present_options(&mut self, vsync: bool, max_frame_latency: u32) {
+        self.surface_config.present_mode = if vsync { PresentMode::AutoVsync } else { PresentMode::AutoNoVsync };
+        self.surface_config.desired_maximum_frame_latency = max_frame_latency.max(1);
+        if self.size.width > 0 && self.size.height > 0 {
+            self.surface.configure(&self.device, &self.surface_config);
+        }
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.surface_config.present_mode == PresentMode::AutoVsync
+    }
+
+    // moving to a monitor with a different dpi. the window's physical size usually changes with it, so this goes through resize too
+    pub fn set_scale_factor(&mut self, scale_factor: f32, new_size: winit::dpi::PhysicalSize<u32>) {
+        self.scale_factor = scale_factor;
+        self.resize(new_size);
+    }
+
+    // ui is sized off the window's physical height, which already accounts for the dpi scale
+    fn update_ui_scale(&mut self) {
+        self.ui_scale = self.ui_scale_manual_adjust * self.size.height as f32 / 600.0;
+
+        self.text_manager.on_resize(self.size, self.ui_scale);
+        self.text_manager.viewport.update(
+            &self.queue,
+            glyphon::Resolution {
+                width: self.size.width,
+                height: self.size.height,
+            },
+        );
     }
 
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -445,16 +967,7 @@ impl<'a> Renderer<'a> {
             self.size = new_size;
             self.aspect_ratio = self.size.width as f32 / self.size.height as f32;
             self.window_center_px = winit::dpi::PhysicalPosition::new(self.size.width/2, self.size.height/2);
-            self.ui_scale = self.ui_scale_manual_adjust * self.size.height as f32 / 600.0;
-
-            self.text_manager.on_resize(new_size, self.ui_scale);
-            self.text_manager.viewport.update(
-                &self.queue,
-                glyphon::Resolution {
-                    width: new_size.width,
-                    height: new_size.height,
-                },
-            );
+            self.update_ui_scale();
 
             self.camera.set_aspect_ratio(self.aspect_ratio);
 
@@ -483,13 +996,12 @@ impl<'a> Renderer<'a> {
     }
 
     pub fn render(&mut self, world: &world::World) -> Result<(), wgpu::SurfaceError> {
-        if self.pipeline.is_none() {
-            self.pipeline = Some(self.create_main_pipeline());
-        }
-
         // get framebuffer (wgpu considers every Image to be a texture) and view
         let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = output.texture.create_view(&wgpu::TextureViewDescriptor {
+            format: Some(self.render_format),
+            ..Default::default()
+        });
         // create command buffer builder
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
@@ -497,6 +1009,18 @@ impl<'a> Renderer<'a> {
 
         self.text_manager.prepare(&self.device, &self.queue);
 
+        let ghost = self.placement_ghost.filter(|_| self.show_placement_ghost);
+        if let Some((pos, block_id)) = ghost {
+            if self.ghost_mesh.as_ref().map(|(id, _)| *id) != Some(block_id) {
+                self.ghost_mesh = Some((block_id, self.build_ghost_mesh(world, block_id)));
+            }
+            self.queue.write_buffer(&self.ghost_instance_buffer, 0, bytemuck::cast_slice(&[ChunkInstance { origin: pos.floor().to_array(), fade: 1.0 }]));
+        }
+
+        if let Some(occlusion) = &mut self.occlusion {
+            occlusion.begin_frame(&self.device);
+        }
+
         // create render pass
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
@@ -505,10 +1029,11 @@ impl<'a> Renderer<'a> {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
+                        // the clear color skips the shaders and goes straight into the sRGB target, so it has to be linear
                         load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: world.sky_color[0].into(),
-                            g: world.sky_color[1].into(),
-                            b: world.sky_color[2].into(),
+                            r: srgb_to_linear(world.sky_color[0]).into(),
+                            g: srgb_to_linear(world.sky_color[1]).into(),
+                            b: srgb_to_linear(world.sky_color[2]).into(),
                             a: 1.0,
                         }),
                         store: wgpu::StoreOp::Store,
@@ -522,13 +1047,23 @@ impl<'a> Renderer<'a> {
                     }),
                     stencil_ops: None,
                 }),
-                occlusion_query_set: None,
+                occlusion_query_set: self.occlusion.as_ref().map(|occlusion| &occlusion.query_set),
                 timestamp_writes: None,
             });
 
+            // take the player lock once and copy out everything we need for the frame
+            let player = world.entities.read_lock(world.player).unwrap();
+            let projview = self.camera.get_projview(&player);
+            let pos = player.pos;
+            let facing = player.look_dir();
+            drop(player);
+            // the player and camera are relative to the world origin, so chunks get drawn relative to it too
+            let origin = world.origin.as_vec3();
+
             // FRAME DATA UNIFORM
             let data = FrameData {
-                projview: self.camera.get_projview(world.entities.fetch_lock(world.player).unwrap()).to_cols_array_2d()
+                projview: projview.to_cols_array_2d(),
+                sun_dir: world.sun_direction().extend(0.0).to_array(),
             };
             self.queue.write_buffer(
                 &self.frame_data_buffer,
@@ -537,55 +1072,149 @@ impl<'a> Renderer<'a> {
             );
 
             // SEND IT ALL IN
-            
-            use glam::{Vec3, Mat3};
-            let player = world.entities.read_lock(world.player).unwrap();
-            let pos = player.pos;
-            let facing = player.facing;
             // improved frustum culling can be done if the fov is taken into account and culling happens on the normals of the 4 planes of the camera's view
-            drop(player);
-            //let mut j = 0;
+            // collect the chunks we're going to draw first so their origins can go into the instance buffer in one write
+            let mut visible_chunks = vec![];
             for lock in world.chunks.iter() {
                 match lock.try_read() {
                     Ok(chunk) => {
-
-                        //println!("{} {}", chunk.ready_to_display, chunk.pos);
                         if !chunk.ready_to_display {
                             continue;
                         }
-                        //j += 1;
+                        // a slot the player has moved away from but that hasn't been unloaded yet
+                        if !world.chunks.check_in_bounds(world.chunks.world_to_chunk_coords(chunk.pos)) {
+                            continue;
+                        }
                         // DO FRUSTUM CULLING
-                        if (chunk.pos - pos).dot(facing) < -23.0 {
-                            //println!("skipped {} {} {}", chunk.x, chunk.y, chunk.z);
+                        let chunk_pos = chunk.pos - origin;
+                        if (chunk_pos - pos).dot(facing) < -chunk::CHUNK_DIAGONAL {
                             continue;
                         }
 
+                        visible_chunks.push(chunk);
+                    },
+                    Err(_) => continue,
+                }
+            }
 
-                        render_pass.set_pipeline(self.pipeline.as_ref().unwrap()); // 2.
-                        render_pass.set_bind_group(0, &self.frame_data_bind_group, &[]);
-                        for (i, texset) in self.texture_sets.iter().enumerate() {
-                            render_pass.set_bind_group((i+1) as u32, &texset.bind_group, &[]);
+            let fade = |chunk: &chunk::Chunk| match chunk.displayed_at {
+                Some(t) if self.chunk_fade_time > 0.0 => (t.elapsed().as_secs_f32() / self.chunk_fade_time).min(1.0),
+                _ => 1.0,
+            };
+            let instances = visible_chunks.iter().map(|chunk| ChunkInstance { origin: (chunk.pos - origin).to_array(), fade: fade(chunk) }).collect::<Vec<ChunkInstance>>();
+            if instances.len() > self.chunk_instance_capacity {
+                self.chunk_instance_capacity = instances.len().next_power_of_two();
+                self.chunk_instance_buffer = Self::create_chunk_instance_buffer(&self.device, self.chunk_instance_capacity);
+            }
+            self.queue.write_buffer(&self.chunk_instance_buffer, 0, bytemuck::cast_slice(&instances));
+
+            render_pass.set_pipeline(self.pipeline.as_ref().expect("The pipeline was never built! Call build_pipeline() after loading texture sets")); // 2.
+            render_pass.set_bind_group(0, &self.frame_data_bind_group, &[]);
+            render_pass.set_vertex_buffer(1, self.chunk_instance_buffer.slice(..));
+
+            // only rebind textures when the set actually changes between draws
+            let mut bound_texture_set = None;
+            let mut hidden = vec![];
+            // (instance, chunk) for everything that got past occlusion, for the other layers
+            let mut drawn = vec![];
+            for (i, chunk) in visible_chunks.iter().enumerate() {
+                let instance = i as u32;
+                // hidden last we heard: skip it and just test its box once everything else is drawn
+                if self.occlusion.as_ref().is_some_and(|occlusion| occlusion.is_occluded(chunk.pos)) {
+                    hidden.push((chunk.pos, instance));
+                    continue;
+                }
+                if bound_texture_set != Some(chunk.texture_set) {
+                    match self.texture_sets.get(chunk.texture_set) {
+                        Some(texset) => render_pass.set_bind_group(1, &texset.bind_group, &[]),
+                        None => {
+                            warn!("Chunk at {} wants texture set {} but only {} are loaded", chunk.pos, chunk.texture_set, self.texture_sets.len());
+                            continue;
                         }
+                    }
+                    bound_texture_set = Some(chunk.texture_set);
+                }
+                let gpu_mesh = chunk.gpu_mesh.as_ref().expect("A mesh was never pushed to the GPU!");
+                drawn.push((instance, &**chunk));
+                let indices = gpu_mesh.layer_indices(RenderLayer::Opaque);
+                // nothing opaque would always come back with zero samples and get the chunk's other layers culled too
+                if indices.is_empty() {
+                    continue;
+                }
+                render_pass.set_vertex_buffer(0, gpu_mesh.vertex_buffer.slice(..));
+                render_pass.set_index_buffer(gpu_mesh.index_buffer.slice(..), gpu_mesh.index_format); // 1.
+                let query = self.occlusion.as_mut().and_then(|occlusion| occlusion.next_query(chunk.pos));
+                if let Some(query) = query {
+                    render_pass.begin_occlusion_query(query);
+                }
+                render_pass.draw_indexed(indices, 0, instance..instance+1); // 2.
+                if query.is_some() {
+                    render_pass.end_occlusion_query();
+                }
+            }
+            // cutout writes depth like opaque does, so it goes in before the occlusion boxes are tested
+            self.draw_chunk_layer(&mut render_pass, &drawn, RenderLayer::Cutout);
+            if let Some(occlusion) = &mut self.occlusion {
+                occlusion.draw_hidden_boxes(&mut render_pass, &hidden);
+            }
+            // translucent faces blend over whatever's already behind them, so far chunks have to go down before near ones.
+            // faces within a chunk aren't sorted, which only shows where two panes overlap inside one chunk
+            let chunk_center = |chunk: &chunk::Chunk| chunk.pos - origin + glam::Vec3::splat(chunk::CHUNK_SIZE_F / 2.0);
+            let mut back_to_front = drawn.iter()
+                .filter(|(_, chunk)| chunk.gpu_mesh.as_ref().is_some_and(|mesh| !mesh.layer_indices(RenderLayer::Translucent).is_empty()))
+                .copied()
+                .collect::<Vec<_>>();
+            back_to_front.sort_by(|(_, a), (_, b)| chunk_center(b).distance_squared(pos).total_cmp(&chunk_center(a).distance_squared(pos)));
+            self.draw_chunk_layer(&mut render_pass, &back_to_front, RenderLayer::Translucent);
+
+            // after everything opaque so it blends over it
+            if let (Some(_), Some((_, mesh)), Some(texset)) = (ghost, &self.ghost_mesh, self.texture_sets.first()) {
+                render_pass.set_pipeline(self.ghost_pipeline.as_ref().expect("The pipeline was never built! Call build_pipeline() after loading texture sets"));
+                render_pass.set_bind_group(0, &self.frame_data_bind_group, &[]);
+                render_pass.set_bind_group(1, &texset.bind_group, &[]);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass.set_vertex_buffer(1, self.ghost_instance_buffer.slice(..));
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+                render_pass.draw_indexed(0..mesh.index_count, 0, 0..1);
+            }
 
-                        
-                        render_pass.set_vertex_buffer(0, chunk.vertex_buffer.as_ref().expect("A vertex buffer was never pushed to the GPU!").slice(..));
-                        render_pass.set_index_buffer(chunk.index_buffer.as_ref().expect("An index buffer was never pushed to the GPU!").slice(..), wgpu::IndexFormat::Uint32); // 1.
-                        render_pass.draw_indexed(0..chunk.index_count, 0, 0..1); // 2.
+            if self.show_chunk_borders {
+                let lines = Self::chunk_border_lines(world);
+                let line_buffer = self.device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Debug Line Buffer"),
+                        contents: bytemuck::cast_slice(&lines),
+                        usage: wgpu::BufferUsages::VERTEX,
+                    }
+                );
+                render_pass.set_pipeline(self.debug_line_pipeline.as_ref().expect("The pipeline was never built! Call build_pipeline() after loading texture sets"));
+                render_pass.set_bind_group(0, &self.frame_data_bind_group, &[]);
+                render_pass.set_vertex_buffer(0, line_buffer.slice(..));
+                render_pass.draw(0..lines.len() as u32, 0..1);
+            }
 
-                    },
-                    Err(_) => continue,
-                }
-                
+            if self.show_crosshair {
+                self.queue.write_buffer(&self.crosshair_buffer, 0, bytemuck::cast_slice(&self.crosshair_vertices()));
+                render_pass.set_pipeline(self.crosshair_pipeline.as_ref().expect("The pipeline was never built! Call build_pipeline() after loading texture sets"));
+                render_pass.set_vertex_buffer(0, self.crosshair_buffer.slice(..));
+                render_pass.draw(0..CROSSHAIR_VERTEX_COUNT as u32, 0..1);
             }
-            //println!("Rendered {} chunks", j);
 
             self.text_manager.render(&mut render_pass);
         }
 
+    if let Some(occlusion) = &mut self.occlusion {
+        occlusion.resolve(&mut encoder);
+    }
+
     // submit will accept anything that implements IntoIter
     self.queue.submit(std::iter::once(encoder.finish()));
     output.present();
 
+    if let Some(occlusion) = &mut self.occlusion {
+        occlusion.after_submit();
+    }
+
     self.text_manager.atlas.trim();
 
     Ok(())
@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use crate::chunk::{Chunk, ChunkDecodeError, CHUNK_SIZE_F};
+use crate::chunkset::ChunkCoord;
+
+// every world gets its own folder in here, named after its seed
+pub const SAVES_DIR: &str = "saves";
+
+#[derive(Debug)]
+pub enum SaveError {
+    Io { path: PathBuf, error: std::io::Error },
+    Corrupt { path: PathBuf, error: ChunkDecodeError },
+}
+impl std::fmt::Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Couldn't access {}: {}", path.display(), error),
+            Self::Corrupt { path, error } => write!(f, "Couldn't read the chunk in {}: {}", path.display(), error),
+        }
+    }
+}
+
+pub fn world_save_dir(seed: u32) -> PathBuf {
+    Path::new(SAVES_DIR).join(seed.to_string())
+}
+
+// one file per chunk, named after its chunk coords
+pub fn chunk_path(save_dir: &Path, chunk_coord: ChunkCoord) -> PathBuf {
+    save_dir.join(format!("{}_{}_{}.chunk", chunk_coord.0, chunk_coord.1, chunk_coord.2))
+}
+
+pub fn save_chunk(save_dir: &Path, chunk_coord: ChunkCoord, chunk: &Chunk) -> Result<(), SaveError> {
+    std::fs::create_dir_all(save_dir).map_err(|error| SaveError::Io { path: save_dir.to_path_buf(), error })?;
+    let path = chunk_path(save_dir, chunk_coord);
+    std::fs::write(&path, chunk.serialize()).map_err(|error| SaveError::Io { path, error })
+}
+
+// None if the chunk was never saved, in which case it should be generated
+pub fn load_chunk(save_dir: &Path, chunk_coord: ChunkCoord) -> Result<Option<Chunk>, SaveError> {
+    let path = chunk_path(save_dir, chunk_coord);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(SaveError::Io { path, error }),
+    };
+    let (x, y, z) = (chunk_coord.0 as f32 * CHUNK_SIZE_F, chunk_coord.1 as f32 * CHUNK_SIZE_F, chunk_coord.2 as f32 * CHUNK_SIZE_F);
+    Chunk::deserialize(&bytes, x, y, z)
+        .map(Some)
+        .map_err(|error| SaveError::Corrupt { path, error })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::Vec3;
+
+    // a fresh folder per test, so tests running at the same time don't see each other's chunks
+    fn temp_save_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("voxelgame-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn saved_chunk_loads_back() {
+        let save_dir = temp_save_dir("round-trip");
+        let chunk_coord = (-1, 2, 0);
+        let mut chunk = Chunk::new(-CHUNK_SIZE_F, 2.0 * CHUNK_SIZE_F, 0.0);
+        chunk.set_block_id_at(Vec3::new(-3.0, 40.0, 5.0), 6);
+
+        assert!(load_chunk(&save_dir, chunk_coord).unwrap().is_none());
+        save_chunk(&save_dir, chunk_coord, &chunk).unwrap();
+        let loaded = load_chunk(&save_dir, chunk_coord).unwrap().unwrap();
+        assert_eq!(loaded.pos, chunk.pos);
+        assert_eq!(loaded.get_block_id_at(Vec3::new(-3.0, 40.0, 5.0)), 6);
+        assert_eq!(loaded.serialize(), chunk.serialize());
+        // only that chunk was saved
+        assert!(load_chunk(&save_dir, (0, 2, 0)).unwrap().is_none());
+
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_chunk_file_is_an_error() {
+        let save_dir = temp_save_dir("corrupt");
+        std::fs::create_dir_all(&save_dir).unwrap();
+        std::fs::write(chunk_path(&save_dir, (0, 0, 0)), [0xff, 1, 2]).unwrap();
+        assert!(matches!(load_chunk(&save_dir, (0, 0, 0)), Err(SaveError::Corrupt { error: ChunkDecodeError::UnknownVersion(0xff), .. })));
+
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+}
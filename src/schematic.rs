@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use glam::IVec3;
+use crate::block::BlockID;
+use crate::geometry::Facing;
+
+#[derive(Debug)]
+pub enum SchematicError {
+    Io { path: String, error: std::io::Error },
+    Parse { path: String, error: toml::de::Error },
+    Serialize(toml::ser::Error),
+    // the block list doesn't match the size it claims
+    WrongBlockCount { path: String, expected: usize, found: usize },
+    BadOrientation { path: String, orientation: u8 },
+}
+impl std::fmt::Display for SchematicError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Couldn't access {}: {}", path, error),
+            Self::Parse { path, error } => write!(f, "Improperly formatted {}: {}", path, error),
+            Self::Serialize(error) => write!(f, "Couldn't write the schematic out: {}", error),
+            Self::WrongBlockCount { path, expected, found } => write!(f,
+                "{} should have {} blocks for its size but has {}", path, expected, found
+            ),
+            Self::BadOrientation { path, orientation } => write!(f,
+                "{} has a block with orientation {}, which isn't a facing (0-5)", path, orientation
+            ),
+        }
+    }
+}
+
+// a copied box of blocks, positioned relative to its min corner. see World::copy_region and World::paste
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Schematic {
+    pub size: [i32; 3],
+    // x fastest, then y, then z (same order as World::positions_in)
+    pub ids: Vec<BlockID>,
+    // Facing indices, one per block
+    pub orientations: Vec<u8>,
+}
+
+impl Schematic {
+    pub fn new(size: IVec3) -> Self {
+        let volume = (size.x * size.y * size.z) as usize;
+        Self {
+            size: size.to_array(),
+            ids: Vec::with_capacity(volume),
+            orientations: Vec::with_capacity(volume),
+        }
+    }
+
+    pub fn push(&mut self, id: BlockID, orientation: Facing) {
+        self.ids.push(id);
+        self.orientations.push(orientation as u8);
+    }
+
+    pub fn size(&self) -> IVec3 {
+        IVec3::from_array(self.size)
+    }
+
+    pub fn volume(&self) -> usize {
+        self.ids.len()
+    }
+
+    // (offset from the min corner, id, orientation) for every block
+    pub fn blocks(&self) -> impl Iterator<Item=(IVec3, BlockID, Facing)> + '_ {
+        let size = self.size();
+        self.ids.iter().zip(&self.orientations).enumerate().map(move |(i, (&id, &orientation))| {
+            let i = i as i32;
+            let offset = IVec3::new(i % size.x, (i / size.x) % size.y, i / (size.x * size.y));
+            (offset, id, Facing::from_index(orientation))
+        })
+    }
+
+    // schematics are toml so they can be shared (and poked at) like the other configs
+    pub fn save(&self, fp: &str) -> Result<(), SchematicError> {
+        let data = toml::to_string(self).map_err(SchematicError::Serialize)?;
+        std::fs::write(fp, data).map_err(|error| SchematicError::Io { path: fp.to_string(), error })
+    }
+
+    pub fn load(fp: &str) -> Result<Self, SchematicError> {
+        let data = std::fs::read_to_string(fp).map_err(|error| SchematicError::Io { path: fp.to_string(), error })?;
+        let schematic = toml::from_str::<Self>(&data).map_err(|error| SchematicError::Parse { path: fp.to_string(), error })?;
+        let expected = schematic.size.iter().map(|&n| n.max(0) as usize).product::<usize>();
+        for found in [schematic.ids.len(), schematic.orientations.len()] {
+            if found != expected {
+                return Err(SchematicError::WrongBlockCount { path: fp.to_string(), expected, found });
+            }
+        }
+        if let Some(&orientation) = schematic.orientations.iter().find(|&&o| o as usize >= Facing::all().len()) {
+            return Err(SchematicError::BadOrientation { path: fp.to_string(), orientation });
+        }
+        Ok(schematic)
+    }
+}
@@ -0,0 +1,394 @@
+use serde::{Deserialize, Serialize};
+use log::warn;
+use winit::keyboard::KeyCode;
+
+pub const SETTINGS_PATH: &str = "config/settings.toml";
+// set this to read and write settings somewhere other than SETTINGS_PATH
+pub const SETTINGS_PATH_ENV: &str = "VOXELGAME_SETTINGS";
+
+pub fn settings_path() -> String {
+    std::env::var(SETTINGS_PATH_ENV).unwrap_or_else(|_| SETTINGS_PATH.to_string())
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io { path: String, error: std::io::Error },
+    Serialize(toml::ser::Error),
+}
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, error } => write!(f, "Couldn't write {}: {}", path, error),
+            Self::Serialize(error) => write!(f, "Couldn't write the settings out: {}", error),
+        }
+    }
+}
+
+// player-tweakable options from config/settings.toml. anything left out of the file keeps its default
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    pub camera: CameraSettings,
+    pub player: PlayerSettings,
+    pub graphics: GraphicsSettings,
+    pub world: WorldSettings,
+    pub audio: AudioSettings,
+    pub controls: ControlSettings,
+    pub ui: UiSettings,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSettings {
+    // off for anyone who gets motion sick
+    pub view_bobbing: bool,
+    pub bob_amplitude: f32, // blocks
+    pub bob_frequency: f32, // bobs per block walked
+    pub sprint_fov_boost: f32, // degrees the view widens by while sprinting, 0 to turn it off
+    pub look_sensitivity: f32, // degrees turned per unit of mouse movement
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerSettings {
+    pub gravity: f32, // blocks/s^2
+    pub jump_height: f32, // blocks
+    pub move_speed: f32, // blocks/s, before sprinting
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphicsSettings {
+    pub chunk_fade_time: f32, // seconds, 0 for no fade
+    pub placement_ghost: bool, // see-through preview of where right click puts a block
+    pub texture_filter: TextureFilter,
+    pub vsync: bool,
+    pub max_frame_latency: u32, // frames the gpu can be queued up behind us. 1 is snappiest, 2-3 smoother
+    pub fps_limit: f32, // only used with vsync off, 0 for no limit
+    pub render_distance: usize, // chunks each way from the player, up to world::MAX_RENDER_DISTANCE
+}
+
+// how block textures are sampled
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFilter {
+    // crisp pixels up close and far away
+    Nearest,
+    // smooth everywhere, blurry up close
+    Linear,
+    // crisp up close, smoothed out in the distance
+    Mixed,
+}
+
+impl TextureFilter {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Nearest => Self::Linear,
+            Self::Linear => Self::Mixed,
+            Self::Mixed => Self::Nearest,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    pub volume: f32, // 0 to mute, 1 as recorded
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            volume: 1.0,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ControlSettings {
+    pub repeat_rate: f32, // blocks broken a second while the left button is held, 0 for one per click
+    pub place_cooldown: f32, // seconds between blocks placed while the right button is held, 0 for one per click
+    pub keys: KeyBindings,
+}
+
+impl Default for ControlSettings {
+    fn default() -> Self {
+        Self {
+            repeat_rate: 4.0,
+            place_cooldown: 0.25,
+            keys: KeyBindings::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct UiSettings {
+    pub font_family: String, // any family in assets/fonts/ or installed on the system. falls back to the first one in assets/fonts/ if it isn't found
+}
+
+impl Default for UiSettings {
+    fn default() -> Self {
+        Self {
+            font_family: crate::renderer::DEFAULT_FONT_FAMILY.to_string(),
+        }
+    }
+}
+
+// what a bound key does in game
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    Forward,
+    Back,
+    Left,
+    Right,
+    Jump, // flies up when flying
+    Descend, // only does anything when flying
+    Sprint,
+    ToggleFlying,
+}
+
+// physical keys, so the layout stays put on AZERTY and the like. named the way winit names them: "KeyW", "Space", "ShiftLeft"...
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub forward: KeyCode,
+    pub back: KeyCode,
+    pub left: KeyCode,
+    pub right: KeyCode,
+    pub jump: KeyCode,
+    pub descend: KeyCode,
+    pub sprint: KeyCode,
+    pub toggle_flying: KeyCode,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            forward: KeyCode::KeyW,
+            back: KeyCode::KeyS,
+            left: KeyCode::KeyA,
+            right: KeyCode::KeyD,
+            jump: KeyCode::Space,
+            descend: KeyCode::ShiftLeft,
+            sprint: KeyCode::KeyR,
+            toggle_flying: KeyCode::KeyF,
+        }
+    }
+}
+
+impl KeyBindings {
+    // if one key is bound to several actions, the first in KeyAction order wins
+    pub fn action_for(&self, key: KeyCode) -> Option<KeyAction> {
+        [
+            (self.forward, KeyAction::Forward),
+            (self.back, KeyAction::Back),
+            (self.left, KeyAction::Left),
+            (self.right, KeyAction::Right),
+            (self.jump, KeyAction::Jump),
+            (self.descend, KeyAction::Descend),
+            (self.sprint, KeyAction::Sprint),
+            (self.toggle_flying, KeyAction::ToggleFlying),
+        ].into_iter().find(|&(bound, _)| bound == key).map(|(_, action)| action)
+    }
+}
+
+// what happens when you fall under the world
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum VoidMode {
+    // a bedrock layer at void_level you can't fall through
+    Floor,
+    // nothing down there; drop below void_level and you're sent back to spawn
+    Respawn,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct WorldSettings {
+    pub void_mode: VoidMode,
+    pub void_level: f32, // z of the floor, or the height you respawn at
+    pub tick_rate: f32, // world updates per second, regardless of the frame rate
+    pub physics_rate: f32, // physics steps per second, also fixed. higher is smoother and more precise but costs more
+    pub max_chunks_per_tick: usize, // chunks generated or remeshed a frame. lower for smoother frames, higher to fill in faster
+    pub terrain: TerrainSettings,
+}
+
+// the shape of the height map, see terrain.rs
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainSettings {
+    pub scale: f32, // frequency of the broadest hills, per block
+    pub amplitude: f32, // blocks from the lowest valley to sea level, and from sea level to the highest peak
+    pub octaves: u32, // layers of detail, 1 for plain rolling hills
+    pub persistence: f32, // how much weaker each octave is than the last
+    pub lacunarity: f32, // how much finer each octave is than the last
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            scale: 0.05,
+            amplitude: 16.0,
+            octaves: 4,
+            persistence: 0.5,
+            lacunarity: 2.0,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            camera: CameraSettings::default(),
+            player: PlayerSettings::default(),
+            graphics: GraphicsSettings::default(),
+            world: WorldSettings::default(),
+            audio: AudioSettings::default(),
+            controls: ControlSettings::default(),
+            ui: UiSettings::default(),
+        }
+    }
+}
+
+impl Default for WorldSettings {
+    fn default() -> Self {
+        Self {
+            void_mode: VoidMode::Floor,
+            void_level: -64.0,
+            tick_rate: 20.0,
+            physics_rate: 120.0,
+            max_chunks_per_tick: 64,
+            terrain: TerrainSettings::default(),
+        }
+    }
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            chunk_fade_time: 0.5,
+            placement_ghost: true,
+            texture_filter: TextureFilter::Mixed,
+            vsync: true,
+            max_frame_latency: 2,
+            fps_limit: 0.0,
+            render_distance: 10,
+        }
+    }
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        Self {
+            gravity: 9.8*2.5,
+            jump_height: 1.3,
+            move_speed: 4.3,
+        }
+    }
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self {
+            view_bobbing: true,
+            bob_amplitude: 0.05,
+            bob_frequency: 0.6,
+            sprint_fov_boost: 10.0,
+            look_sensitivity: crate::camera::DEFAULT_LOOK_SENSITIVITY,
+        }
+    }
+}
+
+impl Settings {
+    // a missing or broken settings file isn't worth refusing to start over, so this always hands back something usable
+    pub fn load(fp: &str) -> Self {
+        let data = match std::fs::read_to_string(fp) {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Couldn't open {} ({}), using default settings", fp, e);
+                return Self::default();
+            }
+        };
+        match toml::from_str::<Self>(&data) {
+            Ok(settings) => settings,
+            Err(e) => {
+                warn!("Improperly formatted {}, using default settings: {}", fp, e);
+                Self::default()
+            }
+        }
+    }
+
+    // note this writes the whole file fresh, so any comments in it are lost
+    pub fn save(&self, fp: &str) -> Result<(), SettingsError> {
+        let data = toml::to_string_pretty(self).map_err(SettingsError::Serialize)?;
+        if let Some(dir) = std::path::Path::new(fp).parent() {
+            std::fs::create_dir_all(dir).map_err(|error| SettingsError::Io { path: fp.to_string(), error })?;
+        }
+        std::fs::write(fp, data).map_err(|error| SettingsError::Io { path: fp.to_string(), error })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_settings_parse() {
+        let settings = toml::from_str::<Settings>(include_str!("../config/settings.toml")).unwrap();
+        assert_eq!(settings.player.jump_height, 1.3);
+        assert_eq!(settings.player.gravity, 24.5);
+        // left commented out so it picks the platform's default
+        assert_eq!(settings.camera.look_sensitivity, CameraSettings::default().look_sensitivity);
+    }
+
+    #[test]
+    fn missing_sections_keep_their_defaults() {
+        let settings = toml::from_str::<Settings>("[player]\ngravity = 4.0\n").unwrap();
+        assert_eq!(settings.player.gravity, 4.0);
+        assert_eq!(settings.player.jump_height, PlayerSettings::default().jump_height);
+        assert_eq!(settings.world, WorldSettings::default());
+        assert_eq!(toml::from_str::<Settings>("").unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn unknown_fields_are_refused() {
+        assert!(toml::from_str::<Settings>("[player]\ngravityy = 4.0\n").is_err());
+    }
+
+    #[test]
+    fn defaults_survive_a_save_and_load() {
+        let data = toml::to_string_pretty(&Settings::default()).unwrap();
+        assert_eq!(toml::from_str::<Settings>(&data).unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn unreadable_file_falls_back_to_defaults() {
+        assert_eq!(Settings::load("config/definitely_not_here.toml"), Settings::default());
+    }
+
+    #[test]
+    fn rebound_keys_resolve_to_their_action() {
+        let settings = toml::from_str::<Settings>("[controls.keys]\nforward = \"ArrowUp\"\njump = \"KeyW\"\n").unwrap();
+        let keys = &settings.controls.keys;
+        assert_eq!(keys.action_for(KeyCode::ArrowUp), Some(KeyAction::Forward));
+        assert_eq!(keys.action_for(KeyCode::KeyW), Some(KeyAction::Jump));
+        // the rest keep the usual layout
+        assert_eq!(keys.action_for(KeyCode::KeyS), Some(KeyAction::Back));
+        assert_eq!(keys.action_for(KeyCode::Space), None);
+        assert_eq!(keys.action_for(KeyCode::KeyQ), None);
+    }
+
+    #[test]
+    fn first_action_wins_a_doubly_bound_key() {
+        let keys = KeyBindings { sprint: KeyCode::KeyW, ..KeyBindings::default() };
+        assert_eq!(keys.action_for(KeyCode::KeyW), Some(KeyAction::Forward));
+        assert_eq!(keys.action_for(KeyCode::KeyR), None);
+    }
+
+    #[test]
+    fn unknown_key_names_are_refused() {
+        assert!(toml::from_str::<Settings>("[controls.keys]\nforward = \"W\"\n").is_err());
+    }
+}
@@ -0,0 +1,37 @@
+use noise::{NoiseFn, Perlin};
+use crate::settings::TerrainSettings;
+
+// the world's height map. one per world, shared by every chunk that generates
+pub struct Terrain {
+    noise: Perlin,
+    pub settings: TerrainSettings,
+}
+
+impl Terrain {
+    pub fn new(seed: u32, settings: TerrainSettings) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            settings,
+        }
+    }
+
+    // fractal brownian motion: `octaves` layers of the noise, each `lacunarity` times finer and `persistence` times weaker
+    // than the last. normalized back to -1..1 so adding octaves adds detail without making the hills any taller
+    pub fn fbm(&self, x: f64, y: f64) -> f64 {
+        let (persistence, lacunarity) = (self.settings.persistence as f64, self.settings.lacunarity as f64);
+        let (mut sum, mut total_amplitude) = (0.0, 0.0);
+        let (mut amplitude, mut frequency) = (1.0, self.settings.scale as f64);
+        for _ in 0..self.settings.octaves.max(1) {
+            sum += self.noise.get([x * frequency, y * frequency]) * amplitude;
+            total_amplitude += amplitude;
+            amplitude *= persistence;
+            frequency *= lacunarity;
+        }
+        sum / total_amplitude
+    }
+
+    // z of the grass at world column (x, y)
+    pub fn height_at(&self, x: f64, y: f64) -> f32 {
+        (self.fbm(x, y) * self.settings.amplitude as f64).floor() as f32
+    }
+}
@@ -1,8 +1,32 @@
+use crate::settings::TextureFilter;
+
 pub struct TextureSet {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     pub sampler: wgpu::Sampler,
     pub bind_group: wgpu::BindGroup,
+    pub filter: TextureFilter,
+}
+
+// copies of the stock block textures so the game can run without assets/ next to it
+const BUILTIN_TEXTURES: &[(&str, &[u8])] = &[
+    ("bedrock.png", include_bytes!("../assets/textures/bedrock.png")),
+    ("stone.png", include_bytes!("../assets/textures/stone.png")),
+    ("cobblestone.png", include_bytes!("../assets/textures/cobblestone.png")),
+    ("grass_block_side.png", include_bytes!("../assets/textures/grass_block_side.png")),
+    ("grass_block_top.png", include_bytes!("../assets/textures/grass_block_top.png")),
+    ("dirt.png", include_bytes!("../assets/textures/dirt.png")),
+    ("gold_block.png", include_bytes!("../assets/textures/gold_block.png")),
+    ("coal_ore.png", include_bytes!("../assets/textures/coal_ore.png")),
+    ("iron_ore.png", include_bytes!("../assets/textures/iron_ore.png")),
+    ("oak_leaves.png", include_bytes!("../assets/textures/oak_leaves.png")),
+    ("glass.png", include_bytes!("../assets/textures/glass.png")),
+];
+
+// look up a built-in texture by file name (or any path ending in that file name)
+pub fn builtin_texture(fp: &str) -> Option<&'static [u8]> {
+    let name = std::path::Path::new(fp).file_name()?.to_str()?;
+    BUILTIN_TEXTURES.iter().find(|(builtin_name, _)| *builtin_name == name).map(|(_, data)| *data)
 }
 
 pub const TEXTURE_SET_LAYOUT_DESC: wgpu::BindGroupLayoutDescriptor = wgpu::BindGroupLayoutDescriptor {
@@ -28,15 +52,43 @@ pub const TEXTURE_SET_LAYOUT_DESC: wgpu::BindGroupLayoutDescriptor = wgpu::BindG
 };
 
 impl TextureSet {
-    pub fn from_fp_vec(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, fp_vec: Vec<String>) -> Self {
+    pub fn from_fp_vec(device: &wgpu::Device, queue: &wgpu::Queue, layout: &wgpu::BindGroupLayout, fp_vec: Vec<String>, filter: TextureFilter) -> Self {
         use image::{ImageBuffer, Rgba, ImageReader};
 
-        fn load_rgba8(fp: &str) -> ImageBuffer<Rgba<u8>, Vec<u8>> {ImageReader::open(fp).expect(&format!("Failed to load {}", fp)).decode().expect(&format!("Failed to decode {}", fp)).into_rgba8()}
+        // disk first (unless the built-in assets are forced), then the built-in copy, then give up
+        fn load_rgba8(fp: &str) -> Option<ImageBuffer<Rgba<u8>, Vec<u8>>> {
+            let from_disk = if crate::block::builtin_assets_forced() {
+                Err("built-in assets forced".to_string())
+            } else {
+                ImageReader::open(fp).map_err(|e| e.to_string()).and_then(|reader| reader.decode().map_err(|e| e.to_string()))
+            };
+            let from_builtin = || builtin_texture(fp).and_then(|data| image::load_from_memory(data).ok());
+            match from_disk {
+                Ok(img) => Some(img.into_rgba8()),
+                Err(e) => match from_builtin() {
+                    Some(img) => Some(img.into_rgba8()),
+                    None => {
+                        log::warn!("Failed to load {} ({}), using a placeholder", fp, e);
+                        None
+                    }
+                }
+            }
+        }
+        // magenta and black checkerboard, hard to miss in game
+        fn placeholder_rgba8(width: u32, height: u32) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+            ImageBuffer::from_fn(width, height, |x, y| {
+                if (x * 2 / width + y * 2 / height) % 2 == 0 { Rgba([255, 0, 255, 255]) } else { Rgba([0, 0, 0, 255]) }
+            })
+        }
+
+        let loaded = fp_vec.iter().map(|fp| load_rgba8(fp)).collect::<Vec<_>>();
+        // placeholders match the real textures so they can share the array
+        let placeholder_dimensions = loaded.iter().flatten().next().map(|img| img.dimensions()).unwrap_or((16, 16));
 
         let mut dimensions: Vec<(u32, u32)> = vec![];
         let mut img_array_raw: Vec<u8> = vec![];
-        for fp in &fp_vec {
-            let img_buffer = load_rgba8(fp);
+        for img_buffer in loaded {
+            let img_buffer = img_buffer.unwrap_or_else(|| placeholder_rgba8(placeholder_dimensions.0, placeholder_dimensions.1));
             dimensions.push(img_buffer.dimensions());
             img_array_raw.extend(img_buffer.into_raw());
         }
@@ -60,6 +112,7 @@ impl TextureSet {
                 sample_count: 1,
                 dimension: wgpu::TextureDimension::D2,
                 // Most images are stored using sRGB, so we need to reflect that here.
+                // sampling then gives linear colors, see the color space notes in renderer.rs
                 format: wgpu::TextureFormat::Rgba8UnormSrgb,
                 // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
                 // COPY_DST means that we want to copy data to this texture
@@ -100,39 +153,58 @@ impl TextureSet {
             ..Default::default()
         });
 
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        let sampler = Self::create_sampler(device, filter);
+        let bind_group = Self::create_bind_group(device, layout, &view, &sampler);
+
+        Self {
+            texture,
+            view,
+            sampler,
+            bind_group,
+            filter,
+        }
+    }
+
+    // only the sampler and bind group change, the texture data stays on the gpu as is
+    pub fn set_filter(&mut self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, filter: TextureFilter) {
+        self.sampler = Self::create_sampler(device, filter);
+        self.bind_group = Self::create_bind_group(device, layout, &self.view, &self.sampler);
+        self.filter = filter;
+    }
+
+    fn create_sampler(device: &wgpu::Device, filter: TextureFilter) -> wgpu::Sampler {
+        let (mag_filter, min_filter) = match filter {
+            TextureFilter::Nearest => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Nearest),
+            TextureFilter::Linear => (wgpu::FilterMode::Linear, wgpu::FilterMode::Linear),
+            TextureFilter::Mixed => (wgpu::FilterMode::Nearest, wgpu::FilterMode::Linear),
+        };
+        device.create_sampler(&wgpu::SamplerDescriptor {
             address_mode_u: wgpu::AddressMode::Repeat,
             address_mode_v: wgpu::AddressMode::Repeat,
             address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Nearest,
-            min_filter: wgpu::FilterMode::Linear,
+            mag_filter,
+            min_filter,
             mipmap_filter: wgpu::FilterMode::Nearest,
             ..Default::default()
-        });
-
+        })
+    }
 
-        let bind_group = device.create_bind_group(
+    fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, view: &wgpu::TextureView, sampler: &wgpu::Sampler) -> wgpu::BindGroup {
+        device.create_bind_group(
             &wgpu::BindGroupDescriptor {
-                layout: &layout,
+                layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
-                        resource: wgpu::BindingResource::TextureView(&view),
+                        resource: wgpu::BindingResource::TextureView(view),
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&sampler),
+                        resource: wgpu::BindingResource::Sampler(sampler),
                     }
                 ],
                 label: None,
             }
-        );
-
-        Self {
-            texture,
-            view,
-            sampler,
-            bind_group,
-        }
+        )
     }
 }
\ No newline at end of file
@@ -5,136 +5,708 @@ use std::sync::Arc;
 use crate::block::BlockID;
 use std::time::SystemTime;
 use std::sync::Mutex;
+use std::sync::mpsc;
 use crate::camera;
 use crate::entity::*;
 use crate::geometry;
-use std::collections::VecDeque;
+use crate::geometry::Facing;
+use std::collections::HashSet;
 use glam::f32::{Vec3};
+use glam::{BVec3, IVec3};
 use crate::block;
-use crate::memarena::{Arena, ArenaHandle};
+use crate::memarena::{Arena, ArenaHandle, ArenaError};
 use crate::chunk::{Chunk, CHUNK_SIZE_F};
-use crate::chunkset::{ChunkSet, ChunkCoord};
+use crate::chunkset::{ChunkSet, ChunkCoord, ChunkQueue, PendingEdit, chunk_distance_squared};
+use crate::meshcache::MeshCache;
+use crate::schematic::Schematic;
+use crate::settings::{TerrainSettings, VoidMode, WorldSettings};
+use crate::terrain::Terrain;
+use crate::save;
+use std::path::PathBuf;
 use ndarray::prelude::*;
 use ndarray::{Array3};
+use rand::SeedableRng;
+use rand::rngs::SmallRng;
 
 
 const ENTITY_LIMIT: usize = 128;
-pub const RENDER_DISTANCE: usize = 10;
+const BLOCKS_CONFIG_PATH: &str = "config/blocks.toml";
+// in chunks, each way from the player's chunk
+pub const DEFAULT_RENDER_DISTANCE: usize = 10;
+pub const MAX_RENDER_DISTANCE: usize = 32;
+// see World::max_chunks_per_tick
+pub const DEFAULT_MAX_CHUNKS_PER_TICK: usize = 64;
+// cap on gpu uploads in one frame so a burst of newly loaded chunks doesn't hitch; the rest wait for the next frames
+const MAX_UPLOADS_PER_FRAME: usize = 128;
+// horizontal speed below which a sprinting entity that hit a wall counts as stopped
+const SPRINT_CANCEL_SPEED: f32 = 1.0;
+// seconds for a full day and night
+const DAY_LENGTH: f32 = 600.0;
+// time_of_day the world starts at, mid morning
+const START_TIME_OF_DAY: f32 = 0.15;
 
+// how far the player can get from the origin before it's moved to them. f32 positions are still good to about a
+// thousandth of a block out here, where at a million blocks they're down to sixteenths and everything visibly jitters
+const REBASE_DISTANCE: f32 = 1024.0;
+
+// blocks walked between footstep sounds
+const STEP_LENGTH: f32 = 1.6;
+
+// longest move checked for collisions at once. shorter than an entity is wide, so nothing can slip between two checks
+const MAX_COLLISION_STEP: f32 = 0.5;
+
+// terminal velocity, so a long fall can't carry you through the ground in one step
+const MAX_FALL_SPEED: f32 = 60.0;
+// with a void floor you shouldn't get under it, but if you do (e.g. the floor chunk isn't loaded yet) you still get rescued
+const VOID_FLOOR_RESCUE_DEPTH: f32 = 32.0;
+
+
+// scrambles every bit of the input into every bit of the output, good enough to hash coordinates into a seed
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E3779B97F4A7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// a snapshot for the debug overlay, to tell whether time is going into generation, meshing or drawing
+#[derive(Debug, Clone, Copy)]
+pub struct WorldStats {
+    pub loaded_chunks: usize,
+    pub queued_gen: usize,
+    pub queued_mesh: usize,
+    pub total_vertices: usize,
+}
+
+// positions going in and out of World (entities, raycasts, block lookups) are relative to `origin`, so they stay near
+// zero wherever you are. chunks keep absolute positions; block_pos converts between the two with integer math
+// what one collide_step did
+struct CollisionStep {
+    moved: Vec3,
+    // axes something got in the way on
+    blocked: BVec3,
+    landed: bool,
+    hit_wall: bool,
+}
 
 pub struct World {
     pub chunks: ChunkSet,
+    // absolute block position of local (0, 0, 0), always on a chunk corner. moved by rebase_origin
+    pub origin: IVec3,
 
     pub entities: Arena<Entity>,
 
-    pub block_properties: block::BlockProtoSet,
+    // shared with generation tasks in flight, which keep the set they started with even if it's reloaded
+    pub block_properties: Arc<block::BlockProtoSet>,
 
-    pub spawn_point: Vec3,
-    pub sky_color: [f32; 4],
+    pub spawn_point: Vec3, // absolute, unlike entity positions
+    // everything random about the world comes from this, see rng_at. fixed once the world is made, since the terrain noise is built from it
+    seed: u32,
+    terrain: Arc<Terrain>,
+    // where edited chunks are kept between sessions, see save_region
+    pub save_dir: PathBuf,
+    // seconds of game time, drives the day cycle
+    pub time: f32,
+    pub void_mode: VoidMode,
+    pub void_level: f32,
+    pub sky_color: [f32; 4], // sRGB, see the color space notes in renderer.rs
     pub player: ArenaHandle<Entity>,
     last_player_chunk_coords: Option<ChunkCoord>,
 
-    pub need_mesh_update: Mutex<VecDeque<ArenaHandle<Chunk>>>,
-    pub need_generation_update: Mutex<VecDeque<ArenaHandle<Chunk>>>,
-    thread_pool: rayon::ThreadPool,
+    // a set, so a chunk edited many times in a frame still only gets meshed once (see update_meshes)
+    pub need_mesh_update: Mutex<HashSet<ChunkCoord>>,
+    // chunks waiting to be generated, nearest the player first. `generation_queued` mirrors it, plus everything still being
+    // generated, so nothing gets queued (and generated) twice
+    pub need_generation_update: Mutex<ChunkQueue>,
+    generation_queued: Mutex<HashSet<ChunkCoord>>,
+    // finished chunks coming back from the thread pool, see spawn_chunk_updates
+    generated_tx: mpsc::Sender<(ChunkCoord, Chunk)>,
+    generated_rx: Mutex<mpsc::Receiver<(ChunkCoord, Chunk)>>,
+    // lets chunks with identical blocks (all air, flat ground...) share one gpu mesh
+    pub mesh_cache: Mutex<MeshCache>,
+    // chunks with a fresh cpu mesh waiting for the gpu. meshing can happen on any thread, only upload_meshes touches the device
+    mesh_upload_tx: mpsc::Sender<ChunkCoord>,
+    mesh_upload_rx: Mutex<mpsc::Receiver<ChunkCoord>>,
+    thread_pool: Arc<rayon::ThreadPool>,
+    // most chunks sent off to generate, and most remeshed, in one frame. the rest wait in their queues for later frames,
+    // so crossing into new chunks costs a little every frame instead of one long stall
+    pub max_chunks_per_tick: usize,
+    // blocks stepped on since the last take_footsteps, for sounds
+    footsteps: Mutex<Vec<BlockID>>,
 }
 
 impl World {
     pub fn new() -> Self {
+        Self::new_with_seed(0)
+    }
+
+    // the same seed always generates the same terrain
+    pub fn new_with_seed(seed: u32) -> Self {
+        Self::create(seed, false)
+    }
+
+    // `builtin_assets` skips config/blocks.toml and uses the block set compiled into the binary
+    pub fn new_with_assets(builtin_assets: bool) -> Self {
+        Self::create(0, builtin_assets)
+    }
+
+    fn create(seed: u32, builtin_assets: bool) -> Self {
         let spawn_pos = Vec3::new(0.0, 0.0, 32.0);
         let mut entities = Arena::<Entity>::new(ENTITY_LIMIT);
-        let player = entities.create(Entity::new(spawn_pos)).unwrap();
-        let thread_pool = rayon::ThreadPoolBuilder::new().build().unwrap();
-        println!("Created threadpool with {} threads", thread_pool.current_num_threads());
+        let player = entities.create(Entity::new(spawn_pos)).expect("The entity arena can't be full before anything has spawned");
+        let thread_pool = Arc::new(rayon::ThreadPoolBuilder::new().build().unwrap());
+        let block_properties = match block::BlockProtoSet::load(BLOCKS_CONFIG_PATH, builtin_assets) {
+            Ok(block_properties) => Arc::new(block_properties),
+            Err(e) => panic!("{}", e),
+        };
+        log::debug!("Created threadpool with {} threads", thread_pool.current_num_threads());
+        let (mesh_upload_tx, mesh_upload_rx) = mpsc::channel();
+        let (generated_tx, generated_rx) = mpsc::channel();
         return Self {
-            // render distance changing is easy. `chunks = Arena::from_iter(chunks.iter())`. then, ensure Arena::drop() works.
-            
-            chunks: ChunkSet::new((0, 0, 32), RENDER_DISTANCE),
+            chunks: ChunkSet::new((0, 0, 32), DEFAULT_RENDER_DISTANCE),
+            origin: IVec3::ZERO,
             entities,
 
-            block_properties: block::BlockProtoSet::from_toml("config/blocks.toml"),
+            block_properties,
 
-            spawn_point: Vec3::new(0.0, 0.0, 0.0),
+            spawn_point: spawn_pos,
+            seed,
+            terrain: Arc::new(Terrain::new(seed, TerrainSettings::default())),
+            save_dir: save::world_save_dir(seed),
+            time: START_TIME_OF_DAY * DAY_LENGTH,
+            void_mode: VoidMode::Floor,
+            void_level: -64.0,
             player,
             sky_color: [155./255., 230./255., 255./255., 1.0],
             last_player_chunk_coords: None,
 
-            need_mesh_update: Mutex::new(VecDeque::new()),
-            need_generation_update: Mutex::new(VecDeque::new()),
+            need_mesh_update: Mutex::new(HashSet::new()),
+            need_generation_update: Mutex::new(ChunkQueue::new()),
+            generation_queued: Mutex::new(HashSet::new()),
+            generated_tx,
+            generated_rx: Mutex::new(generated_rx),
+            mesh_cache: Mutex::new(MeshCache::new()),
+            mesh_upload_tx,
+            mesh_upload_rx: Mutex::new(mesh_upload_rx),
             thread_pool,
+            max_chunks_per_tick: DEFAULT_MAX_CHUNKS_PER_TICK,
+            footsteps: Mutex::new(vec![]),
         };
     }
 
-    // return the first non air block you hit (where you want to destroy a block), the last air block you hit (where you want to place a block), and the block id at that spot
-    pub fn cast_ray_to_first_non_air_block(&self, start_pos: Vec3, facing: Vec3, max_distance: f32) -> (Vec3, Vec3, BlockID) {
-        let midpoint_offset = Vec3::new(0.5, 0.5, 0.5);
-        let max_distance = max_distance*max_distance;
-        let dirs_manhattan = {
-            let facing_manhattan = facing.signum();
-            [facing_manhattan.x*Vec3::X, facing_manhattan.y*Vec3::Y, facing_manhattan.z*Vec3::Z]
-        };
+    // reproducible randomness tied to a spot in the world: the same seed, position and salt always give the same stream,
+    // so anything placed with it (ores, trees, mob spawns) comes out the same every time. use a different salt per feature
+    // so they don't all roll the same numbers
+    pub fn rng_at(&self, x: i32, y: i32, z: i32, salt: u32) -> SmallRng {
+        let mut h = splitmix64(self.seed as u64 ^ (salt as u64) << 32);
+        for n in [x, y, z] {
+            h = splitmix64(h ^ n as u32 as u64);
+        }
+        SmallRng::seed_from_u64(h)
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    // 0 at sunrise, 0.25 noon, 0.5 sunset, 0.75 midnight
+    pub fn time_of_day(&self) -> f32 {
+        (self.time / DAY_LENGTH).fract()
+    }
+
+    // unit vector the sunlight travels along. the sun rises in the east and sets in the west, a little off to the south
+    pub fn sun_direction(&self) -> Vec3 {
+        let angle = self.time_of_day() * std::f32::consts::TAU;
+        let towards_sun = Vec3::new(angle.cos(), -0.3, angle.sin()).normalize();
+        -towards_sun
+    }
+
+    // back to spawn_point, stopped and facing the way you spawned
+    pub fn respawn_player(&self) {
+        self.entities.write_lock(self.player).unwrap().reset_to(self.to_local(self.spawn_point));
+    }
 
-        let mut block_id: BlockID = 0;
-        let mut ray_pos = start_pos.floor()+midpoint_offset;
-        let mut ray = ray_pos-start_pos;
-        let mut last_ray_pos = start_pos;
+    // the absolute position of the block containing local `pos`. flooring before adding the origin keeps it exact,
+    // where adding first would round a position just inside a block's edge into the next one far from spawn
+    pub fn block_pos(&self, pos: Vec3) -> Vec3 {
+        (pos.floor().as_ivec3() + self.origin).as_vec3()
+    }
 
-        while block_id == 0 && ray.length_squared() < max_distance {
-            let candidates = [ray + dirs_manhattan[0], ray + dirs_manhattan[1], ray + dirs_manhattan[2]];
-            let errors = candidates.iter().map(|cand| cand.cross(facing).length_squared());
-            let best_index = errors.enumerate().min_by(|(_, a), (_, b)| a.total_cmp(b)).map(|(index, _)| index).unwrap();
+    pub fn to_local(&self, absolute: Vec3) -> Vec3 {
+        absolute - self.origin.as_vec3()
+    }
 
-            ray = candidates[best_index];
-            last_ray_pos = ray_pos;
-            ray_pos = start_pos+ray;
-            block_id = self.get_block_id_at(ray_pos);
+    // once the player strays REBASE_DISTANCE from the origin, move it to the chunk they're in and shift every entity to match.
+    // chunks and their meshes don't care, they're absolute and drawn relative to the origin each frame
+    pub fn rebase_origin(&mut self) {
+        let player_pos = self.entities.read_lock(self.player).unwrap().pos;
+        if player_pos.abs().max_element() < REBASE_DISTANCE {
+            return;
+        }
+        let shift = (player_pos / CHUNK_SIZE_F).floor().as_ivec3() * CHUNK_SIZE_F as i32;
+        for e in self.entities.iter() {
+            self.entities.write_lock(e).unwrap().pos -= shift.as_vec3();
         }
+        self.origin += shift;
+        log::debug!("Moved the origin to {}", self.origin);
+    }
 
-        (ray_pos-midpoint_offset, last_ray_pos-midpoint_offset, block_id)
+    // break whatever the player is looking at, unless an entity is in the way. returns the block that was broken
+    pub fn player_break_block(&mut self) -> Option<BlockID> {
+        let player = self.entities.read_lock(self.player).unwrap();
+        let (destroy_location, _, looking_at_id) = player.get_block_looking_at(self);
+        let (eye, facing) = (player.eye_pos(), player.look_dir());
+        drop(player);
+        if looking_at_id == 0 {
+            return None;
+        }
+        // an entity in front of the block takes the hit instead (nothing to do to entities yet)
+        let block_distance = (destroy_location + Vec3::splat(0.5) - eye).length();
+        if self.raycast_entity(eye, facing, block_distance, Some(self.player)).is_some() {
+            return None;
+        }
+        self.set_block_id_at(destroy_location, 0).map(|_| looking_at_id)
+    }
+
+    // put `id` against the face the player is looking at, if there's room. returns whether it went down
+    pub fn player_place_block(&mut self, id: BlockID) -> bool {
+        let (destroy_location, place_location, looking_at_id) = self.entities.read_lock(self.player).unwrap().get_block_looking_at(self);
+        if id == 0 || looking_at_id == 0 || !self.can_place_at(place_location) {
+            return false;
+        }
+        // orientable blocks point their top away from the face they were placed against
+        let orientation = Facing::from_normal(place_location - destroy_location).unwrap_or(Facing::U);
+        self.set_block_at(place_location, id, orientation).is_some()
+    }
+
+    // an empty spot in a loaded chunk that isn't inside the player. unloaded chunks are refused rather than queued,
+    // since the player can't see what they'd be placing into
+    pub fn can_place_at(&self, pos: Vec3) -> bool {
+        if self.get_chunk_at(pos).is_none() || self.get_block_id_at(pos) != 0 {
+            return false;
+        }
+        let player_box = self.entities.read_lock(self.player).unwrap().aabb();
+        !geometry::Aabb::UNIT.offset(pos.floor()).intersects(&player_box)
+    }
+
+    // chunks already generated keep whatever floor and terrain they were made with
+    pub fn apply_settings(&mut self, settings: &WorldSettings) {
+        self.void_mode = settings.void_mode;
+        self.void_level = settings.void_level;
+        self.max_chunks_per_tick = settings.max_chunks_per_tick.max(1);
+        self.terrain = Arc::new(Terrain::new(self.seed, settings.terrain.clone()));
+    }
+
+    // return the first non air block you hit (where you want to destroy a block), the last air block you hit (where you want to place a block), and the block id at that spot.
+    // both positions are block corners. walks the ray one block boundary at a time (amanatides & woo), so it can't skip a block
+    // clipped at a grazing angle, and the air block is always the one across the face the ray came in through
+    pub fn cast_ray_to_first_non_air_block(&self, start_pos: Vec3, facing: Vec3, max_distance: f32) -> (Vec3, Vec3, BlockID) {
+        let dir = facing.normalize();
+        // signum gives 1 for 0, which is fine: an axis the ray doesn't move along has an infinite t_max and is never stepped
+        let step = dir.signum();
+        // how far along the ray it takes to cross a whole block on each axis
+        let t_delta = dir.recip().abs();
+
+        let mut block_pos = start_pos.floor();
+        // how far along the ray the next boundary on each axis is
+        let mut t_max = (block_pos + step.max(Vec3::ZERO) - start_pos) / dir;
+        let mut last_air_pos = block_pos;
+        let mut block_id = self.get_block_id_at(block_pos);
+
+        while block_id == 0 {
+            let axis = if t_max.x < t_max.y && t_max.x < t_max.z { 0 } else if t_max.y < t_max.z { 1 } else { 2 };
+            if t_max[axis] > max_distance {
+                break;
+            }
+            last_air_pos = block_pos;
+            block_pos[axis] += step[axis];
+            t_max[axis] += t_delta[axis];
+            block_id = self.get_block_id_at(block_pos);
+        }
+
+        (block_pos, last_air_pos, block_id)
     }
 
     
 
+    // nearest entity whose box the ray passes through within max_distance, and how far along the ray it is.
+    // `caster` is skipped so you can't pick yourself from inside your own box
+    pub fn raycast_entity(&self, start: Vec3, dir: Vec3, max_distance: f32, caster: Option<ArenaHandle<Entity>>) -> Option<(ArenaHandle<Entity>, f32)> {
+        let dir = dir.normalize();
+        self.entities.iter()
+            .filter(|handle| Some(*handle) != caster)
+            .filter_map(|handle| {
+                let aabb = self.entities.read_lock(handle).ok()?.aabb();
+                geometry::ray_aabb(start, dir, &aabb, max_distance).map(|distance| (handle, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
     pub fn get_block_id_at(&self, pos: Vec3) -> BlockID {
         // returns 0 if the chunk isn't loaded
         match self.get_chunk_at(pos) {
             Some(lock) => {
                 match lock.read() {
-                    Ok(chunk) => chunk.get_block_id_at(pos),
+                    Ok(chunk) => chunk.get_block_id_at(self.block_pos(pos)),
                     Err(_) => 0
                 }
             }
             None => 0
         }
     }
-    pub fn set_block_id_at(&mut self, pos: Vec3, id: BlockID, device: &wgpu::Device) -> Option<()> {
-        // returns None and noops if the chunk isn't loaded
+    // fails with ArenaError::OutOfMemory once ENTITY_LIMIT entities exist, so callers can just not spawn instead of crashing
+    pub fn spawn_entity(&mut self, entity: Entity) -> Result<ArenaHandle<Entity>, ArenaError> {
+        self.entities.create(entity)
+    }
+
+    // U if the chunk isn't loaded
+    pub fn get_orientation_at(&self, pos: Vec3) -> Facing {
+        match self.get_chunk_at(pos) {
+            Some(lock) => {
+                match lock.read() {
+                    Ok(chunk) => chunk.get_orientation_at(self.block_pos(pos)),
+                    Err(_) => Facing::U
+                }
+            }
+            None => Facing::U
+        }
+    }
+
+    // the collision box of the block at pos, in world coords
+    pub fn collision_box_at(&self, pos: Vec3) -> Option<geometry::Aabb> {
+        self.block_properties.collision_box(self.get_block_id_at(pos), self.get_orientation_at(pos))
+            .map(|aabb| aabb.offset(pos.floor()))
+    }
+
+    // whether pos is inside something you can't walk through. slabs only count where the slab actually is
+    pub fn is_solid_at(&self, pos: Vec3) -> bool {
+        self.collision_box_at(pos).is_some_and(|aabb| aabb.contains(pos))
+    }
+
+    pub fn set_block_id_at(&mut self, pos: Vec3, id: BlockID) -> Option<()> {
+        self.set_block_at(pos, id, Facing::U)
+    }
+    // orientation is where the block's top should point; it's ignored for blocks that aren't orientable
+    pub fn set_block_at(&mut self, pos: Vec3, id: BlockID, orientation: Facing) -> Option<()> {
+        let orientation = if self.block_properties.by_id(id).orientable { orientation } else { Facing::U };
+        // if the chunk isn't loaded the edit is held until it is, and we return None
+        let block_pos = self.block_pos(pos);
         match self.get_chunk_at(pos) {
             Some(lock) => {
-                self.thread_pool.install(||{
-                    let mut chunk = lock.write().unwrap();
-                    chunk.set_block_id_at(pos, id);
-                    chunk.make_mesh(&self.block_properties, &self.thread_pool);
-                    chunk.make_vertex_buffer(device);
-                    drop(chunk);
-                })
-                
-                //self.queue_mesh_update(handle);
+                lock.write().unwrap().set_block_at(block_pos, id, orientation);
+                for chunk_coord in self.chunks_touched_by(block_pos) {
+                    self.queue_mesh_update(chunk_coord);
+                }
+            }
+            None => {
+                self.chunks.queue_edit(PendingEdit { pos: block_pos, id, orientation });
+                return None
             }
-            None => return None
         }
         Some(())
     }
 
+    // the chunk holding pos, plus any neighbor it's up against: a block on a chunk face decides whether the
+    // neighbor's boundary face shows, so that chunk needs a remesh too. a corner block touches up to three. pos is absolute
+    fn chunks_touched_by(&self, pos: Vec3) -> Vec<ChunkCoord> {
+        let chunk_coord = self.chunks.world_to_chunk_coords(pos);
+        let local = pos.floor() - Vec3::new(chunk_coord.0 as f32, chunk_coord.1 as f32, chunk_coord.2 as f32) * CHUNK_SIZE_F;
+        let mut touched = vec![chunk_coord];
+        for axis in 0..3 {
+            let step = if local[axis] == 0.0 {
+                -1
+            } else if local[axis] == CHUNK_SIZE_F - 1.0 {
+                1
+            } else {
+                continue;
+            };
+            let mut neighbor = [chunk_coord.0, chunk_coord.1, chunk_coord.2];
+            neighbor[axis] += step;
+            touched.push((neighbor[0], neighbor[1], neighbor[2]));
+        }
+        touched
+    }
+
+    // every block position in the box from min to max (both inclusive), x fastest then y then z
+    pub fn positions_in(min: IVec3, max: IVec3) -> impl Iterator<Item=IVec3> {
+        let (lo, hi) = (min.min(max), min.max(max));
+        (lo.z..=hi.z).flat_map(move |z| (lo.y..=hi.y).flat_map(move |y| (lo.x..=hi.x).map(move |x| IVec3::new(x, y, z))))
+    }
+
+    // the ids in a box of blocks (corners inclusive, in any order). unloaded blocks read as air
+    pub fn iter_blocks_in(&self, min: IVec3, max: IVec3) -> impl Iterator<Item=(IVec3, BlockID)> + '_ {
+        Self::positions_in(min, max).map(|pos| (pos, self.get_block_id_at(pos.as_vec3())))
+    }
+
+    // set every block in the box. each chunk touched is remeshed once next update_meshes no matter how many blocks changed,
+    // and parts of the box in unloaded chunks are filled in when they load
+    pub fn fill(&mut self, min: IVec3, max: IVec3, id: BlockID) {
+        for pos in Self::positions_in(min, max) {
+            self.set_block_id_at(pos.as_vec3(), id);
+        }
+    }
+
+    // copy a box of blocks (corners inclusive, in any order) to paste somewhere else
+    pub fn copy_region(&self, min: IVec3, max: IVec3) -> Schematic {
+        let (lo, hi) = (min.min(max), min.max(max));
+        let mut schematic = Schematic::new(hi - lo + IVec3::ONE);
+        for pos in Self::positions_in(lo, hi) {
+            let pos = pos.as_vec3();
+            schematic.push(self.get_block_id_at(pos), self.get_orientation_at(pos));
+        }
+        schematic
+    }
+
+    // put a schematic down with its min corner at `origin`, air included. like fill, anything landing in unloaded chunks waits for them
+    pub fn paste(&mut self, schematic: &Schematic, origin: IVec3) {
+        for (offset, id, orientation) in schematic.blocks() {
+            self.set_block_at((origin + offset).as_vec3(), id, orientation);
+        }
+    }
+
+    // blow out a ball of blocks. the blast has `radius` strength at the center and loses one per block it travels, so
+    // tough blocks only break near the middle. blocks in unloaded chunks are left alone. returns how many blocks broke
+    pub fn explode(&mut self, center: Vec3, radius: f32) -> usize {
+        let reach = Vec3::splat(radius);
+        let (min, max) = ((center - reach).floor().as_ivec3(), (center + reach).floor().as_ivec3());
+        let broken = Self::positions_in(min, max)
+            .map(|pos| pos.as_vec3())
+            .filter(|&pos| self.get_chunk_at(pos).is_some())
+            .filter(|&pos| {
+                let id = self.get_block_id_at(pos);
+                let strength = radius - (pos + Vec3::splat(0.5)).distance(center);
+                id != 0 && strength > self.block_properties.by_id(id).blast_resistance
+            })
+            .collect::<Vec<Vec3>>();
+        // every chunk hit is remeshed just once, set_block_id_at queues them in a set
+        for &pos in &broken {
+            self.set_block_id_at(pos, 0);
+        }
+        broken.len()
+    }
+
+    // walks every loaded chunk, so only call this when someone's going to look at it
+    pub fn stats(&self) -> WorldStats {
+        WorldStats {
+            loaded_chunks: self.chunks.loaded_count(),
+            queued_gen: self.need_generation_update.lock().unwrap().len(),
+            queued_mesh: self.need_mesh_update.lock().unwrap().len(),
+            // chunks that are busy being written to are skipped rather than waited on
+            total_vertices: self.chunks.iter().filter_map(|lock| lock.try_read().ok()).map(|chunk| chunk.mesh.len()).sum(),
+        }
+    }
+
+    pub fn queue_mesh_update(&self, chunk_coord: ChunkCoord) {
+        self.need_mesh_update.lock().unwrap().insert(chunk_coord);
+    }
+
+    // remesh what's been edited since last time, once per chunk and at most max_chunks_per_tick of them, nearest the player first.
+    // call once per frame before upload_meshes
+    pub fn update_meshes(&self) {
+        let queued = {
+            let mut need_mesh_update = self.need_mesh_update.lock().unwrap();
+            let mut batch = need_mesh_update.iter().copied().collect::<Vec<ChunkCoord>>();
+            if batch.len() > self.max_chunks_per_tick {
+                let center = self.chunks.center;
+                batch.select_nth_unstable_by_key(self.max_chunks_per_tick, |&chunk_coord| chunk_distance_squared(chunk_coord, center));
+                batch.truncate(self.max_chunks_per_tick);
+            }
+            for chunk_coord in &batch {
+                need_mesh_update.remove(chunk_coord);
+            }
+            batch
+        };
+        for chunk_coord in queued {
+            // it may have been unloaded since it was queued
+            if let Some(lock) = self.chunks.get_chunk_at_chunk_coords(chunk_coord) {
+                self.thread_pool.install(||{
+                    lock.write().unwrap().make_mesh(&self.block_properties, &self.thread_pool);
+                });
+                self.queue_upload(chunk_coord);
+            }
+        }
+    }
+
+    // returns false if there was nothing to do: the chunk is already generated, or already waiting to be
+    pub fn queue_chunk_update(&self, chunk_coord: ChunkCoord) -> bool {
+        if !self.chunks.is_unloaded(chunk_coord) {
+            return false;
+        }
+        if !self.generation_queued.lock().unwrap().insert(chunk_coord) {
+            return false;
+        }
+        self.need_generation_update.lock().unwrap().push(chunk_coord);
+        true
+    }
+
+    // generation runs on the thread pool so the frame never waits on it. each task builds its chunk as an owned value and sends
+    // it back over `generated_tx`; the only things it shares with us are the block set and terrain, read only behind Arcs.
+    // only this function (on the main thread) ever puts chunks in the set, so nothing in the set is touched from two threads.
+    // finished chunks are picked up here, then up to max_chunks_per_tick more are sent off.
+    // remeshing after edits (update_meshes) stays on this thread, so a broken block shows up the same frame
+    pub fn spawn_chunk_updates(&mut self) {
+        self.receive_generated_chunks();
+
+        let void_floor = (self.void_mode == VoidMode::Floor).then_some(self.void_level);
+        let mut dispatched = 0;
+        while dispatched < self.max_chunks_per_tick {
+            let Some(chunk_coord) = self.need_generation_update.lock().unwrap().pop() else { break };
+            // the player may have moved on since, or it got loaded some other way
+            if !self.chunks.check_in_bounds(chunk_coord) || !self.chunks.is_unloaded(chunk_coord) {
+                self.generation_queued.lock().unwrap().remove(&chunk_coord);
+                continue;
+            }
+            let edits = self.chunks.take_pending_edits(chunk_coord);
+            let (tp, block_properties, terrain, seed, tx) = (self.thread_pool.clone(), self.block_properties.clone(), self.terrain.clone(), self.seed, self.generated_tx.clone());
+            let save_dir = self.save_dir.clone();
+            self.thread_pool.spawn(move || {
+                let chunk = ChunkSet::generate_chunk(chunk_coord, &tp, &block_properties, &terrain, seed, void_floor, &save_dir, edits);
+                // the world is gone if this fails, and the chunk with it
+                let _ = tx.send((chunk_coord, chunk));
+            });
+            dispatched += 1;
+        }
+    }
+
+    fn receive_generated_chunks(&mut self) {
+        let generated = self.generated_rx.lock().unwrap().try_iter().collect::<Vec<_>>();
+        for (chunk_coord, mut chunk) in generated {
+            self.generation_queued.lock().unwrap().remove(&chunk_coord);
+            // left behind while it was being made. the pending edits it went out with were applied to it and nowhere else,
+            // so it goes to disk like any edited chunk that unloads, and they come back with it next time
+            if !self.chunks.check_in_bounds(chunk_coord) || !self.chunks.is_unloaded(chunk_coord) {
+                if chunk.modified {
+                    if let Err(e) = save::save_chunk(&self.save_dir, chunk_coord, &chunk) {
+                        log::error!("{}", e);
+                    }
+                }
+                continue;
+            }
+            // edits that came in while it was generating
+            let edits = self.chunks.take_pending_edits(chunk_coord);
+            if !edits.is_empty() {
+                for edit in edits {
+                    chunk.set_block_at(edit.pos, edit.id, edit.orientation);
+                }
+                chunk.make_mesh(&self.block_properties, &self.thread_pool);
+            }
+            self.chunks.insert_chunk(chunk_coord, chunk);
+            self.queue_upload(chunk_coord);
+        }
+    }
+
+    fn queue_upload(&self, chunk_coord: ChunkCoord) {
+        // the receiver lives as long as we do, so this can't fail
+        self.mesh_upload_tx.send(chunk_coord).unwrap();
+    }
+
+    // re-read the block config from disk. block ids are positions in the file, so blocks may only be appended -
+    // if an existing id was removed or renamed we keep the old set rather than scramble every loaded chunk
+    pub fn reload_block_properties(&mut self) -> Result<(), String> {
+        let new_properties = block::BlockProtoSet::from_toml(BLOCKS_CONFIG_PATH).map_err(|e| e.to_string())?;
+        if new_properties.len() < self.block_properties.len() {
+            return Err(format!("{} now defines {} blocks but {} are in use", BLOCKS_CONFIG_PATH, new_properties.len(), self.block_properties.len()));
+        }
+        for id in 0..self.block_properties.len() as BlockID {
+            let (old_name, new_name) = (&self.block_properties.by_id(id).name, &new_properties.by_id(id).name);
+            if old_name != new_name {
+                return Err(format!("block id {} changed from {} to {}", id, old_name, new_name));
+            }
+        }
+        self.block_properties = Arc::new(new_properties);
+        self.mesh_cache.lock().unwrap().clear();
+        Ok(())
+    }
+
+    // rebuild every loaded chunk's mesh, e.g. after the block properties or textures changed
+    pub fn remesh_all_chunks(&self) {
+        for lock in self.chunks.iter() {
+            self.thread_pool.install(||{
+                lock.write().unwrap().make_mesh(&self.block_properties, &self.thread_pool);
+            });
+            self.queue_upload(self.chunks.world_to_chunk_coords(lock.read().unwrap().pos));
+        }
+    }
+
+    // everything above is cpu-only so the world can be generated and meshed headless (no window or gpu);
+    // this is the one place meshes meet the device. call it once per frame before rendering.
+    // drains the upload queue, at most MAX_UPLOADS_PER_FRAME chunks at a time
+    pub fn upload_meshes(&self, device: &wgpu::Device) {
+        let mesh_upload_rx = self.mesh_upload_rx.lock().unwrap();
+        let mut mesh_cache = self.mesh_cache.lock().unwrap();
+        let mut uploaded = 0;
+        while uploaded < MAX_UPLOADS_PER_FRAME {
+            let Ok(chunk_coord) = mesh_upload_rx.try_recv() else { break };
+            // unloaded while it was waiting
+            let Some(lock) = self.chunks.get_chunk_at_chunk_coords(chunk_coord) else { continue };
+            let mut chunk = lock.write().unwrap();
+            // queued more than once and already taken care of
+            if !chunk.needs_upload {
+                continue;
+            }
+            chunk.make_vertex_buffer(device, &mut mesh_cache);
+            chunk.ready_to_display = true;
+            chunk.displayed_at.get_or_insert_with(std::time::Instant::now);
+            uploaded += 1;
+        }
+        if uploaded > 0 {
+            mesh_cache.prune();
+        }
+    }
+
     pub fn get_chunk_at(&self, pos: Vec3) -> Option<&RwLock<Chunk>> {
-        self.chunks.get_chunk_at_world_coords(pos)
+        self.chunks.get_chunk_at_world_coords(self.block_pos(pos))
+    }
+
+    // write every loaded chunk that was edited since it was generated (or last saved) to save_dir, returning how many.
+    // untouched chunks aren't saved, the terrain regenerates the same from the seed. chunks are also saved as they unload
+    pub fn save_region(&self) -> Result<usize, save::SaveError> {
+        let mut saved = 0;
+        for lock in self.chunks.iter() {
+            let mut chunk = lock.write().unwrap();
+            if !chunk.modified {
+                continue;
+            }
+            let chunk_coord = self.chunks.world_to_chunk_coords(chunk.pos);
+            save::save_chunk(&self.save_dir, chunk_coord, &chunk)?;
+            chunk.modified = false;
+            saved += 1;
+        }
+        Ok(saved)
+    }
+
+    // the saved copy of a chunk, if it's ever been saved. generation already checks here first, this is for anything else
+    pub fn load_region(&self, chunk_coord: ChunkCoord) -> Result<Option<Chunk>, save::SaveError> {
+        save::load_chunk(&self.save_dir, chunk_coord)
+    }
+
+    pub fn render_distance(&self) -> usize {
+        self.chunks.render_distance as usize
+    }
+
+    // clamped to 1..=MAX_RENDER_DISTANCE. chunks now out of range are freed right away, and new ones are queued for generation
+    pub fn set_render_distance(&mut self, render_distance: usize) {
+        let render_distance = render_distance.clamp(1, MAX_RENDER_DISTANCE);
+        if render_distance == self.render_distance() {
+            return;
+        }
+        // shrinking drops chunks without going through update_loaded_chunks
+        if let Err(e) = self.save_region() {
+            log::error!("{}", e);
+        }
+        self.chunks.set_render_distance(render_distance);
+        // forget where the player was so the next update queues everything in the new range
+        self.last_player_chunk_coords = None;
+        self.update_loaded_chunks();
+        log::info!("Render distance: {} chunks", render_distance);
     }
 
     fn get_player_chunk_coords(&self) -> (isize, isize, isize) {
-        self.chunks.world_to_chunk_coords(self.entities.read_lock(self.player).unwrap().pos)
+        let player_pos = self.entities.read_lock(self.player).unwrap().pos;
+        self.chunks.world_to_chunk_coords(self.block_pos(player_pos))
     }
 
-    pub fn update_loaded_chunks(&mut self, device: &wgpu::Device) {
+    pub fn update_loaded_chunks(&mut self) {
 
         let pcp = self.get_player_chunk_coords();
         self.chunks.recenter(pcp);
@@ -144,91 +716,656 @@ impl World {
         }
         //let delta = 
         self.last_player_chunk_coords = Some(pcp);
+        self.need_generation_update.lock().unwrap().reprioritize(pcp);
 
         // GENERATE ANY CHUNKS THAT HAVEN'T BEEN LOADED YET
         let mut to_unload = Vec::<ChunkCoord>::new();
         for lock in self.chunks.iter() {
-            let cp = self.chunks.world_to_chunk_coords(lock.read().unwrap().pos);
+            let chunk = lock.read().unwrap();
+            let cp = self.chunks.world_to_chunk_coords(chunk.pos);
             if !self.chunks.check_in_bounds(cp) {
+                // edits would be lost with the chunk, so they go to disk first
+                if chunk.modified {
+                    if let Err(e) = save::save_chunk(&self.save_dir, cp, &chunk) {
+                        log::error!("{}", e);
+                    }
+                }
                 to_unload.push(cp);
             }
         }
 
+        log::debug!("Unloading {} chunks", to_unload.len());
         for cp in to_unload.into_iter() {
             self.chunks.mark_unloaded(cp);
         }
 
-        //let mut i = 0;
-        for x in (pcp.0 - RENDER_DISTANCE as isize)..(pcp.0 + RENDER_DISTANCE as isize) {
-            for y in (pcp.1 - RENDER_DISTANCE as isize)..(pcp.1 + RENDER_DISTANCE as isize) {
-                for z in (pcp.2 - RENDER_DISTANCE as isize)..(pcp.2 + RENDER_DISTANCE as isize) {
-                    if self.chunks.is_unloaded((x,y,z)) {
-                        //i += 1;
-                        self.chunks.generate_chunk((x, y, z), &self.thread_pool, &self.block_properties, device);
+        let mut queued = 0;
+        let rd = self.chunks.render_distance;
+        for x in (pcp.0 - rd)..=(pcp.0 + rd) {
+            for y in (pcp.1 - rd)..=(pcp.1 + rd) {
+                for z in (pcp.2 - rd)..=(pcp.2 + rd) {
+                    if self.queue_chunk_update((x, y, z)) {
+                        queued += 1;
                     }
                 }
             }
         }
-        //println!("Created {} chunks", i);
+        log::debug!("Queued {} chunks for generation", queued);
+    }
+
+    // the collision boxes of every block overlapping `aabb` that actually poke into it
+    pub fn collision_boxes_in(&self, aabb: &geometry::Aabb) -> Vec<geometry::Aabb> {
+        // max is exclusive, so a box ending exactly on a block boundary doesn't reach into the next block
+        let (min, max) = (aabb.min.floor().as_ivec3(), (aabb.max.ceil() - Vec3::ONE).as_ivec3());
+        Self::positions_in(min, max)
+            .filter_map(|pos| self.collision_box_at(pos.as_vec3()))
+            .filter(|block| block.intersects(aabb))
+            .collect()
+    }
+
+    // move `e` by `dx` (at most MAX_COLLISION_STEP) as far as the blocks around it allow, stepping up ledges and landing on the ground.
+    // the whole box is tested against every block it would overlap, one axis at a time so you still slide along walls
+    fn collide_step(&self, e: &mut Entity, dx: Vec3) -> CollisionStep {
+        let start = e.pos;
+        let mut blocked = BVec3::FALSE;
+        let mut hit_wall = false;
+
+        for axis in 0..2 {
+            if dx[axis] == 0.0 {
+                continue;
+            }
+            let mut offset = Vec3::ZERO;
+            offset[axis] = dx[axis];
+            let moved = e.aabb().offset(offset);
+            let hits = self.collision_boxes_in(&moved);
+            if hits.is_empty() {
+                e.pos += offset;
+                continue;
+            }
+            // a ledge low enough to walk up, with room for the whole body on top of it
+            let rise = hits.iter().map(|block| block.max.z).fold(f32::MIN, f32::max) - e.pos.z;
+            if !e.in_air && rise <= e.step_height && self.collision_boxes_in(&moved.offset(Vec3::Z * rise)).is_empty() {
+                e.pos += offset + Vec3::Z * rise;
+            } else {
+                blocked[axis] = true;
+                hit_wall = true;
+            }
+        }
+
+        let mut landed = false;
+        let hits = self.collision_boxes_in(&e.aabb().offset(Vec3::Z * dx.z));
+        if hits.is_empty() {
+            e.pos.z += dx.z;
+            e.in_air = true;
+        } else {
+            blocked.z = true;
+            if dx.z <= 0.0 {
+                // land exactly on top of whatever we hit, which isn't always a whole block (slabs).
+                // never upwards though, if we're somehow already stuck in something
+                let ground = hits.iter().map(|block| block.max.z).fold(f32::MIN, f32::max);
+                e.pos.z = ground.min(e.pos.z);
+                landed = e.in_air;
+                e.in_air = false;
+            }
+        }
+
+        CollisionStep { moved: e.pos - start, blocked, landed, hit_wall }
     }
 
     fn do_physics(&self, dt: f32, e: ArenaHandle<Entity>) {
         let mut e = self.entities.write_lock(e).unwrap();
         let mut dx = Vec3::ZERO;
         let mut dv = Vec3::ZERO;
-        let (x, y, z) = (e.pos.x, e.pos.y, e.pos.z);
 
-        if e.vel.z.abs() > 100.0 {
-            e.pos = Vec3::new(0.0, 0.0, 10.0);
-            e.vel = Vec3::ZERO;
-            e.facing = Vec3::new(0.0, 1.0, 0.0);
+        let respawn_below = match self.void_mode {
+            VoidMode::Floor => self.void_level - VOID_FLOOR_RESCUE_DEPTH,
+            VoidMode::Respawn => self.void_level,
+        };
+        if e.pos.z + (self.origin.z as f32) < respawn_below {
+            e.reset_to(self.to_local(self.spawn_point));
+            return;
         }
+        e.vel.z = e.vel.z.max(-MAX_FALL_SPEED);
 
         //let entity_chunk = self.get_chunk_at(x, y, z);
 
         e.update_time_independent_acceleration();
 
-        if true { // !e.in_air {
-            let decel = e.vel.with_z(0.0)*e.acc_rate/e.move_speed;
-            e.acc -= decel;
-        }
+        let decel = e.vel.with_z(0.0)*e.friction();
+        e.acc -= decel;
         dv += e.acc * dt;
         dx += (e.vel+dv) * dt;
         
-        //let dx_dir = dx.normalize()*0.1;
+        // move in pieces no bigger than MAX_COLLISION_STEP so a fast fall or sprint can't hop over a block between checks
+        let steps = (dx.length() / MAX_COLLISION_STEP).ceil().max(1.0);
+        let mut step_dx = dx / steps;
+        let (mut moved, mut blocked) = (Vec3::ZERO, BVec3::FALSE);
+        let (mut landed, mut hit_wall) = (false, false);
+        for _ in 0..steps as usize {
+            let step = self.collide_step(&mut *e, step_dx);
+            moved += step.moved;
+            blocked |= step.blocked;
+            landed |= step.landed;
+            hit_wall |= step.hit_wall;
+            // a wall stays in the way for the rest of the move. z keeps going so each piece still finds the ground under it
+            step_dx = Vec3::select(blocked, Vec3::ZERO, step_dx.with_z(0.0)).with_z(step_dx.z);
+        }
+        // whatever ran into something loses its speed along that axis
+        dv = Vec3::select(blocked, -e.vel, dv);
 
-        // this will break for high dx (high v)
-        let future_pos = e.pos+dx+dx.signum()*Vec3::new(e.width, e.width, 0.0);
-        let (fx, fy, fz) = (future_pos.x, future_pos.y, future_pos.z);
+        e.vel += dv;
 
-        if self.block_properties.by_id(self.get_block_id_at(Vec3::new(fx, y, z))).solid || self.block_properties.by_id(self.get_block_id_at(Vec3::new(fx, y, z+1.0))).solid {
-            dx = dx.with_x(0.0);
-            dv = dv.with_x(-e.vel.x);
-        }
-        if self.block_properties.by_id(self.get_block_id_at(Vec3::new(x, fy, z))).solid || self.block_properties.by_id(self.get_block_id_at(Vec3::new(x, fy, z+1.0))).solid {
-            dx = dx.with_y(0.0);
-            dv = dv.with_y(-e.vel.y);
-        }
-        if self.block_properties.by_id(self.get_block_id_at(Vec3::new(x, y, fz))).solid {
-            dx = dx.with_z(0.0);
-            dv = dv.with_z(-e.vel.z);
-            e.in_air = false;
-        } else {
-            e.in_air = true;
+        // a footstep every STEP_LENGTH along the ground, and one for landing
+        if !e.in_air && !e.flying {
+            e.step_distance += moved.with_z(0.0).length();
+            if landed || e.step_distance >= STEP_LENGTH {
+                e.step_distance = 0.0;
+                let below = self.get_block_id_at(e.pos - Vec3::Z * 0.5);
+                self.footsteps.lock().unwrap().push(below);
+            }
         }
-        if self.block_properties.by_id(self.get_block_id_at(Vec3::new(x, y, fz+e.height))).solid {
-            dx = dx.with_z(0.0);
-            dv = dv.with_z(-e.vel.z);
+
+        // running into a wall stops a sprint; you have to start it again
+        if hit_wall && e.vel.with_z(0.0).length() < SPRINT_CANCEL_SPEED {
+            e.desired_movement.SPRINT = false;
         }
+    }
 
-        e.vel += dv;
-        e.pos += dx;
+    pub fn take_footsteps(&self) -> Vec<BlockID> {
+        std::mem::take(&mut *self.footsteps.lock().unwrap())
+    }
+
+    // fixed rate world updates, independent of the frame rate (see Clock::take_sim_ticks). anything that should play out
+    // the same no matter the fps goes here: the day cycle now, block updates and mob ai later
+    pub fn sim_step(&mut self, dt: f32) {
+        self.time += dt;
     }
 
     pub fn physics_step(&mut self, dt: f32) {
         for e in self.entities.iter() {
             self.do_physics(dt, e);
         }
+        self.rebase_origin();
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1.0 / 120.0;
+
+    // a World whose save folder is its own and goes away with it, so whatever one test saves (unloading an edited
+    // chunk, say) can't turn up in another test or the next run through generate_chunk's load path
+    struct TestWorld(World);
+    impl std::ops::Deref for TestWorld {
+        type Target = World;
+        fn deref(&self) -> &World {
+            &self.0
+        }
+    }
+    impl std::ops::DerefMut for TestWorld {
+        fn deref_mut(&mut self) -> &mut World {
+            &mut self.0
+        }
+    }
+    impl Drop for TestWorld {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0.save_dir);
+        }
+    }
+
+    // built-in blocks, a small render distance and empty chunks from -1 to 1 each way around the origin
+    fn test_world() -> TestWorld {
+        static WORLDS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        let n = WORLDS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        let mut world = World::new_with_assets(true);
+        world.save_dir = std::env::temp_dir().join(format!("voxelgame-test-world-{}-{}", std::process::id(), n));
+        let _ = std::fs::remove_dir_all(&world.save_dir);
+        world.chunks = ChunkSet::new((0, 0, 0), 2);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    world.chunks.insert_chunk((x, y, z), Chunk::new(x as f32 * CHUNK_SIZE_F, y as f32 * CHUNK_SIZE_F, z as f32 * CHUNK_SIZE_F));
+                }
+            }
+        }
+        TestWorld(world)
+    }
+
+    // an entity standing at pos, looking and walking toward +x
+    fn spawn_walker(world: &mut World, pos: Vec3) -> ArenaHandle<Entity> {
+        let mut entity = Entity::new(pos);
+        entity.body_follows_look = false;
+        entity.turn_body_to(0.0);
+        world.spawn_entity(entity).unwrap()
+    }
+
+    fn simulate(world: &World, entity: ArenaHandle<Entity>, seconds: f32, dt: f32) {
+        for _ in 0..(seconds / dt).round() as usize {
+            world.do_physics(dt, entity);
+        }
+    }
+
+    // run the generation queue until it's empty and everything sent off has come back
+    fn finish_generating(world: &mut World) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        loop {
+            world.spawn_chunk_updates();
+            if world.need_generation_update.lock().unwrap().len() == 0 && world.generation_queued.lock().unwrap().is_empty() {
+                return;
+            }
+            assert!(std::time::Instant::now() < deadline, "chunk generation never finished");
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn chunks_are_only_queued_for_generation_once() {
+        let mut world = test_world();
+        let chunk_coord = (2, 0, 0);
+        assert!(world.queue_chunk_update(chunk_coord));
+        assert!(!world.queue_chunk_update(chunk_coord));
+        assert_eq!(world.need_generation_update.lock().unwrap().len(), 1);
+        // already loaded
+        assert!(!world.queue_chunk_update((0, 0, 0)));
+
+        // still not while it's off generating, nor once it's back
+        world.spawn_chunk_updates();
+        assert!(!world.queue_chunk_update(chunk_coord));
+        finish_generating(&mut world);
+        assert!(!world.chunks.is_unloaded(chunk_coord));
+        assert!(!world.queue_chunk_update(chunk_coord));
+    }
+
+    #[test]
+    fn pending_edit_lands_when_its_chunk_loads() {
+        let mut world = test_world();
+        // (2, 0, 0) is in range but wasn't loaded by test_world
+        let pos = Vec3::new(2.0 * CHUNK_SIZE_F + 3.0, 4.0, 8.0);
+        let chunk_coord = world.chunks.world_to_chunk_coords(pos);
+        assert!(world.get_chunk_at(pos).is_none());
+        assert_eq!(world.set_block_id_at(pos, 6), None);
+        assert_eq!(world.chunks.pending_edit_count(), 1);
+
+        assert!(world.queue_chunk_update(chunk_coord));
+        finish_generating(&mut world);
+
+        assert!(world.get_chunk_at(pos).is_some());
+        assert_eq!(world.get_block_id_at(pos), 6);
+        assert_eq!(world.chunks.pending_edit_count(), 0);
+    }
+
+    #[test]
+    fn crossing_a_chunk_boundary_moves_the_loaded_area() {
+        let mut world = test_world();
+        world.entities.write_lock(world.player).unwrap().pos = Vec3::new(8.0, 8.0, 8.0);
+        // exactly the chunks test_world loaded
+        world.set_render_distance(1);
+        assert_eq!(world.chunks.loaded_count(), 27);
+        assert_eq!(world.need_generation_update.lock().unwrap().len(), 0);
+
+        world.entities.write_lock(world.player).unwrap().pos = Vec3::new(CHUNK_SIZE_F + 8.0, 8.0, 8.0);
+        world.update_loaded_chunks();
+        finish_generating(&mut world);
+
+        assert_eq!(world.chunks.loaded_count(), 27);
+        let mut loaded = world.chunks.iter().map(|lock| world.chunks.world_to_chunk_coords(lock.read().unwrap().pos)).collect::<Vec<_>>();
+        loaded.sort();
+        let mut expected = vec![];
+        for x in 0..=2 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    expected.push((x, y, z));
+                }
+            }
+        }
+        assert_eq!(loaded, expected);
+    }
+
+    fn mesh_updates_from_edit(pos: Vec3) -> HashSet<ChunkCoord> {
+        let mut world = test_world();
+        world.set_block_id_at(pos, 2);
+        let queued = world.need_mesh_update.lock().unwrap().clone();
+        queued
+    }
+
+    #[test]
+    fn edits_on_chunk_faces_remesh_the_neighbors() {
+        assert_eq!(mesh_updates_from_edit(Vec3::new(5.0, 9.0, 7.0)), HashSet::from([(0, 0, 0)]));
+        // a corner touches the three chunks it's up against
+        assert_eq!(mesh_updates_from_edit(Vec3::new(15.0, 15.0, 15.0)), HashSet::from([(0, 0, 0), (1, 0, 0), (0, 1, 0), (0, 0, 1)]));
+        assert_eq!(mesh_updates_from_edit(Vec3::new(0.0, 0.0, 0.0)), HashSet::from([(0, 0, 0), (-1, 0, 0), (0, -1, 0), (0, 0, -1)]));
+        // an edge only two
+        assert_eq!(mesh_updates_from_edit(Vec3::new(-16.0, 5.0, -1.0)), HashSet::from([(-1, 0, -1), (-2, 0, -1), (-1, 0, 0)]));
+    }
+
+    #[test]
+    fn many_edits_to_one_chunk_mesh_it_once() {
+        let mut world = test_world();
+        // all inside chunk (0, 0, 0), away from its faces so no neighbor is queued
+        for i in 0..100 {
+            world.set_block_id_at(Vec3::new((1 + i % 10) as f32, (1 + i / 10) as f32, 5.0), 2);
+        }
+        assert_eq!(*world.need_mesh_update.lock().unwrap(), HashSet::from([(0, 0, 0)]));
+
+        world.update_meshes();
+        assert!(world.need_mesh_update.lock().unwrap().is_empty());
+        let uploads = world.mesh_upload_rx.lock().unwrap().try_iter().collect::<Vec<_>>();
+        assert_eq!(uploads, vec![(0, 0, 0)]);
+        let chunk = world.chunks.get_chunk_at_chunk_coords((0, 0, 0)).unwrap().read().unwrap();
+        assert!(chunk.needs_upload);
+        assert!(!chunk.mesh.is_empty());
+    }
+
+    // what the renderer sees each frame: every chunk it iterates is in range, and is the chunk the set has for that coord
+    fn assert_drawable_chunks_are_consistent(world: &World) {
+        let mut seen = HashSet::new();
+        for lock in world.chunks.iter() {
+            let pos = lock.read().unwrap().pos;
+            let chunk_coord = world.chunks.world_to_chunk_coords(pos);
+            assert!(world.chunks.check_in_bounds(chunk_coord), "{:?} still loaded around {:?}", chunk_coord, world.chunks.center);
+            assert_eq!(world.chunks.get_chunk_at_chunk_coords(chunk_coord).unwrap().read().unwrap().pos, pos);
+            assert!(seen.insert(chunk_coord), "{:?} loaded twice", chunk_coord);
+        }
+    }
+
+    // running back and forth over chunk boundaries faster than generation keeps up, drawing in between every update
+    #[test]
+    fn loading_and_unloading_while_drawing() {
+        let mut world = test_world();
+        world.set_render_distance(1);
+        for frame in 0..60 {
+            // out 3 chunks along x and back again, a few blocks a frame
+            let x = 8.0 + CHUNK_SIZE_F * 3.0 * (frame as f32 * 0.1).sin().abs();
+            world.entities.write_lock(world.player).unwrap().pos = Vec3::new(x, 8.0, 8.0);
+            world.update_loaded_chunks();
+            assert_drawable_chunks_are_consistent(&world);
+            world.spawn_chunk_updates();
+            assert_drawable_chunks_are_consistent(&world);
+        }
+        finish_generating(&mut world);
+        assert_drawable_chunks_are_consistent(&world);
+        assert_eq!(world.chunks.loaded_count(), 27);
+    }
+
+    #[test]
+    fn pending_edit_survives_its_chunk_being_left_behind() {
+        let mut world = test_world();
+        let pos = Vec3::new(2.0 * CHUNK_SIZE_F + 3.0, 4.0, 8.0);
+        let chunk_coord = world.chunks.world_to_chunk_coords(pos);
+        world.set_block_id_at(pos, 6);
+        assert!(world.queue_chunk_update(chunk_coord));
+        // sent off to generate, taking the edit with it
+        world.spawn_chunk_updates();
+        assert_eq!(world.chunks.pending_edit_count(), 0);
+
+        // the player runs off before it comes back (update_loaded_chunks recenters first thing)
+        world.chunks.recenter((-2, 0, 0));
+        finish_generating(&mut world);
+        assert!(world.chunks.is_unloaded(chunk_coord));
+        let saved = world.load_region(chunk_coord).unwrap().expect("the edited chunk was thrown away");
+        assert_eq!(saved.get_block_id_at(pos), 6);
+
+        // and it's there when they come back
+        world.chunks.recenter((0, 0, 0));
+        assert!(world.queue_chunk_update(chunk_coord));
+        finish_generating(&mut world);
+        assert_eq!(world.get_block_id_at(pos), 6);
+    }
+
+    #[test]
+    fn spawning_past_the_entity_limit_fails_cleanly() {
+        let mut world = World::new_with_assets(true);
+        // the player already takes a slot
+        for _ in 1..ENTITY_LIMIT {
+            world.spawn_entity(Entity::new(Vec3::ZERO)).unwrap();
+        }
+        assert!(matches!(world.spawn_entity(Entity::new(Vec3::ZERO)), Err(ArenaError::OutOfMemory)));
+        assert_eq!(world.entities.iter().count(), ENTITY_LIMIT);
+    }
+
+    #[test]
+    fn raycast_picks_the_nearest_entity() {
+        let mut world = test_world();
+        let near = world.spawn_entity(Entity::new(Vec3::new(5.0, 0.0, 0.0))).unwrap();
+        let far = world.spawn_entity(Entity::new(Vec3::new(9.0, 0.0, 0.0))).unwrap();
+        let start = Vec3::new(0.0, 0.0, 1.0);
+
+        let (hit, distance) = world.raycast_entity(start, Vec3::X, 20.0, None).unwrap();
+        assert!(hit == near);
+        assert!((distance - (5.0 - world.entities.read_lock(near).unwrap().width)).abs() < 1e-5);
+        // looking the other way, or not far enough
+        assert!(world.raycast_entity(start, Vec3::NEG_X, 20.0, None).is_none());
+        assert!(world.raycast_entity(start, Vec3::X, 4.0, None).is_none());
+        // the caster is see-through to itself
+        let inside_near = Vec3::new(5.0, 0.0, 1.0);
+        let (hit, _) = world.raycast_entity(inside_near, Vec3::X, 20.0, Some(near)).unwrap();
+        assert!(hit == far);
+    }
+
+    #[test]
+    fn raycast_straight_down_onto_a_block() {
+        let mut world = test_world();
+        world.set_block_id_at(Vec3::new(3.0, 2.0, 0.0), 2);
+        let start = Vec3::new(3.5, 2.5, 5.5);
+
+        let (hit, air, id) = world.cast_ray_to_first_non_air_block(start, Vec3::NEG_Z, 10.0);
+        assert_eq!((hit, air, id), (Vec3::new(3.0, 2.0, 0.0), Vec3::new(3.0, 2.0, 1.0), 2));
+        // the top of the block is 4.5 away
+        assert_eq!(world.cast_ray_to_first_non_air_block(start, Vec3::NEG_Z, 4.0).2, 0);
+    }
+
+    #[test]
+    fn diagonal_raycast_places_against_the_face_it_crossed() {
+        let mut world = test_world();
+        world.set_block_id_at(Vec3::new(3.0, 2.0, 2.0), 2);
+        // next to the path but never entered
+        world.set_block_id_at(Vec3::new(4.0, 1.0, 2.0), 6);
+        let start = Vec3::new(1.5, 1.5, 2.5);
+        let dir = Vec3::new(1.0, 0.4, 0.0);
+
+        // crosses y = 2 before x = 3, so it comes in through the west face
+        let (hit, air, id) = world.cast_ray_to_first_non_air_block(start, dir, 10.0);
+        assert_eq!((hit, air, id), (Vec3::new(3.0, 2.0, 2.0), Vec3::new(2.0, 2.0, 2.0), 2));
+        assert_eq!(world.cast_ray_to_first_non_air_block(start, dir, 1.0).2, 0);
+        // coming back the other way it hits the east face
+        let (hit, air, _) = world.cast_ray_to_first_non_air_block(Vec3::new(6.5, 3.5, 2.5), -dir, 10.0);
+        assert_eq!((hit, air), (Vec3::new(3.0, 2.0, 2.0), Vec3::new(4.0, 2.0, 2.0)));
+    }
+
+    const SLAB_BLOCKS_TOML: &str = r#"
+        [[blocks]] # id 1
+        name = "Stone"
+        textures = ["stone.png"]
+
+        [[blocks]] # id 2
+        name = "Stone Slab"
+        textures = ["stone.png"]
+        collision_height = 0.5
+        orientable = true
+    "#;
+
+    #[test]
+    fn lands_on_top_of_a_slab() {
+        let mut world = test_world();
+        world.block_properties = Arc::new(block::BlockProtoSet::from_toml_str(SLAB_BLOCKS_TOML, "test").unwrap());
+        world.set_block_id_at(Vec3::new(2.0, 2.0, 0.0), 2);
+        // an upside down slab fills the top half of its block instead
+        world.set_block_at(Vec3::new(6.0, 2.0, 0.0), 2, Facing::D);
+
+        let on_slab = world.spawn_entity(Entity::new(Vec3::new(2.5, 2.5, 3.0))).unwrap();
+        let on_flipped_slab = world.spawn_entity(Entity::new(Vec3::new(6.5, 2.5, 3.0))).unwrap();
+        simulate(&world, on_slab, 1.0, DT);
+        simulate(&world, on_flipped_slab, 1.0, DT);
+        assert_eq!(world.entities.read_lock(on_slab).unwrap().pos.z, 0.5);
+        assert_eq!(world.entities.read_lock(on_flipped_slab).unwrap().pos.z, 1.0);
+    }
+
+    #[test]
+    fn steps_up_onto_a_slab() {
+        let mut world = test_world();
+        world.block_properties = Arc::new(block::BlockProtoSet::from_toml_str(SLAB_BLOCKS_TOML, "test").unwrap());
+        world.fill(IVec3::new(0, 0, 0), IVec3::new(15, 3, 0), 1);
+        world.fill(IVec3::new(4, 0, 1), IVec3::new(15, 3, 1), 2);
+
+        let walker = spawn_walker(&mut world, Vec3::new(1.5, 1.5, 1.0));
+        world.entities.write_lock(walker).unwrap().desired_movement.FORWARD = true;
+        simulate(&world, walker, 1.5, DT);
+
+        let e = world.entities.read_lock(walker).unwrap();
+        assert!(e.pos.x > 5.0);
+        assert_eq!(e.pos.z, 1.5);
+    }
+
+    #[test]
+    fn fill_reads_back_across_a_chunk_boundary() {
+        let mut world = test_world();
+        // x 14 and 15 are in chunk (0, 0, 0), x 16 in (1, 0, 0)
+        let (min, max) = (IVec3::new(14, 7, 7), IVec3::new(16, 9, 9));
+        world.fill(max, min, 6);
+        let blocks = world.iter_blocks_in(min, max).collect::<Vec<_>>();
+        assert_eq!(blocks.len(), 27);
+        assert!(blocks.iter().all(|&(_, id)| id == 6));
+        // x fastest, then y, then z
+        assert_eq!(blocks[..4].iter().map(|&(pos, _)| pos).collect::<Vec<_>>(), vec![
+            IVec3::new(14, 7, 7), IVec3::new(15, 7, 7), IVec3::new(16, 7, 7), IVec3::new(14, 8, 7),
+        ]);
+        assert_eq!(world.get_block_id_at(Vec3::new(17.0, 8.0, 8.0)), 0);
+
+        // (2, 0, 0) starts at x 32 and isn't loaded, so that slice reads as air until it is
+        let (min, max) = (IVec3::new(30, 7, 7), IVec3::new(32, 9, 9));
+        world.fill(min, max, 6);
+        for (pos, id) in world.iter_blocks_in(min, max) {
+            assert_eq!(id, if pos.x < 32 { 6 } else { 0 }, "at {}", pos);
+        }
+        assert_eq!(world.chunks.pending_edit_count(), 9);
+    }
+
+    #[test]
+    fn copy_and_paste_round_trip() {
+        let mut world = test_world();
+        world.block_properties = Arc::new(block::BlockProtoSet::from_toml_str(SLAB_BLOCKS_TOML, "test").unwrap());
+        let (min, max) = (IVec3::new(1, 1, 1), IVec3::new(4, 3, 2));
+        world.fill(min, max, 1);
+        world.set_block_id_at(Vec3::new(2.0, 2.0, 2.0), 0);
+        world.set_block_at(Vec3::new(3.0, 1.0, 1.0), 2, Facing::E);
+
+        // corners given backwards on purpose
+        let schematic = world.copy_region(max, min);
+        assert_eq!(schematic.size(), IVec3::new(4, 3, 2));
+        assert_eq!(schematic.volume(), 24);
+
+        // across the boundary into the next chunk over
+        let to = IVec3::new(14, 5, 1);
+        world.paste(&schematic, to);
+        for (pos, id) in world.iter_blocks_in(min, max) {
+            let pasted = pos - min + to;
+            assert_eq!(world.get_block_id_at(pasted.as_vec3()), id, "at {}", pasted);
+            assert_eq!(world.get_orientation_at(pasted.as_vec3()), world.get_orientation_at(pos.as_vec3()));
+        }
+        assert_eq!(world.get_orientation_at((to + IVec3::new(2, 0, 0)).as_vec3()), Facing::E);
+        assert_eq!(world.copy_region(to, to + max - min), schematic);
+    }
+
+    #[test]
+    fn explosion_across_a_chunk_boundary() {
+        let mut world = test_world();
+        world.fill(IVec3::new(10, 2, 2), IVec3::new(21, 13, 13), 2);
+        world.need_mesh_update.lock().unwrap().clear();
+
+        let center = Vec3::new(16.0, 8.0, 8.0);
+        let broken = world.explode(center, 3.0);
+        assert!(broken > 0);
+        // both sides of x = 16 were hit
+        assert_eq!(world.get_block_id_at(Vec3::new(15.0, 8.0, 8.0)), 0);
+        assert_eq!(world.get_block_id_at(Vec3::new(16.0, 8.0, 8.0)), 0);
+        // and nothing out of reach
+        assert_eq!(world.get_block_id_at(Vec3::new(20.0, 8.0, 8.0)), 2);
+        assert_eq!(world.get_block_id_at(Vec3::new(11.0, 8.0, 8.0)), 2);
+        let left = world.iter_blocks_in(IVec3::new(10, 2, 2), IVec3::new(21, 13, 13)).filter(|&(_, id)| id == 0).count();
+        assert_eq!(left, broken);
+
+        // each chunk is queued once however many of its blocks went
+        let queued = world.need_mesh_update.lock().unwrap().clone();
+        assert_eq!(queued, HashSet::from([(0, 0, 0), (1, 0, 0)]));
+    }
+
+    // a wall at x 5 with a one block wide doorway at y 3, two blocks tall
+    fn walk_at_doorway(half_width: f32, y: f32) -> f32 {
+        let mut world = test_world();
+        world.fill(IVec3::new(0, 0, 0), IVec3::new(15, 7, 0), 2);
+        world.fill(IVec3::new(5, 0, 1), IVec3::new(5, 2, 2), 2);
+        world.fill(IVec3::new(5, 4, 1), IVec3::new(5, 7, 2), 2);
+
+        let walker = spawn_walker(&mut world, Vec3::new(1.5, y, 1.0));
+        world.entities.write_lock(walker).unwrap().width = half_width;
+        world.entities.write_lock(walker).unwrap().desired_movement.FORWARD = true;
+        simulate(&world, walker, 1.5, DT);
+        let x = world.entities.read_lock(walker).unwrap().pos.x;
+        x
+    }
+
+    // `width` is half the entity's width, so these are 0.6 and 1.1 blocks across
+    #[test]
+    fn only_fits_through_a_gap_it_is_narrower_than() {
+        assert!(walk_at_doorway(0.3, 3.5) > 6.0);
+        assert!(walk_at_doorway(0.55, 3.5) < 5.0 - 0.55 + 1e-4);
+        // narrow enough, but lined up with the edge of the doorway so one side of the box hits the wall
+        assert!(walk_at_doorway(0.3, 3.2) < 5.0 - 0.3 + 1e-4);
+    }
+
+    // at a quarter second a frame and terminal velocity it moves 15 blocks a frame, a whole layer of floor at once
+    #[test]
+    fn a_fast_fall_lands_instead_of_tunneling() {
+        let mut world = test_world();
+        world.fill(IVec3::new(0, 0, 0), IVec3::new(15, 3, 0), 2);
+
+        for dt in [0.1, 0.25] {
+            let faller = world.spawn_entity(Entity::new(Vec3::new(1.5, 1.5, 30.0))).unwrap();
+            for _ in 0..(4.0 / dt) as usize {
+                world.do_physics(dt, faller);
+                assert!(world.entities.read_lock(faller).unwrap().pos.z >= 1.0, "fell through at dt {}", dt);
+            }
+            let e = world.entities.read_lock(faller).unwrap();
+            assert_eq!(e.pos.z, 1.0);
+            assert_eq!(e.vel.z, 0.0);
+        }
+    }
+
+    #[test]
+    fn walks_up_a_staircase() {
+        let mut world = test_world();
+        // floor with its top at z 1, then steps one block high each
+        world.fill(IVec3::new(0, 0, 0), IVec3::new(15, 3, 0), 2);
+        world.fill(IVec3::new(3, 0, 1), IVec3::new(15, 3, 1), 2);
+        world.fill(IVec3::new(4, 0, 2), IVec3::new(15, 3, 2), 2);
+        world.fill(IVec3::new(5, 0, 3), IVec3::new(15, 3, 3), 2);
+
+        let walker = spawn_walker(&mut world, Vec3::new(1.5, 1.5, 1.0));
+        world.entities.write_lock(walker).unwrap().step_height = 1.0;
+        world.entities.write_lock(walker).unwrap().desired_movement.FORWARD = true;
+        simulate(&world, walker, 1.5, DT);
+
+        let e = world.entities.read_lock(walker).unwrap();
+        assert!(e.pos.x > 6.0, "stuck at {}", e.pos);
+        assert_eq!(e.pos.z, 4.0);
+    }
+
+    #[test]
+    fn a_full_block_stops_a_short_stepper() {
+        let mut world = test_world();
+        world.fill(IVec3::new(0, 0, 0), IVec3::new(15, 3, 0), 2);
+        world.fill(IVec3::new(3, 0, 1), IVec3::new(15, 3, 1), 2);
+
+        // the default 0.6 clears slabs but not whole blocks
+        let walker = spawn_walker(&mut world, Vec3::new(1.5, 1.5, 1.0));
+        world.entities.write_lock(walker).unwrap().desired_movement.FORWARD = true;
+        simulate(&world, walker, 1.0, DT);
+
+        let e = world.entities.read_lock(walker).unwrap();
+        assert!(e.pos.x < 3.0);
+        assert_eq!(e.pos.z, 1.0);
+    }
+}